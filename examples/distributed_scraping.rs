@@ -10,49 +10,6 @@ use lunatic::Mailbox;
 use rust_wasm_lunatic_nats::*;
 use serde_json::{json, Value};
 use std::time::Duration;
-use std::fs;
-
-// Configuration structures
-#[derive(serde::Deserialize, Debug)]
-struct ScrapingConfig {
-    scraping_targets: Vec<ScrapingTarget>,
-    scraping_config: ScrapingSettings,
-    llm_config: LLMSettings,
-}
-
-#[derive(serde::Deserialize, Debug)]
-struct ScrapingTarget {
-    id: String,
-    url: String,
-    title: String,
-    description: String,
-    priority: String,
-    agent_assignment: String,
-}
-
-#[derive(serde::Deserialize, Debug)]
-struct ScrapingSettings {
-    max_concurrent_requests: u32,
-    request_timeout_seconds: u64,
-    retry_attempts: u32,
-    user_agent: String,
-    respect_robots_txt: bool,
-    rate_limit_delay_ms: u64,
-}
-
-#[derive(serde::Deserialize, Debug)]
-struct LLMSettings {
-    summarization: LLMModelConfig,
-    workflow_planning: LLMModelConfig,
-    reasoning: LLMModelConfig,
-}
-
-#[derive(serde::Deserialize, Debug)]
-struct LLMModelConfig {
-    max_tokens: u32,
-    temperature: f32,
-    model: String,
-}
 
 #[lunatic::main]
 fn main(_: Mailbox<()>) {
@@ -71,7 +28,7 @@ fn main(_: Mailbox<()>) {
     }
     
     // Load configuration
-    let config = match load_scraping_config() {
+    let config = match ScrapingConfig::from_path("scraping_config.json") {
         Ok(config) => {
             log::info!("✅ Loaded scraping configuration with {} targets", config.scraping_targets.len());
             config
@@ -91,16 +48,6 @@ fn main(_: Mailbox<()>) {
     log::info!("=== Real demo completed successfully ===");
 }
 
-fn load_scraping_config() -> Result<ScrapingConfig> {
-    let config_content = fs::read_to_string("scraping_config.json")
-        .map_err(|e| Error::Custom(format!("Failed to read scraping_config.json: {}", e)))?;
-    
-    let config: ScrapingConfig = serde_json::from_str(&config_content)
-        .map_err(|e| Error::Custom(format!("Failed to parse scraping_config.json: {}", e)))?;
-    
-    Ok(config)
-}
-
 fn check_openai_api_key() -> OpenAIStatus {
     match std::env::var("OPENAI_API_KEY") {
         Ok(key) if !key.is_empty() && key.len() > 10 => {
@@ -217,6 +164,7 @@ fn create_real_scraper_configs(config: &ScrapingConfig) -> Vec<AgentConfig> {
                     "timeout_seconds": config.scraping_config.request_timeout_seconds,
                     "user_agent": config.scraping_config.user_agent,
                     "retry_attempts": config.scraping_config.retry_attempts,
+                    "respect_robots_txt": config.scraping_config.respect_robots_txt,
                     "rate_limit_delay_ms": config.scraping_config.rate_limit_delay_ms
                 }
             }),
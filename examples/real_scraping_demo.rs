@@ -11,61 +11,10 @@ use rust_wasm_lunatic_nats::*;
 use rust_wasm_lunatic_nats::Message as AgentMessage;
 use serde_json::{json};
 use std::time::Duration;
-use std::fs;
 
-// Configuration structures
-#[derive(serde::Deserialize, Debug)]
-struct ScrapingConfig {
-    scraping_targets: Vec<ScrapingTarget>,
-    scraping_config: ScrapingSettings,
-    llm_config: LLMSettings,
-    output_config: OutputConfig,
-}
-
-#[derive(serde::Deserialize, Debug)]
-struct ScrapingTarget {
-    id: String,
-    url: String,
-    title: String,
-    description: String,
-    priority: String,
-    agent_assignment: String,
-}
-
-#[derive(serde::Deserialize, Debug)]
-struct ScrapingSettings {
-    max_concurrent_requests: u32,
-    request_timeout_seconds: u64,
-    retry_attempts: u32,
-    user_agent: String,
-    respect_robots_txt: bool,
-    rate_limit_delay_ms: u64,
-}
-
-#[derive(serde::Deserialize, Debug)]
-struct LLMSettings {
-    summarization: LLMModelConfig,
-    workflow_planning: LLMModelConfig,
-    reasoning: LLMModelConfig,
-}
-
-#[derive(serde::Deserialize, Debug)]
-struct LLMModelConfig {
-    max_tokens: u32,
-    temperature: f32,
-    model: String,
-}
-
-#[derive(serde::Deserialize, serde::Serialize, Debug)]
-struct OutputConfig {
-    summary_file: String,
-    workflow_file: String,
-    raw_data_file: String,
-    create_directories: bool,
-    append_timestamp: bool,
-    format: String,
-    include_metadata: bool,
-}
+// Upper bounds on waiting for agents; the waits return as soon as the work is done
+const SCRAPE_WAIT_TIMEOUT: Duration = Duration::from_secs(120);
+const LLM_WAIT_TIMEOUT: Duration = Duration::from_secs(60);
 
 #[derive(Debug)]
 enum OpenAIStatus {
@@ -91,7 +40,7 @@ fn main(_: Mailbox<()>) {
     }
     
     // Load configuration
-    let config = match load_scraping_config() {
+    let config = match ScrapingConfig::from_path("scraping_config.json") {
         Ok(config) => {
             log::info!("✅ Loaded scraping configuration with {} targets", config.scraping_targets.len());
             config
@@ -111,16 +60,6 @@ fn main(_: Mailbox<()>) {
     log::info!("=== Real demo completed successfully ===");
 }
 
-fn load_scraping_config() -> Result<ScrapingConfig> {
-    let config_content = fs::read_to_string("scraping_config.json")
-        .map_err(|e| Error::Custom(format!("Failed to read scraping_config.json: {}", e)))?;
-    
-    let config: ScrapingConfig = serde_json::from_str(&config_content)
-        .map_err(|e| Error::Custom(format!("Failed to parse scraping_config.json: {}", e)))?;
-    
-    Ok(config)
-}
-
 fn check_openai_api_key() -> OpenAIStatus {
     match std::env::var("OPENAI_API_KEY") {
         Ok(key) if !key.is_empty() && key.len() > 10 => {
@@ -170,28 +109,34 @@ fn run_real_distributed_scraping_demo(config: ScrapingConfig, api_status: OpenAI
     
     // Step 3: Send real scraping tasks based on configuration
     log::info!("📋 Distributing real URL scraping tasks to agents");
+    for agent in &scraper_agents {
+        pass_scraping_config_to_agent(agent, &config.scraping_config);
+    }
     send_real_scraping_tasks(&scraper_agents, &config);
     
-    // Wait for agents to process scraping tasks (real HTTP requests take time)
-    log::info!("⏳ Waiting for agents to complete real web scraping tasks...");
-    lunatic::sleep(Duration::from_millis(8000));
-    
     // Step 4: Collect data and send to OpenAI-enabled summarizer
     log::info!("📊 Collecting scraped data and sending to OpenAI summarizer");
     let collected_data = collect_real_scraped_data(&scraper_agents, &config);
     
     // Pass output configuration to the summarizer agent
     pass_output_config_to_agent(&summarizer_agent, &config.output_config);
+    pass_llm_config_to_agent(&summarizer_agent, &config.llm_config);
+    pass_llm_config_to_agent(&coordinator_agent, &config.llm_config);
     
-    send_data_to_openai_summarizer(&summarizer_agent, collected_data);
+    let summary_operation = send_data_to_openai_summarizer(&summarizer_agent, collected_data);
     
     // Step 5: Request intelligent workflow plan
     log::info!("🗺️ Requesting AI-powered workflow plan from coordinator");
-    request_intelligent_workflow_plan(&coordinator_agent, &config);
+    let plan_operation = request_intelligent_workflow_plan(&coordinator_agent, &config);
     
     // Wait for real LLM API processing (OpenAI calls take time)
     log::info!("⏳ Waiting for real OpenAI API processing...");
-    lunatic::sleep(Duration::from_millis(5000));
+    for (agent, operation_id) in [(&summarizer_agent, &summary_operation), (&coordinator_agent, &plan_operation)] {
+        match wait_for_operation(agent, operation_id, LLM_WAIT_TIMEOUT) {
+            Ok(status) => log::info!("✅ LLM operation {} finished: {:?}", operation_id, status),
+            Err(e) => log::warn!("⚠️ {}", e),
+        }
+    }
     
     // Step 6: Display enhanced results
     log::info!("📈 Checking real LLM integration results");
@@ -224,6 +169,7 @@ fn create_real_scraper_configs(config: &ScrapingConfig) -> Vec<AgentConfig> {
             memory_backend_type: MemoryBackendType::InMemory,
             nats_enabled: false,
             llm_enabled: false, // Scrapers don't need LLM
+            ..Default::default()
         });
     }
     
@@ -241,6 +187,7 @@ fn create_real_summarizer_config(config: &ScrapingConfig, api_status: &OpenAISta
         memory_backend_type: MemoryBackendType::InMemory,
         nats_enabled: false,
         llm_enabled,
+        ..Default::default()
     }
 }
 
@@ -253,6 +200,7 @@ fn create_real_coordinator_config(config: &ScrapingConfig) -> AgentConfig {
         memory_backend_type: MemoryBackendType::InMemory,
         nats_enabled: false,
         llm_enabled: true, // Coordinators benefit from LLM for workflow planning
+        ..Default::default()
     }
 }
 
@@ -304,10 +252,15 @@ fn send_real_scraping_tasks(agents: &[lunatic::ap::ProcessRef<AgentProcess>], co
                     "config": {
                         "timeout_seconds": config.scraping_config.request_timeout_seconds,
                         "user_agent": config.scraping_config.user_agent,
-                        "retry_attempts": config.scraping_config.retry_attempts
+                        "retry_attempts": config.scraping_config.retry_attempts,
+                        "respect_robots_txt": config.scraping_config.respect_robots_txt
                     }
                 }),
                 timestamp: chrono::Utc::now().timestamp_millis() as u64,
+                expires_at: None,
+                correlation_id: None,
+                reply_to: None,
+                signature: None,
             };
             
             send_message_to_agent(agent, scraping_message);
@@ -321,23 +274,37 @@ fn collect_real_scraped_data(agents: &[lunatic::ap::ProcessRef<AgentProcess>], c
     
     log::info!("🔍 Collecting real scraped data from agents...");
     
-    // Give agents time to complete scraping tasks
-    lunatic::sleep(Duration::from_millis(5000));
-    
     // Collect data from each agent
     for (i, agent) in agents.iter().enumerate() {
         log::info!("📊 Retrieving scraped data from agent {}", i + 1);
-        let agent_state = get_agent_state(agent);
-        
-        // Look for scraped data in the agent state
-        for (key, value) in agent_state.iter() {
-            if key.starts_with("scraped_data_") {
-                log::info!("✅ Found scraped data: {}", key);
-                collected_data.push(value.clone());
-            } else if key.starts_with("scraping_error_") {
-                log::warn!("⚠️ Found scraping error: {}", key);
-                // Still include error data for completeness
-                collected_data.push(value.clone());
+
+        // Have the agent gather its results (errors included) into `scrape_dataset`
+        send_message_to_agent(agent, AgentMessage {
+            id: format!("finalize_scrape_{}", i + 1),
+            from: AgentId("demo_controller".to_string()),
+            to: AgentId("scraper_agent".to_string()),
+            payload: json!({"message_type": "finalize_scrape"}),
+            timestamp: chrono::Utc::now().timestamp_millis() as u64,
+            expires_at: None,
+            correlation_id: None,
+            reply_to: None,
+            signature: None,
+        });
+
+        // Scraping tasks queued ahead of the finalize message are handled first
+        let dataset = wait_for_state_key(agent, "scrape_dataset", SCRAPE_WAIT_TIMEOUT)
+            .unwrap_or_else(|e| {
+                log::warn!("⚠️ {}", e);
+                json!([])
+            });
+        if let Some(dataset) = dataset.as_array() {
+            for item in dataset {
+                if item.get("status").and_then(|v| v.as_str()) == Some("error") {
+                    log::warn!("⚠️ Found scraping error: {}", item["task_id"]);
+                } else {
+                    log::info!("✅ Found scraped data: {}", item["task_id"]);
+                }
+                collected_data.push(item.clone());
             }
         }
     }
@@ -379,22 +346,29 @@ fn collect_real_scraped_data(agents: &[lunatic::ap::ProcessRef<AgentProcess>], c
     collected_data
 }
 
-fn send_data_to_openai_summarizer(agent: &lunatic::ap::ProcessRef<AgentProcess>, data: Vec<serde_json::Value>) {
+fn send_data_to_openai_summarizer(agent: &lunatic::ap::ProcessRef<AgentProcess>, data: Vec<serde_json::Value>) -> String {
+    let operation_id = format!("summarize_task_{}", uuid::Uuid::new_v4());
     let summarization_message = AgentMessage {
-        id: format!("summarize_task_{}", uuid::Uuid::new_v4()),
+        id: operation_id.clone(),
         from: AgentId("demo_controller".to_string()),
         to: AgentId("openai_summarizer".to_string()),
         payload: json!({
             "llm_task": "summarize",
             "message_type": "llm_request", 
             "priority": "high",
+            "operation_id": operation_id,
             "data": data
         }),
         timestamp: chrono::Utc::now().timestamp_millis() as u64,
+        expires_at: None,
+        correlation_id: None,
+        reply_to: None,
+        signature: None,
     };
     
     send_message_to_agent(agent, summarization_message);
     log::info!("🧠 Sent {} data items to OpenAI summarizer", data.len());
+    operation_id
 }
 
 fn pass_output_config_to_agent(agent: &lunatic::ap::ProcessRef<AgentProcess>, output_config: &OutputConfig) {
@@ -410,21 +384,73 @@ fn pass_output_config_to_agent(agent: &lunatic::ap::ProcessRef<AgentProcess>, ou
             }
         }),
         timestamp: chrono::Utc::now().timestamp_millis() as u64,
+        expires_at: None,
+        correlation_id: None,
+        reply_to: None,
+        signature: None,
     };
     
     send_message_to_agent(agent, config_message);
     log::info!("📁 Sent output configuration to agent: {}", output_config.summary_file);
 }
 
-fn request_intelligent_workflow_plan(agent: &lunatic::ap::ProcessRef<AgentProcess>, config: &ScrapingConfig) {
+fn pass_llm_config_to_agent(agent: &lunatic::ap::ProcessRef<AgentProcess>, llm_config: &LLMSettings) {
+    let config_message = AgentMessage {
+        id: format!("llm_config_{}", uuid::Uuid::new_v4()),
+        from: AgentId("demo_controller".to_string()),
+        to: AgentId("agent".to_string()),
+        payload: json!({
+            "message_type": "state_update",
+            "priority": "high",
+            "updates": {
+                "llm_config": llm_config
+            }
+        }),
+        timestamp: chrono::Utc::now().timestamp_millis() as u64,
+        expires_at: None,
+        correlation_id: None,
+        reply_to: None,
+        signature: None,
+    };
+
+    send_message_to_agent(agent, config_message);
+    log::info!("🤖 Sent LLM model configuration to agent: {}", llm_config.summarization.model);
+}
+
+fn pass_scraping_config_to_agent(agent: &lunatic::ap::ProcessRef<AgentProcess>, scraping_config: &ScrapingSettings) {
+    let config_message = AgentMessage {
+        id: format!("scraping_config_{}", uuid::Uuid::new_v4()),
+        from: AgentId("demo_controller".to_string()),
+        to: AgentId("agent".to_string()),
+        payload: json!({
+            "message_type": "state_update",
+            "priority": "high",
+            "updates": {
+                "scraping_config": scraping_config
+            }
+        }),
+        timestamp: chrono::Utc::now().timestamp_millis() as u64,
+        expires_at: None,
+        correlation_id: None,
+        reply_to: None,
+        signature: None,
+    };
+    
+    send_message_to_agent(agent, config_message);
+    log::info!("⏱️ Sent scraping configuration to agent: {}ms per-host delay", scraping_config.rate_limit_delay_ms);
+}
+
+fn request_intelligent_workflow_plan(agent: &lunatic::ap::ProcessRef<AgentProcess>, config: &ScrapingConfig) -> String {
+    let operation_id = format!("workflow_plan_{}", uuid::Uuid::new_v4());
     let workflow_message = AgentMessage {
-        id: format!("workflow_plan_{}", uuid::Uuid::new_v4()),
+        id: operation_id.clone(),
         from: AgentId("demo_controller".to_string()),
         to: AgentId("intelligent_coordinator".to_string()),
         payload: json!({
             "llm_task": "plan_workflow",
             "message_type": "llm_request",
             "priority": "medium", 
+            "operation_id": operation_id,
             "task_description": format!("Create an intelligent workflow for scraping {} targets with LLM-powered analysis", config.scraping_targets.len()),
             "available_agents": config.scraping_targets.iter().map(|t| &t.agent_assignment).collect::<std::collections::HashSet<_>>().into_iter().collect::<Vec<_>>(),
             "constraints": {
@@ -434,10 +460,15 @@ fn request_intelligent_workflow_plan(agent: &lunatic::ap::ProcessRef<AgentProces
             }
         }),
         timestamp: chrono::Utc::now().timestamp_millis() as u64,
+        expires_at: None,
+        correlation_id: None,
+        reply_to: None,
+        signature: None,
     };
     
     send_message_to_agent(agent, workflow_message);
     log::info!("🗺️ Requested intelligent workflow planning from LLM coordinator");
+    operation_id
 }
 
 fn display_openai_results(
@@ -1,11 +1,13 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use crate::{Result, Error};
 use crate::memory::MemoryBackend;
-use crate::nats_comm::NatsConnection;
+use crate::nats_comm::{subject_for_agent, NatsConnection};
 use crate::llm_client::{LLMClient, WorkflowStep};
+use crate::telemetry::{agent_event, OperationSpan};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct AgentId(pub String);
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -15,6 +17,400 @@ pub struct Message {
     pub to: AgentId,
     pub payload: serde_json::Value,
     pub timestamp: u64,
+    /// Unix time in seconds after which the message is dropped unprocessed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// Copied onto the result message so the requester can match it to this request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    /// Agent that receives the result; defaults to `from`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<AgentId>,
+    /// Hex HMAC-SHA256 over every other field; see `sign_message`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+/// Routing priority from a payload's `priority` field. Ordered from `Low` to `Critical`;
+/// "medium" is read as `Normal`, and missing or unrecognised values default to `Normal`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+    Critical,
+}
+
+impl Priority {
+    /// The level named `name`, or `None` if it isn't one
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "low" => Some(Priority::Low),
+            "normal" | "medium" => Some(Priority::Normal),
+            "high" => Some(Priority::High),
+            "critical" => Some(Priority::Critical),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+            Priority::Critical => "critical",
+        }
+    }
+}
+
+impl std::fmt::Display for Priority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Priority {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        Ok(value.as_str().and_then(Priority::from_name).unwrap_or_default())
+    }
+}
+
+/// Typed view of a message payload, produced by [`validate_payload`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum MessageKind {
+    LlmTask { task: String },
+    StateUpdate { updates: serde_json::Map<String, serde_json::Value> },
+    Coordination { coordination_type: String },
+    DataTransfer { transfer_id: String, data: serde_json::Value },
+    ScrapingTask { url: String, title: String, task_id: String, config: Option<serde_json::Value> },
+    Crawl { crawl_id: String, crawl: serde_json::Value, config: Option<serde_json::Value> },
+    WorkflowStep { step_id: String },
+    Result { correlation_id: String, result: serde_json::Value },
+    FinalizeScrape { clear: bool },
+    Generic,
+}
+
+/// Classify a message by its payload, rejecting known message types that lack required fields.
+/// Payloads without `llm_task` or a recognised `message_type` are `Generic`.
+pub fn validate_payload(message: &Message) -> Result<MessageKind> {
+    let payload = &message.payload;
+    let invalid = |reason: String| Error::InvalidMessage(format!("message {}: {}", message.id, reason));
+
+    if let Some(task) = payload.get("llm_task") {
+        let task = task.as_str().ok_or_else(|| invalid("'llm_task' must be a string".to_string()))?;
+        return Ok(MessageKind::LlmTask { task: task.to_string() });
+    }
+
+    let message_type = match payload.get("message_type") {
+        Some(value) => value.as_str().ok_or_else(|| invalid("'message_type' must be a string".to_string()))?,
+        None => return Ok(MessageKind::Generic),
+    };
+    let required = |object: &serde_json::Value, field: &str| -> Result<serde_json::Value> {
+        object.get(field).cloned()
+            .ok_or_else(|| invalid(format!("{} is missing required field '{}'", message_type, field)))
+    };
+    let optional_str = |object: &serde_json::Value, field: &str, default: &str| -> String {
+        object.get(field).and_then(|v| v.as_str()).unwrap_or(default).to_string()
+    };
+
+    match message_type {
+        "state_update" => match required(payload, "updates")? {
+            serde_json::Value::Object(updates) => Ok(MessageKind::StateUpdate { updates }),
+            _ => Err(invalid("state_update 'updates' must be an object".to_string())),
+        },
+        "coordination" => Ok(MessageKind::Coordination {
+            coordination_type: optional_str(payload, "coordination_type", "unknown"),
+        }),
+        "data_transfer" => Ok(MessageKind::DataTransfer {
+            data: required(payload, "data")?,
+            transfer_id: optional_str(payload, "transfer_id", "unknown"),
+        }),
+        "scraping_task" => {
+            let target = required(payload, "target")?;
+            let url = required(&target, "url")?.as_str()
+                .ok_or_else(|| invalid("scraping_task target 'url' must be a string".to_string()))?
+                .to_string();
+            Ok(MessageKind::ScrapingTask {
+                url,
+                title: optional_str(&target, "title", "Unknown"),
+                task_id: optional_str(&target, "id", "unknown"),
+                config: payload.get("config").cloned(),
+            })
+        }
+        "crawl" => {
+            required(payload, "start_url")?.as_str()
+                .ok_or_else(|| invalid("crawl 'start_url' must be a string".to_string()))?;
+            Ok(MessageKind::Crawl {
+                crawl_id: optional_str(payload, "crawl_id", &message.id),
+                crawl: payload.clone(),
+                config: payload.get("config").cloned(),
+            })
+        }
+        "workflow_step" => Ok(MessageKind::WorkflowStep {
+            step_id: optional_str(payload, "step_id", "unknown"),
+        }),
+        "result" => Ok(MessageKind::Result {
+            correlation_id: message.correlation_id.clone()
+                .ok_or_else(|| invalid("result message has no correlation_id".to_string()))?,
+            result: required(payload, "result")?,
+        }),
+        "finalize_scrape" => Ok(MessageKind::FinalizeScrape {
+            clear: payload.get("clear").and_then(|v| v.as_bool()).unwrap_or(false),
+        }),
+        _ => Ok(MessageKind::Generic),
+    }
+}
+
+/// Wire encoding for messages published over NATS
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Serializer {
+    #[default]
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+// Binary encodings start with a tag byte that can never open a JSON document, so a
+// peer decoding the wrong format fails instead of misreading the bytes. JSON stays
+// untagged to keep existing peers compatible.
+const MESSAGE_PACK_TAG: u8 = 0x01;
+const CBOR_TAG: u8 = 0x02;
+
+// Debug builds can pretty-print JSON wire payloads so captured traffic is readable
+static PRETTY_JSON: AtomicBool = AtomicBool::new(false);
+
+/// Pretty-print JSON-encoded messages. Only honoured in debug builds; release builds
+/// always encode compactly.
+pub fn set_pretty_json(enabled: bool) {
+    PRETTY_JSON.store(enabled, Ordering::Relaxed);
+}
+
+fn pretty_json() -> bool {
+    cfg!(debug_assertions) && PRETTY_JSON.load(Ordering::Relaxed)
+}
+
+/// Wire encoding for agent messages: compact JSON. Everything that puts a message on
+/// NATS by hand should go through this (or `Message::encode`) rather than `serde_json`.
+pub fn encode_message(message: &Message) -> Result<Vec<u8>> {
+    message.encode(Serializer::Json)
+}
+
+/// Decode a message from the wire, whichever serializer produced it
+pub fn decode_message(bytes: &[u8]) -> Result<Message> {
+    Message::decode(bytes)
+}
+
+type HmacSha256 = hmac::Hmac<sha2::Sha256>;
+
+// HMAC-SHA256 of `data`, ready to finalize or verify
+fn hmac_sha256(key: &[u8], data: &[u8]) -> HmacSha256 {
+    use hmac::Mac;
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/// Body of a message that arrived from outside the agent system. Text and binary payloads
+/// keep their type so agents can handle them deliberately instead of as opaque JSON.
+/// Stored in `Message::payload` as `{"payload_type": "text" | "binary", "data": ...}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "payload_type", content = "data", rename_all = "snake_case")]
+pub enum MessagePayload {
+    Json(serde_json::Value),
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl MessagePayload {
+    /// Classify raw bytes by their content type. Without one, anything that isn't JSON is binary.
+    pub fn from_bytes(data: &[u8], content_type: Option<&str>) -> Self {
+        let content_type = content_type.map(|ct| ct.split(';').next().unwrap_or("").trim().to_ascii_lowercase());
+        let as_text = || std::str::from_utf8(data).ok().map(|text| MessagePayload::Text(text.to_string()));
+        match content_type.as_deref() {
+            None => serde_json::from_slice(data).map(MessagePayload::Json)
+                .unwrap_or_else(|_| MessagePayload::Binary(data.to_vec())),
+            Some(ct) if ct == "application/json" || ct.ends_with("+json") => serde_json::from_slice(data)
+                .map(MessagePayload::Json)
+                .ok()
+                .or_else(as_text)
+                .unwrap_or_else(|| MessagePayload::Binary(data.to_vec())),
+            Some(ct) if ct.starts_with("text/") => as_text().unwrap_or_else(|| MessagePayload::Binary(data.to_vec())),
+            Some(_) => MessagePayload::Binary(data.to_vec()),
+        }
+    }
+
+    /// The value to put in `Message::payload`; JSON payloads are stored as-is
+    pub fn into_value(self) -> serde_json::Value {
+        match self {
+            MessagePayload::Json(value) => value,
+            other => serde_json::to_value(other).unwrap_or(serde_json::Value::Null),
+        }
+    }
+}
+
+/// State key under which a correlated result is stored on the requesting agent
+pub fn correlation_key(correlation_id: &str) -> String {
+    format!("correlation_{}", correlation_id)
+}
+
+impl Message {
+    /// Where a result for this message should go, if the sender asked for one
+    pub fn reply_target(&self) -> Option<(String, AgentId)> {
+        let correlation_id = self.correlation_id.clone()?;
+        Some((correlation_id, self.reply_to.clone().unwrap_or_else(|| self.from.clone())))
+    }
+
+    /// Result message answering a request that carried `correlation_id`
+    pub fn result(from: AgentId, to: AgentId, correlation_id: String, result: serde_json::Value) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            from,
+            to,
+            payload: serde_json::json!({
+                "message_type": "result",
+                "result": result,
+            }),
+            timestamp: chrono::Utc::now().timestamp() as u64,
+            expires_at: None,
+            correlation_id: Some(correlation_id),
+            reply_to: None,
+            signature: None,
+        }
+    }
+
+    /// Sign with a secret shared by the agents that should trust this message. Sign after the
+    /// last change to the signed fields; changing them later invalidates the signature.
+    pub fn sign_message(&mut self, secret: impl AsRef<[u8]>) {
+        use hmac::Mac;
+        let mac = hmac_sha256(secret.as_ref(), &self.signed_bytes()).finalize().into_bytes();
+        self.signature = Some(mac.iter().map(|byte| format!("{:02x}", byte)).collect());
+    }
+
+    /// Whether the message carries a valid signature for `secret`. Unsigned messages fail.
+    pub fn verify_message(&self, secret: impl AsRef<[u8]>) -> bool {
+        use hmac::Mac;
+        let Some(signature) = self.signature.as_deref().and_then(decode_hex) else {
+            return false;
+        };
+        // Constant-time, so the time taken doesn't reveal how much matched
+        hmac_sha256(secret.as_ref(), &self.signed_bytes()).verify_slice(&signature).is_ok()
+    }
+
+    // Canonical form of the signed fields. `serde_json` sorts object keys, so a payload
+    // decoded and re-encoded by the receiver gives the same bytes. Routing fields are
+    // included so a result can't be redirected and an expiry can't be lifted.
+    fn signed_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(&(
+            &self.id,
+            &self.from.0,
+            &self.to.0,
+            &self.payload,
+            self.timestamp,
+            self.expires_at,
+            &self.correlation_id,
+            self.reply_to.as_ref().map(|id| &id.0),
+        ))
+        .unwrap_or_default()
+    }
+
+    /// The payload as a `MessagePayload`, recognising text and binary bodies stored by `into_value`
+    pub fn typed_payload(&self) -> MessagePayload {
+        match self.payload.get("payload_type").and_then(|v| v.as_str()) {
+            Some("text") | Some("binary") => serde_json::from_value(self.payload.clone())
+                .unwrap_or_else(|_| MessagePayload::Json(self.payload.clone())),
+            _ => MessagePayload::Json(self.payload.clone()),
+        }
+    }
+
+    /// The payload's `priority`; unknown values are logged and treated as `Normal`
+    pub fn priority(&self) -> Priority {
+        let Some(name) = self.payload.get("priority").and_then(|v| v.as_str()) else {
+            return Priority::default();
+        };
+        Priority::from_name(name).unwrap_or_else(|| {
+            log::warn!("Message {} has unknown priority {:?}, using normal", self.id, name);
+            Priority::default()
+        })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at < chrono::Utc::now().timestamp() as u64)
+    }
+
+    /// Encode for the wire with the given serializer
+    pub fn encode(&self, serializer: Serializer) -> Result<Vec<u8>> {
+        match serializer {
+            Serializer::Json if pretty_json() => Ok(serde_json::to_vec_pretty(self)?),
+            Serializer::Json => Ok(serde_json::to_vec(self)?),
+            #[cfg(feature = "msgpack")]
+            Serializer::MessagePack => {
+                let mut bytes = vec![MESSAGE_PACK_TAG];
+                rmp_serde::encode::write_named(&mut bytes, self)
+                    .map_err(|e| Error::Custom(format!("MessagePack encoding failed: {}", e)))?;
+                Ok(bytes)
+            }
+            #[cfg(feature = "cbor")]
+            Serializer::Cbor => {
+                let mut bytes = vec![CBOR_TAG];
+                ciborium::ser::into_writer(self, &mut bytes)
+                    .map_err(|e| Error::Custom(format!("CBOR encoding failed: {}", e)))?;
+                Ok(bytes)
+            }
+            #[allow(unreachable_patterns)]
+            other => Err(Error::Custom(format!("{:?} serializer is not enabled in this build", other))),
+        }
+    }
+
+    /// Decode a message produced by [`Message::encode`] with any serializer
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        match bytes.first() {
+            Some(&MESSAGE_PACK_TAG) => {
+                #[cfg(feature = "msgpack")]
+                return rmp_serde::from_slice(&bytes[1..])
+                    .map_err(|e| Error::InvalidMessage(format!("MessagePack decoding failed: {}", e)));
+                #[cfg(not(feature = "msgpack"))]
+                Err(Error::InvalidMessage("received MessagePack message but the msgpack feature is disabled".to_string()))
+            }
+            Some(&CBOR_TAG) => {
+                #[cfg(feature = "cbor")]
+                return ciborium::de::from_reader(&bytes[1..])
+                    .map_err(|e| Error::InvalidMessage(format!("CBOR decoding failed: {}", e)));
+                #[cfg(not(feature = "cbor"))]
+                Err(Error::InvalidMessage("received CBOR message but the cbor feature is disabled".to_string()))
+            }
+            _ => Ok(serde_json::from_slice(bytes)?),
+        }
+    }
+
+    /// Build the pong reply to a ping, echoing the ping's message id
+    pub fn pong(ping: &Message, from: AgentId) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            from,
+            to: ping.from.clone(),
+            payload: serde_json::json!({
+                "type": "pong",
+                "in_reply_to": ping.id,
+            }),
+            timestamp: chrono::Utc::now().timestamp_millis() as u64,
+            expires_at: None,
+            correlation_id: None,
+            reply_to: None,
+            signature: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,6 +420,34 @@ pub enum StateAction {
     Delete { key: String },
     Clear,
     List,
+    /// Add `delta` to an integer value; a missing key starts from zero
+    Increment { key: String, delta: i64 },
+    /// Push `value` onto an array, creating the array if the key is missing
+    Append { key: String, value: serde_json::Value },
+}
+
+impl StateAction {
+    /// Value of `key` after an `Increment`
+    pub fn incremented(key: &str, current: Option<&serde_json::Value>, delta: i64) -> Result<serde_json::Value> {
+        let base = match current {
+            None => 0,
+            Some(value) => value.as_i64()
+                .ok_or_else(|| Error::Custom(format!("cannot increment non-integer state key '{}'", key)))?,
+        };
+        Ok(serde_json::json!(base.saturating_add(delta)))
+    }
+
+    /// Value of `key` after an `Append`
+    pub fn appended(key: &str, current: Option<serde_json::Value>, value: serde_json::Value) -> Result<serde_json::Value> {
+        match current {
+            None => Ok(serde_json::json!([value])),
+            Some(serde_json::Value::Array(mut items)) => {
+                items.push(value);
+                Ok(serde_json::Value::Array(items))
+            }
+            Some(_) => Err(Error::Custom(format!("cannot append to non-array state key '{}'", key))),
+        }
+    }
 }
 
 // Lightweight agent handle for external API compatibility
@@ -52,6 +476,168 @@ pub struct AgentState {
     pub persistent_backend: Box<dyn MemoryBackend>,
     pub nats: Option<NatsConnection>,
     pub llm_client: Option<LLMClient>,
+    /// Maximum number of dead letters kept in the backend; the oldest are evicted first
+    pub deadletter_capacity: usize,
+    /// Which ephemeral keys `save_persistent_state` writes; everything by default
+    pub persist_filter: PersistFilter,
+    /// Inbound messages kept in the replay log, or `None` when logging is off
+    #[cfg(feature = "replay")]
+    pub message_log_capacity: Option<usize>,
+    // Sequence number for the next logged message; read from the backend on first use
+    #[cfg(feature = "replay")]
+    next_log_seq: Option<u64>,
+}
+
+/// Glob allow/deny lists selecting which state keys get persisted. `*` matches any run of
+/// characters and `?` a single one. An empty `include` allows every key; `exclude` wins.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PersistFilter {
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+impl PersistFilter {
+    pub fn new(include: Vec<String>, exclude: Vec<String>) -> Self {
+        Self { include, exclude }
+    }
+
+    pub fn allows(&self, key: &str) -> bool {
+        let included = self.include.is_empty() || self.include.iter().any(|pattern| glob_match(pattern, key));
+        included && !self.exclude.iter().any(|pattern| glob_match(pattern, key))
+    }
+}
+
+/// Whether `text` matches `pattern`, where `*` is any run of characters and `?` any one
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text index it was tried against
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` swallow one more character
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Backend key prefix for undeliverable messages
+pub const DEADLETTER_PREFIX: &str = "deadletter_";
+pub const DEFAULT_DEADLETTER_CAPACITY: usize = 100;
+
+/// Backend key prefix for the replay log; entries are stored under `msglog:<seq>`
+#[cfg(feature = "replay")]
+pub const MESSAGE_LOG_PREFIX: &str = "msglog:";
+#[cfg(feature = "replay")]
+pub const DEFAULT_MESSAGE_LOG_CAPACITY: usize = 1000;
+
+// Zero-padded so the backend's lexical key order is sequence order
+#[cfg(feature = "replay")]
+fn message_log_key(seq: u64) -> String {
+    format!("{}{:020}", MESSAGE_LOG_PREFIX, seq)
+}
+
+/// Logged messages with a sequence number of at least `from_seq`, in order
+#[cfg(feature = "replay")]
+pub async fn logged_messages(log: &mut dyn MemoryBackend, from_seq: u64) -> Result<Vec<(u64, Message)>> {
+    let mut keys = log.list_keys(Some(MESSAGE_LOG_PREFIX)).await?;
+    keys.sort();
+
+    let mut messages = Vec::with_capacity(keys.len());
+    for key in keys {
+        let Ok(seq) = key[MESSAGE_LOG_PREFIX.len()..].parse::<u64>() else {
+            continue;
+        };
+        if seq < from_seq {
+            continue;
+        }
+        if let Some(entry) = log.retrieve(&key).await? {
+            messages.push((seq, serde_json::from_value(entry["message"].clone())?));
+        }
+    }
+    Ok(messages)
+}
+
+/// Re-send messages from the replay log in `log` to `agent`, starting at `from_seq`.
+/// Returns how many were replayed; a message the agent rejects stops the replay.
+#[cfg(feature = "replay")]
+pub async fn replay_messages(log: &mut dyn MemoryBackend, agent: &mut AgentState, from_seq: u64) -> Result<usize> {
+    let messages = logged_messages(log, from_seq).await?;
+    let count = messages.len();
+    for (seq, message) in messages {
+        log::debug!("Agent {} replaying logged message {} ({})", agent.id.0, seq, message.id);
+        agent.handle_message(message).await?;
+    }
+    Ok(count)
+}
+
+/// Keys fetched per `list_keys_paged` call while loading persistent state
+const LOAD_PAGE_SIZE: usize = 500;
+
+/// Leading bytes of every state snapshot
+pub const SNAPSHOT_MAGIC: &[u8; 4] = b"AGSS";
+/// Snapshot format written by this build; other versions are rejected on import
+pub const SNAPSHOT_VERSION: u16 = 1;
+
+/// Portable copy of an agent's state, used to move an agent between nodes.
+/// Persisted keys are stored without the `<agent id>:` backend prefix.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    pub agent_id: String,
+    pub ephemeral: HashMap<String, serde_json::Value>,
+    pub persisted: HashMap<String, serde_json::Value>,
+}
+
+impl StateSnapshot {
+    /// Magic, big-endian version, then the JSON body
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(64);
+        bytes.extend_from_slice(SNAPSHOT_MAGIC);
+        bytes.extend_from_slice(&SNAPSHOT_VERSION.to_be_bytes());
+        serde_json::to_writer(&mut bytes, self)?;
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let header_len = SNAPSHOT_MAGIC.len() + 2;
+        if bytes.len() < header_len || &bytes[..SNAPSHOT_MAGIC.len()] != SNAPSHOT_MAGIC {
+            return Err(Error::Custom("not an agent state snapshot".to_string()));
+        }
+        let version = u16::from_be_bytes([bytes[4], bytes[5]]);
+        if version != SNAPSHOT_VERSION {
+            return Err(Error::Custom(format!(
+                "unsupported snapshot version {} (expected {})", version, SNAPSHOT_VERSION
+            )));
+        }
+        Ok(serde_json::from_slice(&bytes[header_len..])?)
+    }
+
+    /// Single key/value view; ephemeral values win over persisted ones
+    pub fn merged(self) -> HashMap<String, serde_json::Value> {
+        let mut state = self.persisted;
+        state.extend(self.ephemeral);
+        state
+    }
 }
 
 impl AgentState {
@@ -62,9 +648,32 @@ impl AgentState {
             persistent_backend,
             nats: None,
             llm_client: None,
+            deadletter_capacity: DEFAULT_DEADLETTER_CAPACITY,
+            persist_filter: PersistFilter::default(),
+            #[cfg(feature = "replay")]
+            message_log_capacity: None,
+            #[cfg(feature = "replay")]
+            next_log_seq: None,
         }
     }
 
+    /// Record every inbound message in the backend, keeping the newest `capacity`
+    #[cfg(feature = "replay")]
+    pub fn with_message_log(mut self, capacity: usize) -> Self {
+        self.message_log_capacity = Some(capacity);
+        self
+    }
+
+    pub fn with_persist_filter(mut self, filter: PersistFilter) -> Self {
+        self.persist_filter = filter;
+        self
+    }
+
+    pub fn with_deadletter_capacity(mut self, capacity: usize) -> Self {
+        self.deadletter_capacity = capacity;
+        self
+    }
+
     pub fn with_nats(mut self, nats: NatsConnection) -> Self {
         self.nats = Some(nats);
         self
@@ -81,17 +690,30 @@ impl AgentState {
         self
     }
 
+    /// The client set with [`AgentState::with_llm`], if any
+    pub fn llm(&self) -> Option<&LLMClient> {
+        self.llm_client.as_ref()
+    }
+
     /// Load persistent state into ephemeral cache on startup
     pub async fn load_persistent_state(&mut self) -> Result<()> {
         let prefix = format!("{}:", self.id.0);
-        let keys = self.persistent_backend.list_keys(Some(&prefix)).await?;
-
-        for key in keys {
-            if let Some(local_key) = key.strip_prefix(&prefix) {
-                if let Some(value) = self.persistent_backend.retrieve(&key).await? {
-                    self.ephemeral_state.insert(local_key.to_string(), value);
+        let mut cursor = None;
+
+        loop {
+            let (keys, next) = self.persistent_backend
+                .list_keys_paged(Some(&prefix), cursor, LOAD_PAGE_SIZE).await?;
+            for key in keys {
+                if let Some(local_key) = key.strip_prefix(&prefix) {
+                    if let Some(value) = self.persistent_backend.retrieve(&key).await? {
+                        self.ephemeral_state.insert(local_key.to_string(), value);
+                    }
                 }
             }
+            match next {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
         }
 
         log::info!("Loaded {} state entries for agent {}", 
@@ -99,15 +721,58 @@ impl AgentState {
         Ok(())
     }
 
-    /// Save ephemeral state to persistent backend
+    /// Save ephemeral state to persistent backend, skipping keys `persist_filter` rejects
     pub async fn save_persistent_state(&mut self) -> Result<()> {
+        let mut saved = 0;
         for (key, value) in &self.ephemeral_state {
+            if !self.persist_filter.allows(key) {
+                continue;
+            }
             let persistent_key = format!("{}:{}", self.id.0, key);
             self.persistent_backend.store(&persistent_key, value).await?;
+            saved += 1;
         }
 
-        log::info!("Saved {} state entries for agent {}", 
-                  self.ephemeral_state.len(), self.id.0);
+        log::info!("Saved {} of {} state entries for agent {}", 
+                  saved, self.ephemeral_state.len(), self.id.0);
+        Ok(())
+    }
+
+    /// Serialize ephemeral and persisted state so another node can `import_snapshot` it.
+    /// Takes `&mut self` only because backend reads do.
+    pub async fn export_snapshot(&mut self) -> Result<Vec<u8>> {
+        let prefix = format!("{}:", self.id.0);
+        let mut persisted = HashMap::new();
+        for key in self.persistent_backend.list_keys(Some(&prefix)).await? {
+            if let Some(local_key) = key.strip_prefix(&prefix) {
+                if let Some(value) = self.persistent_backend.retrieve(&key).await? {
+                    persisted.insert(local_key.to_string(), value);
+                }
+            }
+        }
+
+        StateSnapshot {
+            agent_id: self.id.0.clone(),
+            ephemeral: self.ephemeral_state.clone(),
+            persisted,
+        }.to_bytes()
+    }
+
+    /// Replace this agent's state with a snapshot from `export_snapshot`
+    pub async fn import_snapshot(&mut self, bytes: &[u8]) -> Result<()> {
+        let snapshot = StateSnapshot::from_bytes(bytes)?;
+
+        let prefix = format!("{}:", self.id.0);
+        for key in self.persistent_backend.list_keys(Some(&prefix)).await? {
+            self.persistent_backend.delete(&key).await?;
+        }
+        for (key, value) in &snapshot.persisted {
+            self.persistent_backend.store(&format!("{}{}", prefix, key), value).await?;
+        }
+        self.ephemeral_state = snapshot.ephemeral;
+
+        log::info!("Agent {} restored snapshot of agent {} ({} persisted entries)",
+                  self.id.0, snapshot.agent_id, snapshot.persisted.len());
         Ok(())
     }
 
@@ -164,14 +829,54 @@ impl AgentState {
                 let keys: Vec<String> = self.ephemeral_state.keys().cloned().collect();
                 log::info!("Agent {} state keys: {:?}", self.id.0, keys);
             }
+            StateAction::Increment { key, delta } => {
+                let current = self.current_value(&key).await?;
+                let value = StateAction::incremented(&key, current.as_ref(), delta)?;
+                self.store_value(key, value).await?;
+            }
+            StateAction::Append { key, value } => {
+                let current = self.current_value(&key).await?;
+                let value = StateAction::appended(&key, current, value)?;
+                self.store_value(key, value).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Ephemeral value for `key`, falling back to the persistent backend
+    async fn current_value(&mut self, key: &str) -> Result<Option<serde_json::Value>> {
+        if let Some(value) = self.ephemeral_state.get(key) {
+            return Ok(Some(value.clone()));
         }
+        let persistent_key = format!("{}:{}", self.id.0, key);
+        self.persistent_backend.retrieve(&persistent_key).await
+    }
 
+    async fn store_value(&mut self, key: String, value: serde_json::Value) -> Result<()> {
+        let persistent_key = format!("{}:{}", self.id.0, key);
+        self.persistent_backend.store(&persistent_key, &value).await?;
+        log::debug!("Stored state: {} = {:?}", key, value);
+        self.ephemeral_state.insert(key, value);
         Ok(())
     }
 
     /// Process incoming messages
     pub async fn handle_message(&mut self, message: Message) -> Result<()> {
-        log::debug!("Agent {} processing message: {}", self.id.0, message.id);
+        #[cfg(feature = "replay")]
+        self.log_message(&message).await?;
+
+        let span = OperationSpan::new("handle_message", &self.id.0).with_message_id(&message.id);
+        span.run(self.dispatch_message(message)).await
+    }
+
+    async fn dispatch_message(&mut self, message: Message) -> Result<()> {
+        agent_event!(debug, "agent processing message", agent_id = self.id.0, message_id = message.id);
+
+        if message.is_expired() {
+            agent_event!(warn, "agent dropped expired message", agent_id = self.id.0, message_id = message.id);
+            return self.store_deadletter(&message, "message expired").await;
+        }
 
         // Check if this is a state action
         if let Ok(state_action) = serde_json::from_value::<StateAction>(message.payload.clone()) {
@@ -184,18 +889,22 @@ impl AgentState {
         }
 
         // Handle NATS forwarding for inter-node communication
-        if let Some(ref nats) = self.nats {
-            if message.to.0 != self.id.0 {
-                // Forward message via NATS if it's for another agent
-                let subject = format!("agent.{}", message.to.0);
-                let data = serde_json::to_vec(&message)?;
-                nats.publish(&subject, &data).await.map_err(|e| {
-                    Error::Custom(format!("NATS publish failed: {}", e))
-                })?;
-                
-                log::debug!("Forwarded message via NATS to {}", message.to.0);
-                return Ok(());
+        if self.nats.is_some() && message.to.0 != self.id.0 {
+            // Forward message via NATS if it's for another agent
+            let published = match (&self.nats, subject_for_agent(&message.to)) {
+                (Some(nats), Ok(subject)) => nats.publish_message(&subject, &message).await,
+                (_, Err(e)) => Err(e),
+                (None, Ok(_)) => Ok(()),
+            };
+
+            if let Err(e) = published {
+                let error = format!("NATS publish failed: {}", e);
+                self.store_deadletter(&message, &error).await?;
+                return Err(Error::Custom(error));
             }
+            
+            log::debug!("Forwarded message via NATS to {}", message.to.0);
+            return Ok(());
         }
 
         // Process message payload (customize based on your application needs)
@@ -206,6 +915,12 @@ impl AgentState {
 
     /// Application-specific message processing
     async fn process_application_message(&mut self, message: &Message) -> Result<()> {
+        if let Ok(MessageKind::Result { correlation_id, result }) = validate_payload(message) {
+            log::debug!("Agent {} received result for correlation {}", self.id.0, correlation_id);
+            self.ephemeral_state.insert(correlation_key(&correlation_id), result);
+            return Ok(());
+        }
+
         // Store the last message in ephemeral state
         let state_key = format!("last_message_from_{}", message.from.0);
         self.ephemeral_state.insert(state_key, message.payload.clone());
@@ -215,7 +930,7 @@ impl AgentState {
             match msg_type.as_str() {
                 Some("ping") => {
                     log::info!("Agent {} received ping from {}", self.id.0, message.from.0);
-                    // Could send pong response here
+                    self.send_pong(message).await?;
                 }
                 Some("data_update") => {
                     if let Some(data) = message.payload.get("data") {
@@ -237,80 +952,178 @@ impl AgentState {
         Ok(())
     }
 
-    /// LLM-enhanced message processing
+    // Append `message` to the replay log, evicting the entry that falls out of capacity
+    #[cfg(feature = "replay")]
+    async fn log_message(&mut self, message: &Message) -> Result<()> {
+        let Some(capacity) = self.message_log_capacity else {
+            return Ok(());
+        };
+
+        let seq = match self.next_log_seq {
+            Some(seq) => seq,
+            None => logged_messages(self.persistent_backend.as_mut(), 0).await?
+                .last()
+                .map_or(0, |(seq, _)| seq + 1),
+        };
+        let entry = serde_json::json!({
+            "message": message,
+            "timestamp": chrono::Utc::now().timestamp_millis(),
+        });
+        self.persistent_backend.store(&message_log_key(seq), &entry).await?;
+        self.next_log_seq = Some(seq + 1);
+
+        if let Some(evicted) = (seq + 1).checked_sub(capacity as u64 + 1) {
+            self.persistent_backend.delete(&message_log_key(evicted)).await?;
+        }
+        Ok(())
+    }
+
+    /// Keep an undeliverable message in the backend for later retry or inspection
+    async fn store_deadletter(&mut self, message: &Message, error: &str) -> Result<()> {
+        let mut keys = self.persistent_backend.list_keys(Some(DEADLETTER_PREFIX)).await?;
+        if keys.len() >= self.deadletter_capacity {
+            let mut entries = Vec::with_capacity(keys.len());
+            for key in keys.drain(..) {
+                let timestamp = self.persistent_backend.retrieve(&key).await?
+                    .and_then(|entry| entry["timestamp"].as_i64())
+                    .unwrap_or(0);
+                entries.push((timestamp, key));
+            }
+            entries.sort();
+
+            let excess = entries.len() + 1 - self.deadletter_capacity.max(1);
+            for (_, key) in entries.into_iter().take(excess) {
+                log::warn!("Agent {} evicting dead letter {}", self.id.0, key);
+                self.persistent_backend.delete(&key).await?;
+            }
+        }
+
+        let entry = serde_json::json!({
+            "message": message,
+            "error": error,
+            "timestamp": chrono::Utc::now().timestamp_millis(),
+        });
+        self.persistent_backend.store(&format!("{}{}", DEADLETTER_PREFIX, message.id), &entry).await?;
+
+        log::warn!("Agent {} dead-lettered message {}: {}", self.id.0, message.id, error);
+        Ok(())
+    }
+
+    /// Remove and return all dead letters, oldest first, with the error that caused each
+    pub async fn drain_deadletters(&mut self) -> Result<Vec<(Message, String)>> {
+        let keys = self.persistent_backend.list_keys(Some(DEADLETTER_PREFIX)).await?;
+
+        let mut entries = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some(entry) = self.persistent_backend.retrieve(&key).await? {
+                let timestamp = entry["timestamp"].as_i64().unwrap_or(0);
+                let message: Message = serde_json::from_value(entry["message"].clone())?;
+                let error = entry["error"].as_str().unwrap_or_default().to_string();
+                entries.push((timestamp, message, error));
+            }
+            self.persistent_backend.delete(&key).await?;
+        }
+
+        entries.sort_by_key(|(timestamp, _, _)| *timestamp);
+        Ok(entries.into_iter().map(|(_, message, error)| (message, error)).collect())
+    }
+
+    /// Reply to a ping on its `reply_to` subject, or the sender's agent subject
+    async fn send_pong(&self, ping: &Message) -> Result<()> {
+        let Some(ref nats) = self.nats else {
+            log::debug!("Agent {} has no NATS connection to send pong", self.id.0);
+            return Ok(());
+        };
+
+        let pong = Message::pong(ping, self.id.clone());
+        let subject = match ping.payload.get("reply_to").and_then(|v| v.as_str()) {
+            Some(reply_to) => reply_to.to_string(),
+            None => subject_for_agent(&ping.from)?,
+        };
+
+        nats.publish_message(&subject, &pong).await.map_err(|e| {
+            Error::Custom(format!("NATS publish failed: {}", e))
+        })?;
+
+        log::debug!("Agent {} sent pong for {} to {}", self.id.0, ping.id, subject);
+        Ok(())
+    }
+
+    /// Run an `llm_task` message through the agent's `LLMClient`, storing the result in
+    /// ephemeral and persistent state (`last_summary`, `workflow_plan` or `last_reasoning`)
     pub async fn handle_llm_message(&mut self, message: Message) -> Result<()> {
         log::debug!("Processing LLM message: {}", message.id);
 
-        if let Some(ref llm_client) = self.llm_client {
-            match message.payload.get("llm_task").and_then(|v| v.as_str()) {
-                Some("summarize") => {
-                    if let Some(data) = message.payload.get("data") {
-                        let data_array = data.as_array().unwrap_or(&vec![data.clone()]).clone();
-                        let data_array_len = data_array.len();
-                        let summary = llm_client.summarize_data(data_array).await?;
-                        
-                        // Store summary in state
-                        self.ephemeral_state.insert("last_summary".to_string(), serde_json::json!(summary));
-                        
-                        // Publish summary via NATS if configured
-                        if let Some(ref nats) = self.nats {
-                            let summary_msg = Message {
-                                id: uuid::Uuid::new_v4().to_string(),
-                                from: self.id.clone(),
-                                to: AgentId("summary_results".to_string()),
-                                payload: serde_json::json!({
-                                    "type": "summary_result",
-                                    "summary": summary,
-                                    "original_data_count": data_array_len
-                                }),
-                                timestamp: chrono::Utc::now().timestamp() as u64,
-                            };
-                            
-                            let subject = "results.summaries";
-                            let data = serde_json::to_vec(&summary_msg)?;
-                            nats.publish(subject, &data).await.map_err(|e| 
-                                Error::Custom(format!("Failed to publish summary: {}", e)))?;
-                        }
-
-                        log::info!("Agent {} completed summarization task", self.id.0);
-                    }
-                }
-                Some("plan_workflow") => {
-                    if let Some(task_desc) = message.payload.get("task_description").and_then(|v| v.as_str()) {
-                        let agents = message.payload.get("available_agents")
-                            .and_then(|v| v.as_array())
-                            .map(|arr| arr.iter().filter_map(|v| v.as_str().map(String::from)).collect())
-                            .unwrap_or_else(Vec::new);
-
-                        let workflow = llm_client.plan_workflow(task_desc, agents).await?;
-                        
-                        // Store workflow plan
-                        self.ephemeral_state.insert("workflow_plan".to_string(), serde_json::to_value(&workflow)?);
-                        
-                        log::info!("Agent {} created workflow plan with {} steps", self.id.0, workflow.len());
+        let Some(llm_client) = self.llm() else {
+            log::warn!("Agent {} received LLM message but no LLM client configured", self.id.0);
+            return Ok(());
+        };
+
+        match message.payload.get("llm_task").and_then(|v| v.as_str()) {
+            Some("summarize") => {
+                if let Some(data) = message.payload.get("data") {
+                    let data_array = data.as_array().cloned().unwrap_or_else(|| vec![data.clone()]);
+                    let data_array_len = data_array.len();
+                    let summary = llm_client.summarize_data(data_array).await?;
+
+                    self.store_value("last_summary".to_string(), serde_json::json!(summary)).await?;
+
+                    // Publish summary via NATS if configured
+                    if let Some(ref nats) = self.nats {
+                        let summary_msg = Message {
+                            id: uuid::Uuid::new_v4().to_string(),
+                            from: self.id.clone(),
+                            to: AgentId("summary_results".to_string()),
+                            payload: serde_json::json!({
+                                "type": "summary_result",
+                                "summary": summary,
+                                "original_data_count": data_array_len
+                            }),
+                            timestamp: chrono::Utc::now().timestamp() as u64,
+                            expires_at: None,
+                            correlation_id: None,
+                            reply_to: None,
+                            signature: None,
+                        };
+
+                        let subject = "results.summaries";
+                        nats.publish_message(subject, &summary_msg).await.map_err(|e|
+                            Error::Custom(format!("Failed to publish summary: {}", e)))?;
                     }
+
+                    agent_event!(info, "agent completed summarization", agent_id = self.id.0, message_id = message.id, items = data_array_len);
                 }
-                Some("reason") => {
-                    if let Some(prompt) = message.payload.get("prompt").and_then(|v| v.as_str()) {
-                        let context = message.payload.get("context")
-                            .and_then(|v| v.as_object())
-                            .map(|obj| obj.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
-                            .unwrap_or_else(HashMap::new);
-
-                        let reasoning_result = llm_client.reasoning_request(prompt, context).await?;
-                        
-                        // Store reasoning result
-                        self.ephemeral_state.insert("last_reasoning".to_string(), serde_json::json!(reasoning_result));
-                        
-                        log::info!("Agent {} completed reasoning task", self.id.0);
-                    }
+            }
+            Some("plan_workflow") => {
+                if let Some(task_desc) = message.payload.get("task_description").and_then(|v| v.as_str()) {
+                    let agents = message.payload.get("available_agents")
+                        .and_then(|v| v.as_array())
+                        .map(|arr| arr.iter().filter_map(|v| v.as_str().map(String::from)).collect())
+                        .unwrap_or_else(Vec::new);
+
+                    let workflow = llm_client.plan_workflow(task_desc, agents).await?;
+                    let steps = workflow.len();
+                    self.store_value("workflow_plan".to_string(), serde_json::to_value(&workflow)?).await?;
+
+                    log::info!("Agent {} created workflow plan with {} steps", self.id.0, steps);
                 }
-                _ => {
-                    log::debug!("Unknown LLM task type in message: {:?}", message.payload.get("llm_task"));
+            }
+            Some("reason") => {
+                if let Some(prompt) = message.payload.get("prompt").and_then(|v| v.as_str()) {
+                    let context = message.payload.get("context")
+                        .and_then(|v| v.as_object())
+                        .map(|obj| obj.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+                        .unwrap_or_else(HashMap::new);
+
+                    let reasoning_result = llm_client.reasoning_request(prompt, context).await?;
+                    self.store_value("last_reasoning".to_string(), serde_json::json!(reasoning_result)).await?;
+
+                    log::info!("Agent {} completed reasoning task", self.id.0);
                 }
             }
-        } else {
-            log::warn!("Agent {} received LLM message but no LLM client configured", self.id.0);
+            _ => {
+                log::debug!("Unknown LLM task type in message: {:?}", message.payload.get("llm_task"));
+            }
         }
 
         Ok(())
@@ -326,6 +1139,147 @@ mod tests {
     use super::*;
     use crate::memory::InMemoryBackend;
 
+    fn message_with(payload: serde_json::Value) -> Message {
+        Message {
+            id: "kind_msg".to_string(),
+            from: AgentId("sender".to_string()),
+            to: AgentId("receiver".to_string()),
+            payload,
+            timestamp: 0,
+            expires_at: None,
+            correlation_id: None,
+            reply_to: None,
+            signature: None,
+        }
+    }
+
+    #[test]
+    fn test_sign_and_verify_message() {
+        let mut message = message_with(serde_json::json!({"type": "data_update", "data": {"b": 2, "a": 1}}));
+        assert!(!message.verify_message("secret"));
+
+        message.sign_message("secret");
+        assert_eq!(message.signature.as_ref().unwrap().len(), 64);
+        assert!(message.verify_message("secret"));
+        assert!(message.verify_message(b"secret"));
+        assert!(!message.verify_message("other secret"));
+
+        // The signature survives the wire round trip
+        let decoded = decode_message(&encode_message(&message).unwrap()).unwrap();
+        assert!(decoded.verify_message("secret"));
+
+        // Unsigned messages keep their old wire format
+        let unsigned = serde_json::to_value(message_with(serde_json::json!({}))).unwrap();
+        assert!(unsigned.get("signature").is_none());
+    }
+
+    #[test]
+    fn test_tampered_message_fails_verification() {
+        let mut signed = message_with(serde_json::json!({"amount": 10}));
+        signed.sign_message("secret");
+
+        let mut payload = signed.clone();
+        payload.payload["amount"] = serde_json::json!(1000);
+        let mut sender = signed.clone();
+        sender.from = AgentId("impostor".to_string());
+        let mut timestamp = signed.clone();
+        timestamp.timestamp += 1;
+        let mut expiry = signed.clone();
+        expiry.expires_at = Some(u64::MAX);
+        let mut correlation = signed.clone();
+        correlation.correlation_id = Some("other_request".to_string());
+        let mut reply_to = signed.clone();
+        reply_to.reply_to = Some(AgentId("eavesdropper".to_string()));
+        let mut signature = signed.clone();
+        signature.signature = Some("0".repeat(64));
+        let mut garbled = signed.clone();
+        garbled.signature = Some("not hex".to_string());
+
+        for tampered in [payload, sender, timestamp, expiry, correlation, reply_to, signature, garbled] {
+            assert!(!tampered.verify_message("secret"));
+        }
+    }
+
+    #[test]
+    fn test_hmac_sha256_matches_rfc_4231() {
+        // Test case 2 from RFC 4231
+        let mac = hmac::Mac::finalize(hmac_sha256(b"Jefe", b"what do ya want for nothing?")).into_bytes();
+        let hex: String = mac.iter().map(|byte| format!("{:02x}", byte)).collect();
+        assert_eq!(hex, "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+    }
+
+    #[test]
+    fn test_message_payload_from_bytes() {
+        let binary = [0xff, 0x00, 0x10];
+        assert_eq!(MessagePayload::from_bytes(&binary, None), MessagePayload::Binary(binary.to_vec()));
+        assert_eq!(MessagePayload::from_bytes(b"plain text", None), MessagePayload::Binary(b"plain text".to_vec()));
+        assert_eq!(MessagePayload::from_bytes(br#"{"a":1}"#, None), MessagePayload::Json(serde_json::json!({"a": 1})));
+        assert_eq!(MessagePayload::from_bytes(b"plain text", Some("text/plain; charset=utf-8")), MessagePayload::Text("plain text".to_string()));
+        assert_eq!(MessagePayload::from_bytes(br#"{"a":1}"#, Some("application/octet-stream")), MessagePayload::Binary(br#"{"a":1}"#.to_vec()));
+        assert_eq!(MessagePayload::from_bytes(&binary, Some("text/plain")), MessagePayload::Binary(binary.to_vec()));
+
+        // Stored in a message and read back with its type intact
+        let message = message_with(MessagePayload::Binary(binary.to_vec()).into_value());
+        assert_eq!(message.typed_payload(), MessagePayload::Binary(binary.to_vec()));
+        let message = message_with(serde_json::json!({"type": "ping"}));
+        assert_eq!(message.typed_payload(), MessagePayload::Json(serde_json::json!({"type": "ping"})));
+    }
+
+    #[test]
+    fn test_validate_payload_kinds() {
+        let kind = |payload| validate_payload(&message_with(payload)).unwrap();
+
+        assert_eq!(kind(serde_json::json!({"llm_task": "summarize", "data": []})),
+                   MessageKind::LlmTask { task: "summarize".to_string() });
+        assert_eq!(kind(serde_json::json!({"message_type": "state_update", "updates": {"k": 1}})),
+                   MessageKind::StateUpdate { updates: serde_json::json!({"k": 1}).as_object().unwrap().clone() });
+        assert_eq!(kind(serde_json::json!({"message_type": "coordination"})),
+                   MessageKind::Coordination { coordination_type: "unknown".to_string() });
+        assert_eq!(kind(serde_json::json!({"message_type": "data_transfer", "transfer_id": "t1", "data": [1, 2]})),
+                   MessageKind::DataTransfer { transfer_id: "t1".to_string(), data: serde_json::json!([1, 2]) });
+        assert_eq!(kind(serde_json::json!({"message_type": "scraping_task", "target": {"url": "https://example.com", "id": "s1"}})),
+                   MessageKind::ScrapingTask {
+                       url: "https://example.com".to_string(),
+                       title: "Unknown".to_string(),
+                       task_id: "s1".to_string(),
+                       config: None,
+                   });
+        assert_eq!(kind(serde_json::json!({"data": "hello"})), MessageKind::Generic);
+        assert_eq!(kind(serde_json::json!({"message_type": "something_new"})), MessageKind::Generic);
+    }
+
+    #[test]
+    fn test_validate_payload_malformed() {
+        let error = |payload| validate_payload(&message_with(payload)).unwrap_err().to_string();
+
+        assert_eq!(error(serde_json::json!({"message_type": "scraping_task"})),
+                   "Invalid message: message kind_msg: scraping_task is missing required field 'target'");
+        assert!(error(serde_json::json!({"message_type": "scraping_task", "target": {"title": "x"}})).contains("'url'"));
+        assert!(error(serde_json::json!({"message_type": "state_update", "updates": [1]})).contains("must be an object"));
+        assert!(error(serde_json::json!({"message_type": "data_transfer"})).contains("'data'"));
+        assert!(error(serde_json::json!({"llm_task": 3})).contains("'llm_task' must be a string"));
+        assert!(error(serde_json::json!({"message_type": "result", "result": 1})).contains("no correlation_id"));
+    }
+
+    #[test]
+    fn test_result_message_answers_reply_target() {
+        let mut request = message_with(serde_json::json!({"llm_task": "summarize"}));
+        assert_eq!(request.reply_target().map(|(id, _)| id), None);
+
+        request.correlation_id = Some("req-1".to_string());
+        let (correlation_id, reply_to) = request.reply_target().unwrap();
+        assert_eq!(reply_to.0, "sender");
+
+        request.reply_to = Some(AgentId("collector".to_string()));
+        let (_, reply_to) = request.reply_target().unwrap();
+        let result = Message::result(request.to.clone(), reply_to, correlation_id, serde_json::json!({"ok": true}));
+        assert_eq!(result.to.0, "collector");
+        assert_eq!(validate_payload(&result).unwrap(), MessageKind::Result {
+            correlation_id: "req-1".to_string(),
+            result: serde_json::json!({"ok": true}),
+        });
+    }
+
     #[test]
     fn test_agent_id_creation() {
         let agent_id = AgentId("test_agent".to_string());
@@ -340,6 +1294,10 @@ mod tests {
             to: AgentId("receiver".to_string()),
             payload: serde_json::json!({"type": "test"}),
             timestamp: 12345,
+            expires_at: None,
+            correlation_id: None,
+            reply_to: None,
+            signature: None,
         };
         
         assert_eq!(message.id, "test_msg");
@@ -366,6 +1324,261 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_message_round_trip_per_serializer() {
+        let message = Message {
+            id: "codec_msg".to_string(),
+            from: AgentId("sender".to_string()),
+            to: AgentId("receiver".to_string()),
+            payload: serde_json::json!({"type": "data_update", "data": {"items": [1, 2, 3], "ok": true}}),
+            timestamp: 12345,
+            expires_at: None,
+            correlation_id: None,
+            reply_to: None,
+            signature: None,
+        };
+
+        let mut serializers = vec![Serializer::Json];
+        #[cfg(feature = "msgpack")]
+        serializers.push(Serializer::MessagePack);
+        #[cfg(feature = "cbor")]
+        serializers.push(Serializer::Cbor);
+
+        for serializer in serializers {
+            let bytes = message.encode(serializer).unwrap();
+            let decoded = Message::decode(&bytes).unwrap();
+            assert_eq!(decoded.id, message.id, "{:?}", serializer);
+            assert_eq!(decoded.to.0, message.to.0);
+            assert_eq!(decoded.payload, message.payload);
+            assert_eq!(decoded.timestamp, message.timestamp);
+        }
+
+        // A tagged binary frame never parses as JSON by accident
+        assert!(Message::decode(&[MESSAGE_PACK_TAG, 0x80]).is_err());
+    }
+
+    #[test]
+    fn test_encode_message_round_trip() {
+        let mut message = message_with(serde_json::json!({"message_type": "ping", "nested": {"n": 1}}));
+        message.correlation_id = Some("corr".to_string());
+
+        let bytes = encode_message(&message).unwrap();
+        assert!(!bytes.contains(&b'\n'), "wire payloads are compact");
+        let decoded = decode_message(&bytes).unwrap();
+        assert_eq!(decoded.id, message.id);
+        assert_eq!(decoded.payload, message.payload);
+        assert_eq!(decoded.correlation_id, message.correlation_id);
+
+        set_pretty_json(true);
+        let pretty = encode_message(&message).unwrap();
+        set_pretty_json(false);
+        assert_eq!(pretty.contains(&b'\n'), cfg!(debug_assertions));
+        assert_eq!(decode_message(&pretty).unwrap().payload, message.payload);
+    }
+
+    #[test]
+    fn test_snapshot_round_trip_and_version_mismatch() {
+        let mut source = AgentState::new(AgentId("mover".to_string()), Box::new(InMemoryBackend::new()));
+        let mut target = AgentState::new(AgentId("mover".to_string()), Box::new(InMemoryBackend::new()));
+
+        futures::executor::block_on(async {
+            source.handle_state_action(StateAction::Store { key: "progress".to_string(), value: serde_json::json!(42) }).await.unwrap();
+            source.persistent_backend.store("mover:checkpoint", &serde_json::json!({"page": 3})).await.unwrap();
+            source.ephemeral_state.insert("scratch".to_string(), serde_json::json!("in flight"));
+
+            let bytes = source.export_snapshot().await.unwrap();
+            assert_eq!(&bytes[..4], SNAPSHOT_MAGIC);
+
+            target.import_snapshot(&bytes).await.unwrap();
+            assert_eq!(target.ephemeral_state, source.ephemeral_state);
+            assert_eq!(target.persistent_backend.retrieve("mover:checkpoint").await.unwrap(), Some(serde_json::json!({"page": 3})));
+            assert_eq!(target.persistent_backend.retrieve("mover:progress").await.unwrap(), Some(serde_json::json!(42)));
+
+            let mut future_version = bytes.clone();
+            future_version[4..6].copy_from_slice(&(SNAPSHOT_VERSION + 1).to_be_bytes());
+            let err = target.import_snapshot(&future_version).await.unwrap_err();
+            assert!(err.to_string().contains("unsupported snapshot version 2"));
+
+            assert!(target.import_snapshot(b"{}").await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_expired_message_is_dead_lettered_not_stored() {
+        let mut agent_state = AgentState::new(AgentId("ttl_agent".to_string()), Box::new(InMemoryBackend::new()));
+        let mut message = message_with(serde_json::to_value(StateAction::Store {
+            key: "stale_key".to_string(),
+            value: serde_json::json!(true),
+        }).unwrap());
+        message.expires_at = Some(chrono::Utc::now().timestamp() as u64 - 60);
+        assert!(message.is_expired());
+
+        futures::executor::block_on(async {
+            agent_state.handle_message(message).await.unwrap();
+            assert!(!agent_state.ephemeral_state.contains_key("stale_key"));
+
+            let deadletters = agent_state.drain_deadletters().await.unwrap();
+            assert_eq!(deadletters.len(), 1);
+            assert_eq!(deadletters[0].1, "message expired");
+        });
+
+        // Messages serialized before the field existed still decode
+        let legacy: Message = serde_json::from_value(serde_json::json!({
+            "id": "old", "from": "a", "to": "b", "payload": {}, "timestamp": 1
+        })).unwrap();
+        assert_eq!(legacy.expires_at, None);
+        assert!(!legacy.is_expired());
+    }
+
+    #[cfg(feature = "replay")]
+    #[test]
+    fn test_replayed_messages_rebuild_state() {
+        let logged_agent = |capacity| AgentState::new(AgentId("replayed".to_string()), Box::new(InMemoryBackend::new()))
+            .with_message_log(capacity);
+        let messages = [
+            message_with(serde_json::json!({"type": "data_update", "data": [1, 2, 3]})),
+            message_with(serde_json::to_value(StateAction::Increment { key: "hits".to_string(), delta: 2 }).unwrap()),
+            message_with(serde_json::to_value(StateAction::Append { key: "log".to_string(), value: serde_json::json!("seen") }).unwrap()),
+        ];
+
+        futures::executor::block_on(async {
+            let mut original = logged_agent(DEFAULT_MESSAGE_LOG_CAPACITY);
+            let mut bounded = logged_agent(2);
+            for message in messages {
+                original.handle_message(message.clone()).await.unwrap();
+                bounded.handle_message(message).await.unwrap();
+            }
+
+            let mut fresh = AgentState::new(AgentId("replayed".to_string()), Box::new(InMemoryBackend::new()));
+            assert_eq!(replay_messages(original.persistent_backend.as_mut(), &mut fresh, 0).await.unwrap(), 3);
+            assert_eq!(fresh.ephemeral_state, original.ephemeral_state);
+            assert_eq!(fresh.ephemeral_state["hits"], serde_json::json!(2));
+
+            let mut partial = AgentState::new(AgentId("replayed".to_string()), Box::new(InMemoryBackend::new()));
+            assert_eq!(replay_messages(original.persistent_backend.as_mut(), &mut partial, 2).await.unwrap(), 1);
+            assert!(!partial.ephemeral_state.contains_key("hits"));
+
+            // The bounded log keeps only the newest entries
+            let kept = logged_messages(bounded.persistent_backend.as_mut(), 0).await.unwrap();
+            assert_eq!(kept.iter().map(|(seq, _)| *seq).collect::<Vec<_>>(), vec![1, 2]);
+        });
+    }
+
+    #[test]
+    fn test_priority_levels() {
+        let priority = |payload| message_with(payload).priority();
+        assert_eq!(priority(serde_json::json!({"priority": "low"})), Priority::Low);
+        assert_eq!(priority(serde_json::json!({"priority": "normal"})), Priority::Normal);
+        assert_eq!(priority(serde_json::json!({"priority": "medium"})), Priority::Normal);
+        assert_eq!(priority(serde_json::json!({"priority": "high"})), Priority::High);
+        assert_eq!(priority(serde_json::json!({"priority": "critical"})), Priority::Critical);
+        assert!(Priority::Critical > Priority::High && Priority::High > Priority::Normal && Priority::Normal > Priority::Low);
+    }
+
+    #[test]
+    fn test_missing_or_unknown_priority_is_normal() {
+        assert_eq!(message_with(serde_json::json!({"type": "ping"})).priority(), Priority::Normal);
+        assert_eq!(message_with(serde_json::json!({"priority": "urgent"})).priority(), Priority::Normal);
+        assert_eq!(message_with(serde_json::json!({"priority": 5})).priority(), Priority::Normal);
+
+        let parsed: Priority = serde_json::from_value(serde_json::json!("urgent")).unwrap();
+        assert_eq!(parsed, Priority::Normal);
+        let parsed: Priority = serde_json::from_value(serde_json::json!("high")).unwrap();
+        assert_eq!(parsed, Priority::High);
+        assert_eq!(serde_json::to_value(Priority::Critical).unwrap(), serde_json::json!("critical"));
+    }
+
+    #[test]
+    fn test_increment_and_append_state_actions() {
+        let mut agent_state = AgentState::new(
+            AgentId("counter_agent".to_string()),
+            Box::new(InMemoryBackend::new()),
+        );
+
+        futures::executor::block_on(async {
+            for delta in [1, 1, 5] {
+                agent_state.handle_state_action(StateAction::Increment { key: "hits".to_string(), delta }).await.unwrap();
+            }
+            for entry in ["started", "finished"] {
+                agent_state.handle_state_action(StateAction::Append {
+                    key: "log".to_string(),
+                    value: serde_json::json!(entry),
+                }).await.unwrap();
+            }
+
+            assert_eq!(agent_state.ephemeral_state["hits"], serde_json::json!(7));
+            assert_eq!(agent_state.ephemeral_state["log"], serde_json::json!(["started", "finished"]));
+
+            let err = agent_state.handle_state_action(StateAction::Increment { key: "log".to_string(), delta: 1 }).await;
+            assert!(err.is_err());
+        });
+
+        // Existing variants keep their wire format
+        let store = serde_json::to_value(StateAction::Store { key: "k".to_string(), value: serde_json::json!(1) }).unwrap();
+        assert_eq!(store, serde_json::json!({"Store": {"key": "k", "value": 1}}));
+    }
+
+    #[test]
+    fn test_pong_echoes_ping_id() {
+        let ping = Message {
+            id: "ping_1".to_string(),
+            from: AgentId("caller".to_string()),
+            to: AgentId("callee".to_string()),
+            payload: serde_json::json!({"type": "ping"}),
+            timestamp: 12345,
+            expires_at: None,
+            correlation_id: None,
+            reply_to: None,
+            signature: None,
+        };
+
+        let pong = Message::pong(&ping, AgentId("callee".to_string()));
+        assert_eq!(pong.to.0, "caller");
+        assert_eq!(pong.from.0, "callee");
+        assert_eq!(pong.payload["type"], "pong");
+        assert_eq!(pong.payload["in_reply_to"], "ping_1");
+    }
+
+    #[cfg(not(feature = "nats"))]
+    #[test]
+    fn test_failed_forward_goes_to_deadletters() {
+        use crate::nats_comm::NatsConfig;
+
+        let mut agent_state = AgentState::new(
+            AgentId("dlq_agent".to_string()),
+            Box::new(InMemoryBackend::new()),
+        )
+        .with_nats(NatsConnection::failing(NatsConfig::default()))
+        .with_deadletter_capacity(2);
+
+        let message = |id: &str| Message {
+            id: id.to_string(),
+            from: AgentId("dlq_agent".to_string()),
+            to: AgentId("remote_agent".to_string()),
+            payload: serde_json::json!({"type": "data_update"}),
+            timestamp: 12345,
+            expires_at: None,
+            correlation_id: None,
+            reply_to: None,
+            signature: None,
+        };
+
+        futures::executor::block_on(async {
+            for id in ["m1", "m2", "m3"] {
+                assert!(agent_state.handle_message(message(id)).await.is_err());
+                // Keep insertion timestamps distinct so eviction order is deterministic
+                std::thread::sleep(std::time::Duration::from_millis(2));
+            }
+
+            let deadletters = agent_state.drain_deadletters().await.unwrap();
+            let ids: Vec<&str> = deadletters.iter().map(|(m, _)| m.id.as_str()).collect();
+            assert_eq!(ids, vec!["m2", "m3"]);
+            assert!(deadletters[0].1.contains("NATS publish failed"));
+
+            assert!(agent_state.drain_deadletters().await.unwrap().is_empty());
+        });
+    }
+
     #[cfg(feature = "nats")]
     #[tokio::test]
     async fn test_agent_state_operations() {
@@ -423,6 +1636,36 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("pending_llm_task_*", "pending_llm_task_42"));
+        assert!(glob_match("*_data_?", "scraped_data_1"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(!glob_match("a*b*c", "axxbyy"));
+        assert!(!glob_match("scraped_*", "summary"));
+    }
+
+    #[test]
+    fn test_excluded_keys_are_not_persisted() {
+        futures::executor::block_on(async {
+            let mut agent_state = AgentState::new(AgentId("filtered".to_string()), Box::new(InMemoryBackend::new()))
+                .with_persist_filter(PersistFilter::new(
+                    vec!["summary".to_string(), "scraped_*".to_string(), "pending_llm_task_*".to_string()],
+                    vec!["scraped_content_*".to_string(), "pending_llm_task_*".to_string()],
+                ));
+            for key in ["summary", "scraped_data_1", "scraped_content_1", "pending_llm_task_7", "other"] {
+                agent_state.ephemeral_state.insert(key.to_string(), serde_json::json!(key));
+            }
+
+            agent_state.save_persistent_state().await.unwrap();
+
+            let mut keys = agent_state.persistent_backend.list_keys(Some("filtered:")).await.unwrap();
+            keys.sort();
+            assert_eq!(keys, vec!["filtered:scraped_data_1".to_string(), "filtered:summary".to_string()]);
+        });
+    }
+
     #[cfg(feature = "nats")]
     #[tokio::test]
     async fn test_message_processing() {
@@ -442,6 +1685,10 @@ mod tests {
                 value: serde_json::json!({"from_message": true}),
             }).unwrap(),
             timestamp: 12345,
+            expires_at: None,
+            correlation_id: None,
+            reply_to: None,
+            signature: None,
         };
 
         // Process the message
@@ -476,6 +1723,10 @@ mod tests {
                 ]
             }),
             timestamp: chrono::Utc::now().timestamp() as u64,
+            expires_at: None,
+            correlation_id: None,
+            reply_to: None,
+            signature: None,
         };
 
         // Process the LLM message
@@ -510,6 +1761,10 @@ mod tests {
                 "available_agents": ["collector", "processor", "summarizer"]
             }),
             timestamp: chrono::Utc::now().timestamp() as u64,
+            expires_at: None,
+            correlation_id: None,
+            reply_to: None,
+            signature: None,
         };
 
         // Process the workflow planning message
@@ -551,6 +1806,10 @@ mod tests {
                 }
             }),
             timestamp: chrono::Utc::now().timestamp() as u64,
+            expires_at: None,
+            correlation_id: None,
+            reply_to: None,
+            signature: None,
         };
 
         // Process the reasoning message
@@ -562,6 +1821,49 @@ mod tests {
         assert!(reasoning_result.as_str().unwrap().len() > 0);
     }
 
+    #[test]
+    fn test_with_llm_exposes_client() {
+        use crate::llm_client::{LLMConfig, MockLLMProvider};
+
+        let agent_state = AgentState::new(AgentId("plain_agent".to_string()), Box::new(InMemoryBackend::new()));
+        assert!(agent_state.llm().is_none());
+
+        let client = LLMClient::new(Box::new(MockLLMProvider::new()), LLMConfig::default());
+        let agent_state = agent_state.with_llm(client);
+        assert_eq!(agent_state.llm().map(|client| client.provider_name()), Some("mock"));
+        assert_eq!(agent_state.ephemeral_state["llm_client_config"]["provider"], "mock");
+    }
+
+    #[test]
+    fn test_llm_message_uses_client_and_persists_result() {
+        use crate::llm_client::{LLMConfig, MockLLMProvider};
+
+        futures::executor::block_on(async {
+            let backend = InMemoryBackend::new();
+            let client = LLMClient::new(
+                Box::new(MockLLMProvider::new().with_response("reason", "Shard by host.")),
+                LLMConfig::default(),
+            );
+            let mut agent_state = AgentState::new(AgentId("mock_llm_agent".to_string()), Box::new(backend.clone()))
+                .with_llm(client);
+
+            let mut message = message_with(serde_json::json!({
+                "llm_task": "reason",
+                "prompt": "How should scraping be split?",
+                "context": {"hosts": 3}
+            }));
+            message.to = AgentId("mock_llm_agent".to_string());
+            agent_state.handle_message(message).await.unwrap();
+
+            assert_eq!(agent_state.ephemeral_state["last_reasoning"], "Shard by host.");
+            let mut backend = backend;
+            assert_eq!(
+                backend.retrieve("mock_llm_agent:last_reasoning").await.unwrap(),
+                Some(serde_json::json!("Shard by host."))
+            );
+        });
+    }
+
     #[tokio::test]
     async fn test_agent_handles_llm_message_without_client() {
         let backend = Box::new(InMemoryBackend::new());
@@ -581,6 +1883,10 @@ mod tests {
                 "data": [{"title": "Test", "content": "Content"}]
             }),
             timestamp: chrono::Utc::now().timestamp() as u64,
+            expires_at: None,
+            correlation_id: None,
+            reply_to: None,
+            signature: None,
         };
 
         // Should not panic or error, just log a warning
@@ -0,0 +1,181 @@
+//! Typed loader for `scraping_config.json`, the file the scraping demos are driven by
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use crate::agent::Priority;
+use crate::scraping::split_url;
+use crate::{Error, Result};
+
+/// Top-level contents of `scraping_config.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScrapingConfig {
+    pub scraping_targets: Vec<ScrapingTarget>,
+    pub scraping_config: ScrapingSettings,
+    pub llm_config: LLMSettings,
+    #[serde(default)]
+    pub output_config: OutputConfig,
+}
+
+/// One page to scrape and the agent it is assigned to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScrapingTarget {
+    pub id: String,
+    pub url: String,
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    // Any name `Priority::from_name` accepts
+    pub priority: String,
+    pub agent_assignment: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScrapingSettings {
+    pub max_concurrent_requests: u32,
+    pub request_timeout_seconds: u64,
+    pub retry_attempts: u32,
+    pub user_agent: String,
+    pub respect_robots_txt: bool,
+    pub rate_limit_delay_ms: u64,
+}
+
+/// Model settings per LLM task
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LLMSettings {
+    pub summarization: LLMModelConfig,
+    pub workflow_planning: LLMModelConfig,
+    pub reasoning: LLMModelConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LLMModelConfig {
+    pub max_tokens: u32,
+    pub temperature: f32,
+    pub model: String,
+}
+
+/// Where the demos write their results
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputConfig {
+    pub summary_file: String,
+    pub workflow_file: String,
+    pub raw_data_file: String,
+    pub create_directories: bool,
+    pub append_timestamp: bool,
+    pub format: String,
+    pub include_metadata: bool,
+    // Scraping agents append each result here as NDJSON while the run is in progress
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ndjson_file: Option<String>,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            summary_file: "./scraping_results/summary.md".to_string(),
+            workflow_file: "./scraping_results/workflow_plan.json".to_string(),
+            raw_data_file: "./scraping_results/raw_data.json".to_string(),
+            create_directories: true,
+            append_timestamp: true,
+            format: "markdown".to_string(),
+            include_metadata: true,
+            ndjson_file: None,
+        }
+    }
+}
+
+impl ScrapingConfig {
+    /// Read, parse and validate a config file
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| Error::Custom(format!("Failed to read {}: {}", path.display(), e)))?;
+        Self::from_json(&content)
+    }
+
+    /// Parse and validate config JSON
+    pub fn from_json(json: &str) -> Result<Self> {
+        let config: ScrapingConfig = serde_json::from_str(json)
+            .map_err(|e| Error::Custom(format!("Failed to parse scraping config: {}", e)))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Reject configs with no targets, unknown priorities or non-http(s) URLs
+    pub fn validate(&self) -> Result<()> {
+        if self.scraping_targets.is_empty() {
+            return Err(Error::WorkflowValidation("scraping config has no scraping_targets".to_string()));
+        }
+
+        for target in &self.scraping_targets {
+            if target.id.trim().is_empty() {
+                return Err(Error::WorkflowValidation(format!("target for {} has an empty id", target.url)));
+            }
+            if Priority::from_name(&target.priority).is_none() {
+                return Err(Error::WorkflowValidation(format!(
+                    "target {} has invalid priority '{}' (expected low, normal, medium, high or critical)",
+                    target.id, target.priority
+                )));
+            }
+            let scheme_ok = target.url.starts_with("http://") || target.url.starts_with("https://");
+            if !scheme_ok || split_url(&target.url).is_none() {
+                return Err(Error::WorkflowValidation(format!(
+                    "target {} has malformed URL '{}' (expected http:// or https:// with a host)",
+                    target.id, target.url
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Targets assigned to `agent_id`
+    pub fn targets_for(&self, agent_id: &str) -> impl Iterator<Item = &ScrapingTarget> {
+        self.scraping_targets.iter().filter(move |target| target.agent_assignment == agent_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_with_target(url: &str, priority: &str) -> String {
+        let mut config: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/scraping_config.json")).unwrap(),
+        ).unwrap();
+        config["scraping_targets"] = serde_json::json!([{
+            "id": "t1", "url": url, "title": "T", "priority": priority, "agent_assignment": "web_scraper_1"
+        }]);
+        config.to_string()
+    }
+
+    #[test]
+    fn test_loads_repository_config() {
+        let config = ScrapingConfig::from_path(concat!(env!("CARGO_MANIFEST_DIR"), "/scraping_config.json")).unwrap();
+        assert!(!config.scraping_targets.is_empty());
+        assert_eq!(config.scraping_config.user_agent, crate::scraping::DEFAULT_USER_AGENT);
+        assert!(config.targets_for("web_scraper_1").count() > 0);
+    }
+
+    #[test]
+    fn test_rejects_invalid_priority() {
+        let error = ScrapingConfig::from_json(&config_with_target("https://example.com", "urgent")).unwrap_err();
+        assert!(matches!(error, Error::WorkflowValidation(_)));
+        assert!(error.to_string().contains("invalid priority 'urgent'"));
+    }
+
+    #[test]
+    fn test_rejects_malformed_url() {
+        for url in ["ftp://example.com", "https://", "example.com/page"] {
+            let error = ScrapingConfig::from_json(&config_with_target(url, "high")).unwrap_err();
+            assert!(error.to_string().contains("malformed URL"), "{}: {}", url, error);
+        }
+    }
+
+    #[test]
+    fn test_rejects_empty_targets_and_bad_json() {
+        let mut config: serde_json::Value = serde_json::from_str(&config_with_target("https://example.com", "high")).unwrap();
+        config["scraping_targets"] = serde_json::json!([]);
+        assert!(matches!(ScrapingConfig::from_json(&config.to_string()), Err(Error::WorkflowValidation(_))));
+        assert!(matches!(ScrapingConfig::from_json("{"), Err(Error::Custom(_))));
+    }
+}
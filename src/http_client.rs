@@ -0,0 +1,511 @@
+//! Cross-platform HTTP client abstraction
+//!
+//! Native builds use reqwest (behind `native-scraping`), browser WASM builds use the
+//! web-sys fetch API (behind `llm-all`). Builds with neither get a stub client that
+//! reports HTTP as unavailable so callers can fall back gracefully.
+
+use std::collections::HashMap;
+use std::time::Duration;
+use crate::{Result, Error};
+
+pub const DEFAULT_HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_MAX_REDIRECTS: usize = 10;
+
+/// Options applied when building a client
+#[derive(Debug, Clone)]
+pub struct HttpClientConfig {
+    // Applies to every request unless a helper passes its own timeout
+    pub timeout: Duration,
+    pub user_agent: Option<String>,
+    pub max_redirects: usize,
+    // Route requests through this proxy; defaults to `HTTPS_PROXY`
+    pub proxy_url: Option<String>,
+    // Comma-separated hosts that bypass the proxy; defaults to `NO_PROXY`
+    pub no_proxy: Option<String>,
+    // PEM file trusted in addition to the built-in roots (e.g. a corporate CA)
+    pub extra_root_cert_path: Option<String>,
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        Self {
+            timeout: DEFAULT_HTTP_TIMEOUT,
+            user_agent: None,
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            proxy_url: env_setting(&["HTTPS_PROXY", "https_proxy"]),
+            no_proxy: env_setting(&["NO_PROXY", "no_proxy"]),
+            extra_root_cert_path: None,
+        }
+    }
+}
+
+// First non-empty value among `names`
+fn env_setting(names: &[&str]) -> Option<String> {
+    names.iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty())
+}
+
+impl HttpClientConfig {
+    pub fn with_timeout_secs(timeout_seconds: u64) -> Self {
+        Self {
+            timeout: Duration::from_secs(timeout_seconds),
+            ..Self::default()
+        }
+    }
+}
+
+/// Raw HTTP response returned by every client implementation
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+impl HttpResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[async_trait::async_trait]
+pub trait HttpClient: Send + Sync {
+    fn config(&self) -> &HttpClientConfig;
+    async fn get(&self, url: &str, headers: HashMap<String, String>) -> Result<HttpResponse>;
+    async fn get_with_timeout(&self, url: &str, headers: HashMap<String, String>, timeout: Duration) -> Result<HttpResponse>;
+    async fn post(&self, url: &str, body: String, headers: HashMap<String, String>) -> Result<HttpResponse>;
+}
+
+#[cfg(target_arch = "wasm32")]
+#[async_trait::async_trait(?Send)]
+pub trait HttpClient {
+    fn config(&self) -> &HttpClientConfig;
+    async fn get(&self, url: &str, headers: HashMap<String, String>) -> Result<HttpResponse>;
+    async fn get_with_timeout(&self, url: &str, headers: HashMap<String, String>, timeout: Duration) -> Result<HttpResponse>;
+    async fn post(&self, url: &str, body: String, headers: HashMap<String, String>) -> Result<HttpResponse>;
+}
+
+// Native client backed by reqwest
+#[cfg(all(not(target_arch = "wasm32"), feature = "native-scraping"))]
+pub struct NativeHttpClient {
+    client: reqwest::Client,
+    config: HttpClientConfig,
+}
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "native-scraping"))]
+impl NativeHttpClient {
+    pub fn new() -> Self {
+        Self::with_config(HttpClientConfig::default())
+    }
+
+    /// Build a client from `config`, falling back to reqwest defaults if the proxy or CA can't be applied
+    pub fn with_config(config: HttpClientConfig) -> Self {
+        match Self::try_with_config(config.clone()) {
+            Ok(client) => client,
+            Err(e) => {
+                log::warn!("Failed to build configured HTTP client, using defaults: {}", e);
+                Self { client: reqwest::Client::new(), config }
+            }
+        }
+    }
+
+    pub fn try_with_config(config: HttpClientConfig) -> Result<Self> {
+        let mut builder = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .redirect(reqwest::redirect::Policy::limited(config.max_redirects));
+        if let Some(user_agent) = &config.user_agent {
+            builder = builder.user_agent(user_agent.clone());
+        }
+
+        if let Some(proxy_url) = &config.proxy_url {
+            let mut proxy = reqwest::Proxy::all(proxy_url).map_err(Error::http)?;
+            if let Some(no_proxy) = &config.no_proxy {
+                proxy = proxy.no_proxy(reqwest::NoProxy::from_string(no_proxy));
+            }
+            builder = builder.proxy(proxy);
+        }
+
+        if let Some(cert_path) = &config.extra_root_cert_path {
+            let pem = std::fs::read(cert_path)?;
+            let cert = reqwest::Certificate::from_pem(&pem).map_err(Error::http)?;
+            builder = builder.add_root_certificate(cert);
+        }
+
+        let client = builder.build().map_err(Error::http)?;
+        Ok(Self { client, config })
+    }
+
+    async fn execute(&self, request: reqwest::RequestBuilder, headers: HashMap<String, String>) -> Result<HttpResponse> {
+        let mut request = request;
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+
+        let response = request.send().await.map_err(transport_error)?;
+
+        let status = response.status().as_u16();
+        let headers = response.headers().iter()
+            .filter_map(|(name, value)| {
+                value.to_str().ok().map(|value| (name.as_str().to_string(), value.to_string()))
+            })
+            .collect();
+        let body = response.text().await.map_err(transport_error)?;
+
+        Ok(HttpResponse { status, headers, body })
+    }
+}
+
+// Timeouts and connection failures become I/O errors so callers can tell them apart from bad requests
+#[cfg(all(not(target_arch = "wasm32"), feature = "native-scraping"))]
+fn transport_error(e: reqwest::Error) -> Error {
+    use std::io::{Error as IoError, ErrorKind};
+
+    if e.is_timeout() {
+        Error::Io(IoError::new(ErrorKind::TimedOut, e))
+    } else if e.is_connect() || e.is_body() {
+        Error::Io(IoError::new(ErrorKind::ConnectionReset, e))
+    } else {
+        Error::http(e)
+    }
+}
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "native-scraping"))]
+impl Default for NativeHttpClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "native-scraping"))]
+#[async_trait::async_trait]
+impl HttpClient for NativeHttpClient {
+    fn config(&self) -> &HttpClientConfig {
+        &self.config
+    }
+
+    async fn get(&self, url: &str, headers: HashMap<String, String>) -> Result<HttpResponse> {
+        self.execute(self.client.get(url), headers).await
+    }
+
+    async fn get_with_timeout(&self, url: &str, headers: HashMap<String, String>, timeout: Duration) -> Result<HttpResponse> {
+        self.execute(self.client.get(url).timeout(timeout), headers).await
+    }
+
+    async fn post(&self, url: &str, body: String, headers: HashMap<String, String>) -> Result<HttpResponse> {
+        self.execute(self.client.post(url).body(body), headers).await
+    }
+}
+
+// Browser WASM client backed by the fetch API
+#[cfg(all(target_arch = "wasm32", feature = "llm-all"))]
+pub struct WasmHttpClient {
+    config: HttpClientConfig,
+}
+
+#[cfg(all(target_arch = "wasm32", feature = "llm-all"))]
+impl WasmHttpClient {
+    pub fn new() -> Self {
+        Self::with_config(HttpClientConfig::default())
+    }
+
+    // The browser owns redirects and the User-Agent header, so only the timeout applies here
+    pub fn with_config(config: HttpClientConfig) -> Self {
+        Self { config }
+    }
+
+    async fn fetch(&self, method: &str, url: &str, body: Option<String>, headers: HashMap<String, String>, timeout: Duration) -> Result<HttpResponse> {
+        use wasm_bindgen::JsCast;
+        use wasm_bindgen_futures::JsFuture;
+
+        let js_error = |context: &str, e: wasm_bindgen::JsValue| {
+            Error::Custom(format!("{}: {:?}", context, e))
+        };
+
+        let init = web_sys::RequestInit::new();
+        init.set_method(method);
+        let timeout_ms = u32::try_from(timeout.as_millis()).unwrap_or(u32::MAX);
+        init.set_signal(Some(&web_sys::AbortSignal::timeout_with_u32(timeout_ms)));
+        if let Some(body) = body {
+            init.set_body(&wasm_bindgen::JsValue::from_str(&body));
+        }
+
+        let request = web_sys::Request::new_with_str_and_init(url, &init)
+            .map_err(|e| js_error("Failed to build fetch request", e))?;
+        for (name, value) in &headers {
+            request.headers().set(name, value)
+                .map_err(|e| js_error("Failed to set request header", e))?;
+        }
+
+        let window = web_sys::window()
+            .ok_or_else(|| Error::Custom("No window available for fetch".to_string()))?;
+        let response = JsFuture::from(window.fetch_with_request(&request)).await
+            .map_err(|e| {
+                if format!("{:?}", e).contains("TimeoutError") {
+                    Error::Io(std::io::Error::new(std::io::ErrorKind::TimedOut, format!("{} timed out", url)))
+                } else {
+                    js_error("HTTP request failed", e)
+                }
+            })?;
+        let response: web_sys::Response = response.dyn_into()
+            .map_err(|e| js_error("Unexpected fetch result", e))?;
+
+        let status = response.status();
+        let mut response_headers = HashMap::new();
+        if let Ok(Some(content_type)) = response.headers().get("content-type") {
+            response_headers.insert("content-type".to_string(), content_type);
+        }
+
+        let text_promise = response.text()
+            .map_err(|e| js_error("Failed to read HTTP response body", e))?;
+        let text = JsFuture::from(text_promise).await
+            .map_err(|e| js_error("Failed to read HTTP response body", e))?;
+
+        Ok(HttpResponse {
+            status,
+            headers: response_headers,
+            body: text.as_string().unwrap_or_default(),
+        })
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", feature = "llm-all"))]
+#[async_trait::async_trait(?Send)]
+impl HttpClient for WasmHttpClient {
+    fn config(&self) -> &HttpClientConfig {
+        &self.config
+    }
+
+    async fn get(&self, url: &str, headers: HashMap<String, String>) -> Result<HttpResponse> {
+        self.fetch("GET", url, None, headers, self.config.timeout).await
+    }
+
+    async fn get_with_timeout(&self, url: &str, headers: HashMap<String, String>, timeout: Duration) -> Result<HttpResponse> {
+        self.fetch("GET", url, None, headers, timeout).await
+    }
+
+    async fn post(&self, url: &str, body: String, headers: HashMap<String, String>) -> Result<HttpResponse> {
+        self.fetch("POST", url, Some(body), headers, self.config.timeout).await
+    }
+}
+
+// Stub used when no HTTP implementation is compiled in
+#[derive(Default)]
+pub struct UnavailableHttpClient {
+    config: HttpClientConfig,
+}
+
+impl UnavailableHttpClient {
+    fn unavailable(url: &str) -> Error {
+        Error::Custom(format!("HTTP client not available in this build (requested {})", url))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[async_trait::async_trait]
+impl HttpClient for UnavailableHttpClient {
+    fn config(&self) -> &HttpClientConfig {
+        &self.config
+    }
+
+    async fn get(&self, url: &str, _headers: HashMap<String, String>) -> Result<HttpResponse> {
+        Err(Self::unavailable(url))
+    }
+
+    async fn get_with_timeout(&self, url: &str, _headers: HashMap<String, String>, _timeout: Duration) -> Result<HttpResponse> {
+        Err(Self::unavailable(url))
+    }
+
+    async fn post(&self, url: &str, _body: String, _headers: HashMap<String, String>) -> Result<HttpResponse> {
+        Err(Self::unavailable(url))
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+#[async_trait::async_trait(?Send)]
+impl HttpClient for UnavailableHttpClient {
+    fn config(&self) -> &HttpClientConfig {
+        &self.config
+    }
+
+    async fn get(&self, url: &str, _headers: HashMap<String, String>) -> Result<HttpResponse> {
+        Err(Self::unavailable(url))
+    }
+
+    async fn get_with_timeout(&self, url: &str, _headers: HashMap<String, String>, _timeout: Duration) -> Result<HttpResponse> {
+        Err(Self::unavailable(url))
+    }
+
+    async fn post(&self, url: &str, _body: String, _headers: HashMap<String, String>) -> Result<HttpResponse> {
+        Err(Self::unavailable(url))
+    }
+}
+
+/// Create the HTTP client for the current platform and feature set
+pub fn create_http_client(config: HttpClientConfig) -> Box<dyn HttpClient> {
+    #[cfg(all(not(target_arch = "wasm32"), feature = "native-scraping"))]
+    {
+        Box::new(NativeHttpClient::with_config(config))
+    }
+
+    #[cfg(all(target_arch = "wasm32", feature = "llm-all"))]
+    {
+        Box::new(WasmHttpClient::with_config(config))
+    }
+
+    #[cfg(not(any(
+        all(not(target_arch = "wasm32"), feature = "native-scraping"),
+        all(target_arch = "wasm32", feature = "llm-all")
+    )))]
+    {
+        Box::new(UnavailableHttpClient { config })
+    }
+}
+
+/// Fetch a URL with a plain GET request
+pub async fn get(client: &dyn HttpClient, url: &str, headers: HashMap<String, String>) -> Result<HttpResponse> {
+    client.get(url, headers).await
+}
+
+/// GET a URL with its own timeout and return the body bytes; non-2xx responses are errors
+pub async fn get_bytes(
+    client: &dyn HttpClient,
+    url: &str,
+    headers: HashMap<String, String>,
+    timeout: Duration,
+) -> Result<Vec<u8>> {
+    let response = client.get_with_timeout(url, headers, timeout).await?;
+    if !response.is_success() {
+        return Err(Error::Custom(format!("HTTP {} fetching {}", response.status, url)));
+    }
+    Ok(response.body.into_bytes())
+}
+
+/// GET a URL with its own timeout and parse the body as JSON
+pub async fn get_json(
+    client: &dyn HttpClient,
+    url: &str,
+    mut headers: HashMap<String, String>,
+    timeout: Duration,
+) -> Result<serde_json::Value> {
+    headers.entry("Accept".to_string()).or_insert_with(|| "application/json".to_string());
+    let body = get_bytes(client, url, headers, timeout).await?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// POST a JSON body and parse the JSON response, mapping HTTP failures to crate errors.
+/// Transport timeouts become `LLMTimeout` carrying the client's configured timeout.
+pub async fn post_json(
+    client: &dyn HttpClient,
+    url: &str,
+    body: &serde_json::Value,
+    mut headers: HashMap<String, String>,
+) -> Result<serde_json::Value> {
+    headers.insert("Content-Type".to_string(), "application/json".to_string());
+
+    let timeout = client.config().timeout.as_secs();
+    let response = match client.post(url, serde_json::to_string(body)?, headers).await {
+        Ok(response) => response,
+        Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::TimedOut => {
+            return Err(Error::LLMTimeout { timeout });
+        }
+        Err(e) => return Err(e),
+    };
+
+    match response.status {
+        429 => Err(Error::LLMRateLimit(response.body)),
+        408 | 504 => Err(Error::LLMTimeout { timeout }),
+        status if !response.is_success() => {
+            Err(Error::LLMProvider(format!("HTTP {}: {}", status, response.body)))
+        }
+        _ => Ok(serde_json::from_str(&response.body)?),
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    fn config_without_env() -> HttpClientConfig {
+        HttpClientConfig {
+            proxy_url: None,
+            no_proxy: None,
+            ..HttpClientConfig::default()
+        }
+    }
+
+    // GETs return a small JSON body, POSTs time out at the transport level
+    struct StubClient {
+        config: HttpClientConfig,
+    }
+
+    #[async_trait::async_trait]
+    impl HttpClient for StubClient {
+        fn config(&self) -> &HttpClientConfig {
+            &self.config
+        }
+
+        async fn get(&self, url: &str, headers: HashMap<String, String>) -> Result<HttpResponse> {
+            self.get_with_timeout(url, headers, self.config.timeout).await
+        }
+
+        async fn get_with_timeout(&self, _url: &str, _headers: HashMap<String, String>, _timeout: Duration) -> Result<HttpResponse> {
+            Ok(HttpResponse {
+                status: 200,
+                headers: HashMap::new(),
+                body: r#"{"ok": true}"#.to_string(),
+            })
+        }
+
+        async fn post(&self, _url: &str, _body: String, _headers: HashMap<String, String>) -> Result<HttpResponse> {
+            Err(Error::Io(std::io::Error::new(std::io::ErrorKind::TimedOut, "timed out")))
+        }
+    }
+
+    #[test]
+    fn test_post_json_timeout_uses_configured_seconds() {
+        let client = StubClient { config: HttpClientConfig::with_timeout_secs(12) };
+        let result = futures::executor::block_on(post_json(&client, "http://llm.local", &serde_json::json!({}), HashMap::new()));
+        match result {
+            Err(Error::LLMTimeout { timeout }) => assert_eq!(timeout, 12),
+            other => panic!("expected LLM timeout, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_get_json_parses_body() {
+        let client = StubClient { config: config_without_env() };
+        let value = futures::executor::block_on(get_json(&client, "http://api.local", HashMap::new(), Duration::from_secs(1))).unwrap();
+        assert_eq!(value["ok"], true);
+    }
+
+    #[cfg(feature = "native-scraping")]
+    #[test]
+    fn test_native_client_with_proxy() {
+        let config = HttpClientConfig {
+            proxy_url: Some("http://proxy.internal:3128".to_string()),
+            no_proxy: Some("localhost,.internal".to_string()),
+            ..config_without_env()
+        };
+        let client = NativeHttpClient::try_with_config(config).expect("proxy accepted");
+        assert_eq!(client.config().proxy_url.as_deref(), Some("http://proxy.internal:3128"));
+        assert_eq!(client.config().no_proxy.as_deref(), Some("localhost,.internal"));
+    }
+
+    #[cfg(feature = "native-scraping")]
+    #[test]
+    fn test_native_client_rejects_bad_proxy_and_missing_ca() {
+        let bad_proxy = HttpClientConfig {
+            proxy_url: Some("not a url".to_string()),
+            ..config_without_env()
+        };
+        assert!(NativeHttpClient::try_with_config(bad_proxy).is_err());
+
+        let missing_ca = HttpClientConfig {
+            extra_root_cert_path: Some("/nonexistent/corporate-ca.pem".to_string()),
+            ..config_without_env()
+        };
+        assert!(matches!(NativeHttpClient::try_with_config(missing_ca), Err(Error::Io(_))));
+    }
+}
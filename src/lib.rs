@@ -1,28 +1,47 @@
 //! Rust/WASM application using Lunatic and NATS for distributed agent-based systems
 
 pub mod agent;
+pub mod config;
+pub mod http_client;
 pub mod llm_client;
 pub mod memory;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 pub mod nats_comm;
+pub mod scraping;
 pub mod supervisor;
+pub mod telemetry;
 pub mod wasm_nats;
+pub mod workflow;
 
 // Re-export commonly used items
-pub use agent::{Agent, AgentState, AgentId, Message, StateAction};
-pub use llm_client::{LLMClient, LLMProvider, LLMRequest, LLMResponse, WorkflowStep, create_llm_client};
-pub use memory::MemoryBackend;
-pub use nats_comm::{NatsConfig, NatsConnection};
+pub use agent::{Agent, AgentState, AgentId, Message, MessageKind, MessagePayload, encode_message, decode_message, set_pretty_json, PersistFilter, Priority, correlation_key, Serializer, StateAction, StateSnapshot, validate_payload};
+#[cfg(feature = "replay")]
+pub use agent::{logged_messages, replay_messages};
+pub use config::{LLMModelConfig, LLMSettings, OutputConfig, ScrapingConfig, ScrapingSettings, ScrapingTarget};
+pub use llm_client::{ChatMessage, ChunkedSummary, ChatRole, LLMConversation, CircuitBreakerConfig, CircuitState, LLMClient, LLMProvider, LLMRequest, LLMResponse, OpenAIAuthStyle, ToolCall, ToolSpec, WorkflowStep, validate_workflow, create_llm_client, create_llm_client_checked, create_llm_client_strict, create_llm_client_strict_from, StrictLLMConfig, create_llm_client_from_spec, FallbackProvider, LLMProviderSpec, RetryPolicy, retry_llm_operation, safe_llm_operation};
+pub use memory::{migrate_backend, MemoryBackend};
+pub use nats_comm::{AgentLocation, AgentRegistration, BackoffStrategy, BoundedReceiver, LOCK_BUCKET, AgentSubscription, DiscoveryService, NatsConfig, NatsConnection, NatsConnectionPool, install_shared_pool, shared_pool, subject_for_agent, type_subject, validate_subject_id};
+#[cfg(feature = "jetstream")]
+pub use nats_comm::LockGuard;
+pub use scraping::{CrawlConfig, CrawlFrontier, ScrapeErrorKind, ScrapedContent, extract_links};
 pub use supervisor::{
-    AgentConfig, MemoryBackendType, AgentType, AgentProcess, AgentSupervisor,
-    spawn_agent_supervisor, spawn_single_agent, spawn_llm_enabled_agent,
+    AgentArgs, AgentConfig, FallbackMode, MemoryBackendType, AgentType, AgentProcess, AgentSupervisor, AgentAggregate, DEFAULT_AGGREGATE_TIMEOUT,
+    SupervisorOptions, spawn_agent_supervisor, spawn_agent_supervisor_with_options, spawn_single_agent, spawn_llm_enabled_agent,
     send_message_to_agent, send_state_action_to_agent,
-    get_agent_state, shutdown_agent, GetAgentState, Shutdown
+    get_agent_state, get_agent_state_timeout, get_agent_state_filtered, GetAgentStateFiltered, get_collected_count, get_llm_operations, cancel_operation, shutdown_agent, pause_agent, resume_agent, snapshot_agent, restore_agent_snapshot, wait_for_correlation, wait_for_operation, wait_for_operation_every, wait_for_state_key, OperationStatus, GetAgentState, GetLLMOperations, Shutdown, Pause, Resume, GetSnapshot, RestoreSnapshot, CancelOperation,
+    broadcast_message, broadcast_state_action, list_agents, shutdown_all, shutdown_all_with_timeout, DEFAULT_SHUTDOWN_TIMEOUT, flush_low_priority_batch, FlushBatch, COORDINATOR_LOCK, COORDINATOR_LOCK_TTL,
+    get_agent_metrics, get_agent_metrics_timeout, AgentMetrics, GetAgentMetrics, DEFAULT_MESSAGE_DEDUP_WINDOW, check_agent_health, check_agent_health_timeout, AgentHealth, HealthCheck, HEALTH_CACHE_TTL, request_from_agent, request_from_agent_timeout, AgentAck, ReceiveOutcome, bridge_nats_to_agent
 };
 pub use wasm_nats::{WasmNatsConfig, WasmNatsConnection, WasmConnectionStats, WasmNatsPublisher};
+pub use workflow::{StepResult, StepStatus, WorkflowExecutor, WorkflowResult};
 
 /// Common result type for the library
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Boxed underlying error kept as the `source()` of a wrapped failure
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
 /// Common error type for the library
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -53,23 +72,56 @@ pub enum Error {
     
     #[error("Workflow validation error: {0}")]
     WorkflowValidation(String),
+
+    #[error("Invalid message: {0}")]
+    InvalidMessage(String),
+
+    #[error("Scraping {url} failed: {kind}")]
+    Scraping { url: String, kind: ScrapeErrorKind },
+
+    // Same message as `Nats`, but keeps the client error reachable through `source()`
+    #[error("NATS error: {message}")]
+    NatsClient { message: String, #[source] source: BoxError },
+
+    #[error("HTTP request failed: {message}")]
+    Http { message: String, #[source] source: BoxError },
 }
 
 // Enhanced error handling methods
 impl Error {
+    /// Wrap a NATS client error, keeping it as the source
+    pub fn nats<E>(message: impl Into<String>, source: E) -> Self
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        Error::NatsClient { message: message.into(), source: Box::new(source) }
+    }
+
+    /// Wrap an HTTP client error, keeping it as the source
+    pub fn http<E>(source: E) -> Self
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        Error::Http { message: source.to_string(), source: Box::new(source) }
+    }
+
     pub fn is_retryable(&self) -> bool {
-        matches!(self, 
-            Error::LLMTimeout { .. } | 
+        match self {
+            Error::LLMTimeout { .. } |
             Error::LLMRateLimit(_) |
-            Error::Nats(_)
-        )
+            Error::Nats(_) |
+            Error::NatsClient { .. } => true,
+            Error::Scraping { kind, .. } => kind.is_retryable(),
+            _ => false,
+        }
     }
 
     pub fn retry_delay_ms(&self) -> u64 {
         match self {
             Error::LLMTimeout { .. } => 1000,
             Error::LLMRateLimit(_) => 5000,
-            Error::Nats(_) => 500,
+            Error::Nats(_) | Error::NatsClient { .. } => 500,
+            Error::Scraping { kind, .. } => kind.retry_delay_ms(),
             _ => 0,
         }
     }
@@ -77,8 +129,56 @@ impl Error {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    fn scraping_error(kind: ScrapeErrorKind) -> Error {
+        Error::Scraping { url: "https://example.com".to_string(), kind }
+    }
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn test_scraping_error_retryability() {
+        assert!(scraping_error(ScrapeErrorKind::Timeout).is_retryable());
+        assert!(scraping_error(ScrapeErrorKind::HttpStatus(503)).is_retryable());
+        assert!(scraping_error(ScrapeErrorKind::HttpStatus(429)).is_retryable());
+        assert!(!scraping_error(ScrapeErrorKind::HttpStatus(404)).is_retryable());
+        assert!(!scraping_error(ScrapeErrorKind::InvalidUrl).is_retryable());
+        assert!(!scraping_error(ScrapeErrorKind::RobotsBlocked).is_retryable());
+        assert!(!scraping_error(ScrapeErrorKind::Parse).is_retryable());
+    }
+
+    #[test]
+    fn test_scraping_error_retry_delay() {
+        assert_eq!(scraping_error(ScrapeErrorKind::Timeout).retry_delay_ms(), 1000);
+        assert_eq!(scraping_error(ScrapeErrorKind::HttpStatus(429)).retry_delay_ms(), 5000);
+        assert_eq!(scraping_error(ScrapeErrorKind::InvalidUrl).retry_delay_ms(), 0);
+    }
+
+    #[test]
+    fn test_nats_error_keeps_source_chain() {
+        let cause = std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "connection refused");
+        let error = Error::nats(format!("Failed to connect to NATS: {}", cause), cause);
+
+        // Display matches the string-only variant
+        assert_eq!(error.to_string(), "NATS error: Failed to connect to NATS: connection refused");
+        assert!(error.is_retryable());
+
+        let mut chain = Vec::new();
+        let mut source = std::error::Error::source(&error);
+        while let Some(err) = source {
+            chain.push(err.to_string());
+            source = err.source();
+        }
+        assert_eq!(chain, vec!["connection refused".to_string()]);
+    }
+
+    #[test]
+    fn test_scraping_error_display() {
+        let error = scraping_error(ScrapeErrorKind::HttpStatus(404));
+        assert_eq!(error.to_string(), "Scraping https://example.com failed: HTTP 404");
+    }
 }
@@ -1,8 +1,10 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use crate::{Result, Error};
 #[cfg(any(feature = "llm-openai", feature = "llm-anthropic"))]
-use crate::http_client::{HttpClient, create_http_client, post_json};
+use crate::http_client::{HttpClient, HttpClientConfig, create_http_client, get_json, post_json};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LLMRequest {
@@ -10,6 +12,18 @@ pub struct LLMRequest {
     pub context: HashMap<String, serde_json::Value>,
     pub max_tokens: Option<u32>,
     pub temperature: Option<f32>,
+    /// Ask the provider for structured JSON output when it supports it
+    #[serde(default)]
+    pub json_mode: bool,
+    /// Functions the model may call instead of answering in free text
+    #[serde(default)]
+    pub tools: Vec<ToolSpec>,
+    /// Full chat history; when non-empty providers send this instead of `prompt` alone
+    #[serde(default)]
+    pub messages: Vec<ChatMessage>,
+    /// Instructions sent ahead of `prompt` when there is no chat history
+    #[serde(default)]
+    pub system_prompt: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -18,6 +32,83 @@ pub struct LLMResponse {
     pub usage: LLMUsage,
     pub provider: String,
     pub model: String,
+    #[serde(default)]
+    pub tool_calls: Vec<ToolCall>,
+}
+
+/// A function the model is allowed to call, described by a JSON schema
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolSpec {
+    pub name: String,
+    pub description: String,
+    pub parameters: serde_json::Value,
+}
+
+/// A function call requested by the model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatRole {
+    System,
+    User,
+    Assistant,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: ChatRole,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn new(role: ChatRole, content: impl Into<String>) -> Self {
+        Self { role, content: content.into() }
+    }
+}
+
+/// Chat history for a multi-turn dialogue, sent in full on every `LLMClient::chat` call
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LLMConversation {
+    pub messages: Vec<ChatMessage>,
+}
+
+impl LLMConversation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the system prompt, replacing any existing one; it always stays first in the history
+    pub fn system_prompt(&mut self, prompt: impl Into<String>) -> &mut Self {
+        let message = ChatMessage::new(ChatRole::System, prompt);
+        match self.messages.first_mut() {
+            Some(first) if first.role == ChatRole::System => *first = message,
+            _ => self.messages.insert(0, message),
+        }
+        self
+    }
+
+    pub fn push_user(&mut self, content: impl Into<String>) -> &mut Self {
+        self.messages.push(ChatMessage::new(ChatRole::User, content));
+        self
+    }
+
+    pub fn push_assistant(&mut self, content: impl Into<String>) -> &mut Self {
+        self.messages.push(ChatMessage::new(ChatRole::Assistant, content));
+        self
+    }
+
+    /// The most recent user message, if any
+    pub fn last_user_message(&self) -> Option<&str> {
+        self.messages.iter().rev()
+            .find(|m| m.role == ChatRole::User)
+            .map(|m| m.content.as_str())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -42,6 +133,11 @@ impl Default for LLMUsage {
 pub trait LLMProvider: Send + Sync {
     async fn complete(&self, request: LLMRequest) -> Result<LLMResponse>;
     fn provider_name(&self) -> &'static str;
+
+    /// Cheaply verify the provider is usable (credentials, reachability)
+    async fn health_check(&self) -> Result<()> {
+        Ok(())
+    }
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -49,11 +145,35 @@ pub trait LLMProvider: Send + Sync {
 pub trait LLMProvider {
     async fn complete(&self, request: LLMRequest) -> Result<LLMResponse>;
     fn provider_name(&self) -> &'static str;
+
+    /// Cheaply verify the provider is usable (credentials, reachability)
+    async fn health_check(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[async_trait::async_trait]
+pub trait EmbeddingProvider: Send + Sync {
+    async fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>>;
+    fn provider_name(&self) -> &'static str;
+}
+
+#[cfg(target_arch = "wasm32")]
+#[async_trait::async_trait(?Send)]
+pub trait EmbeddingProvider {
+    async fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>>;
+    fn provider_name(&self) -> &'static str;
 }
 
 pub struct LLMClient {
     provider: Box<dyn LLMProvider>,
     default_config: LLMConfig,
+    embedding_provider: Option<Box<dyn EmbeddingProvider>>,
+    rate_limiter: Option<Mutex<TokenBucket>>,
+    circuit_breaker: Option<Mutex<CircuitBreaker>>,
+    clock: Arc<dyn Clock>,
+    system_prompt: Option<String>,
 }
 
 impl std::fmt::Debug for LLMClient {
@@ -61,6 +181,9 @@ impl std::fmt::Debug for LLMClient {
         f.debug_struct("LLMClient")
             .field("provider", &self.provider.provider_name())
             .field("default_config", &self.default_config)
+            .field("embedding_provider", &self.embedding_provider.as_ref().map(|p| p.provider_name()))
+            .field("rate_limited", &self.rate_limiter.is_some())
+            .field("circuit_state", &self.circuit_state())
             .finish()
     }
 }
@@ -70,6 +193,10 @@ pub struct LLMConfig {
     pub max_tokens: u32,
     pub temperature: f32,
     pub timeout_seconds: u64,
+    /// Throttle outgoing requests to this rate; `None` disables client-side limiting
+    pub requests_per_minute: Option<u32>,
+    /// Model context window (prompt plus completion), in tokens
+    pub context_window_tokens: u32,
 }
 
 impl Default for LLMConfig {
@@ -78,34 +205,252 @@ impl Default for LLMConfig {
             max_tokens: 1000,
             temperature: 0.7,
             timeout_seconds: 30,
+            requests_per_minute: None,
+            context_window_tokens: 16_385,
+        }
+    }
+}
+
+/// Rough characters-per-token ratio for English text
+pub const CHARS_PER_TOKEN: usize = 4;
+/// Tokens kept free for the system prompt and instructions around the data
+const PROMPT_OVERHEAD_TOKENS: u32 = 200;
+
+impl LLMConfig {
+    /// Characters of data that fit in a prompt while leaving room for `max_tokens` of completion
+    pub fn prompt_char_budget(&self) -> usize {
+        let tokens = self.context_window_tokens
+            .saturating_sub(self.max_tokens)
+            .saturating_sub(PROMPT_OVERHEAD_TOKENS);
+        tokens as usize * CHARS_PER_TOKEN
+    }
+}
+
+/// Result of a map-reduce summarization: the final summary plus the per-chunk summaries it was built from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkedSummary {
+    pub summary: String,
+    /// Intermediate summaries, one per chunk, kept for debugging
+    pub chunk_summaries: Vec<String>,
+}
+
+// Split `items` into groups of at most `max_items` whose serialized size stays within `budget`;
+// a single oversized item still gets a group of its own
+fn budget_chunks<T: Serialize + Clone>(items: &[T], max_items: usize, budget: usize) -> Vec<Vec<T>> {
+    let mut chunks = Vec::new();
+    let mut current: Vec<T> = Vec::new();
+    let mut current_chars = 0;
+    for item in items {
+        let chars = serde_json::to_string(item).map(|s| s.len()).unwrap_or(0);
+        if !current.is_empty() && (current.len() >= max_items || current_chars + chars > budget) {
+            chunks.push(std::mem::take(&mut current));
+            current_chars = 0;
         }
+        current.push(item.clone());
+        current_chars += chars;
+    }
+    if !current.is_empty() {
+        chunks.push(current);
     }
+    chunks
 }
 
 impl LLMClient {
     pub fn new(provider: Box<dyn LLMProvider>, config: LLMConfig) -> Self {
+        let rate_limiter = config.requests_per_minute
+            .filter(|rpm| *rpm > 0)
+            .map(|rpm| Mutex::new(TokenBucket::per_minute(rpm)));
+
         Self {
             provider,
             default_config: config,
+            embedding_provider: None,
+            rate_limiter,
+            circuit_breaker: None,
+            clock: Arc::new(SystemClock::new()),
+            system_prompt: None,
+        }
+    }
+
+    /// Use `prompt` instead of the per-task default system prompt; `None` restores the defaults
+    pub fn with_system_prompt(mut self, prompt: Option<String>) -> Self {
+        self.system_prompt = prompt;
+        self
+    }
+
+    // The configured system prompt, or the default for the request's `task` context entry
+    fn system_prompt_for(&self, context: &HashMap<String, serde_json::Value>) -> String {
+        self.system_prompt.clone().unwrap_or_else(|| {
+            let task = context.get("task").and_then(|v| v.as_str()).unwrap_or("reasoning");
+            default_system_prompt(task).to_string()
+        })
+    }
+
+    /// Stop calling the provider after repeated retryable failures (see [`CircuitBreaker`])
+    pub fn with_circuit_breaker(mut self, config: CircuitBreakerConfig) -> Self {
+        self.circuit_breaker = Some(Mutex::new(CircuitBreaker::new(config)));
+        self
+    }
+
+    pub fn circuit_state(&self) -> Option<CircuitState> {
+        self.circuit_breaker.as_ref().map(|breaker| breaker.lock().unwrap().state())
+    }
+
+    /// Replace the clock used for rate limiting (tests drive time manually)
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.lock().unwrap().last_refill = clock.now();
+        }
+        self.clock = clock;
+        self
+    }
+
+    /// Send a request to the provider, checking the circuit breaker and waiting for a
+    /// rate-limit permit first
+    async fn send(&self, request: LLMRequest) -> Result<LLMResponse> {
+        if let Some(breaker) = &self.circuit_breaker {
+            breaker.lock().unwrap().try_acquire(self.clock.now())?;
+        }
+
+        if let Some(limiter) = &self.rate_limiter {
+            let wait = limiter.lock().unwrap().reserve(self.clock.now());
+            if !wait.is_zero() {
+                log::debug!("LLM rate limit reached, delaying request by {:?}", wait);
+                self.clock.sleep(wait).await;
+            }
+        }
+
+        let result = self.complete_with_timeout(request).await;
+
+        if let Some(breaker) = &self.circuit_breaker {
+            breaker.lock().unwrap().record(&result, self.clock.now());
+        }
+
+        result
+    }
+
+    /// Call the provider, giving up with `LLMTimeout` after `timeout_seconds` (0 disables the limit).
+    /// Inside a Lunatic process there is no timer to race, so agents enforce the limit on
+    /// the worker process running the request instead (see `supervisor::LlmDeadline`).
+    async fn complete_with_timeout(&self, request: LLMRequest) -> Result<LLMResponse> {
+        let timeout_seconds = self.default_config.timeout_seconds;
+        if timeout_seconds == 0 {
+            return self.provider.complete(request).await;
+        }
+
+        #[cfg(feature = "tokio")]
+        {
+            let timeout = Duration::from_secs(timeout_seconds);
+            tokio::time::timeout(timeout, self.provider.complete(request)).await
+                .unwrap_or(Err(Error::LLMTimeout { timeout: timeout_seconds }))
+        }
+
+        // No async timer without tokio, so a sleeping thread wakes the race instead
+        #[cfg(all(not(feature = "tokio"), not(target_arch = "wasm32")))]
+        {
+            use futures::future::{select, Either};
+
+            let timeout = Duration::from_secs(timeout_seconds);
+            let (expire, expired) = futures::channel::oneshot::channel::<()>();
+            std::thread::spawn(move || {
+                std::thread::sleep(timeout);
+                let _ = expire.send(());
+            });
+
+            let completion = self.provider.complete(request);
+            futures::pin_mut!(completion);
+            match select(completion, expired).await {
+                Either::Left((result, _)) => result,
+                Either::Right(_) => Err(Error::LLMTimeout { timeout: timeout_seconds }),
+            }
         }
+
+        #[cfg(all(not(feature = "tokio"), target_arch = "wasm32"))]
+        {
+            self.provider.complete(request).await
+        }
+    }
+
+    pub fn with_embedding_provider(mut self, embedding_provider: Box<dyn EmbeddingProvider>) -> Self {
+        self.embedding_provider = Some(embedding_provider);
+        self
     }
 
     pub fn provider_name(&self) -> &'static str {
         self.provider.provider_name()
     }
 
+    pub async fn health_check(&self) -> Result<()> {
+        self.provider.health_check().await
+    }
+
+    /// Embed texts with the configured embedding provider, one vector per input text
+    pub async fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        match &self.embedding_provider {
+            Some(provider) => provider.embed(texts).await,
+            None => Err(Error::LLMProvider("No embedding provider configured".to_string())),
+        }
+    }
+
     pub async fn reasoning_request(&self, prompt: &str, context: HashMap<String, serde_json::Value>) -> Result<String> {
+        self.complete_prompt(prompt, context, false).await
+    }
+
+    async fn complete_prompt(&self, prompt: &str, context: HashMap<String, serde_json::Value>, json_mode: bool) -> Result<String> {
         let request = LLMRequest {
             prompt: prompt.to_string(),
+            system_prompt: Some(self.system_prompt_for(&context)),
             context,
             max_tokens: Some(self.default_config.max_tokens),
             temperature: Some(self.default_config.temperature),
+            json_mode,
+            tools: Vec::new(),
+            messages: Vec::new(),
+        };
+
+        let response = self.send(request).await?;
+        Ok(response.content)
+    }
+
+    /// Send the whole conversation and append the assistant's reply to it
+    pub async fn chat(&self, conversation: &mut LLMConversation) -> Result<String> {
+        let prompt = conversation.last_user_message()
+            .ok_or_else(|| Error::Custom("Conversation has no user message to answer".to_string()))?
+            .to_string();
+
+        let request = LLMRequest {
+            prompt,
+            context: HashMap::new(),
+            max_tokens: Some(self.default_config.max_tokens),
+            temperature: Some(self.default_config.temperature),
+            json_mode: false,
+            tools: Vec::new(),
+            messages: conversation.messages.clone(),
+            system_prompt: None,
         };
 
-        let response = self.provider.complete(request).await?;
+        let response = self.send(request).await?;
+        conversation.push_assistant(response.content.clone());
         Ok(response.content)
     }
 
+    /// Send a prompt with a set of callable tools and return the full response,
+    /// including any `tool_calls` the model chose to make
+    pub async fn complete_with_tools(&self, prompt: &str, context: HashMap<String, serde_json::Value>, tools: Vec<ToolSpec>) -> Result<LLMResponse> {
+        let request = LLMRequest {
+            prompt: prompt.to_string(),
+            system_prompt: Some(self.system_prompt_for(&context)),
+            context,
+            max_tokens: Some(self.default_config.max_tokens),
+            temperature: Some(self.default_config.temperature),
+            json_mode: false,
+            tools,
+            messages: Vec::new(),
+        };
+
+        self.send(request).await
+    }
+
     pub async fn summarize_data(&self, data: Vec<serde_json::Value>) -> Result<String> {
         let context = HashMap::from([
             ("task".to_string(), serde_json::json!("summarization")),
@@ -121,6 +466,60 @@ impl LLMClient {
         self.reasoning_request(&prompt, context).await
     }
 
+    /// Map-reduce summarization for datasets too large for one prompt: summarize each chunk of
+    /// up to `chunk_size` items, then combine the chunk summaries into a final one
+    pub async fn summarize_data_chunked(&self, data: Vec<serde_json::Value>, chunk_size: usize) -> Result<ChunkedSummary> {
+        let budget = self.default_config.prompt_char_budget();
+        let mut chunk_summaries = Vec::new();
+        for chunk in budget_chunks(&data, chunk_size.max(1), budget) {
+            chunk_summaries.push(self.summarize_data(chunk).await?);
+        }
+
+        // Reduce in rounds so the combined summaries never overflow the prompt
+        let mut summaries = chunk_summaries.clone();
+        while summaries.len() > 1 {
+            let mut reduced = Vec::new();
+            for group in budget_chunks(&summaries, summaries.len(), budget) {
+                reduced.push(self.combine_summaries(&group).await?);
+            }
+            if reduced.len() == summaries.len() {
+                // Every summary fills the budget alone; pair them up so the rounds still converge
+                reduced = Vec::new();
+                for pair in summaries.chunks(2) {
+                    reduced.push(self.combine_summaries(pair).await?);
+                }
+            }
+            summaries = reduced;
+        }
+
+        Ok(ChunkedSummary {
+            summary: summaries.pop().unwrap_or_default(),
+            chunk_summaries,
+        })
+    }
+
+    async fn combine_summaries(&self, summaries: &[String]) -> Result<String> {
+        if let [single] = summaries {
+            return Ok(single.clone());
+        }
+        let context = HashMap::from([
+            ("task".to_string(), serde_json::json!("summarization")),
+            ("chunk_count".to_string(), serde_json::json!(summaries.len())),
+        ]);
+
+        let sections: Vec<String> = summaries.iter()
+            .enumerate()
+            .map(|(i, summary)| format!("Part {}:\n{}", i + 1, summary))
+            .collect();
+        let prompt = format!(
+            "Please summarize the following {} partial summaries of one dataset into a single comprehensive summary:\n\n{}\n\nHighlight the key insights and patterns across all parts.",
+            summaries.len(),
+            sections.join("\n\n")
+        );
+
+        self.reasoning_request(&prompt, context).await
+    }
+
     pub async fn plan_workflow(&self, task_description: &str, available_agents: Vec<String>) -> Result<Vec<WorkflowStep>> {
         let context = HashMap::from([
             ("task".to_string(), serde_json::json!("workflow_planning")),
@@ -129,16 +528,112 @@ impl LLMClient {
 
         let prompt = format!(
             "Given the task: '{}' and available agents: {:?}, create a detailed workflow plan. 
-            Respond with a JSON array of workflow steps, each containing: 
-            {{\"step_id\": \"string\", \"agent_type\": \"string\", \"action\": \"string\", \"inputs\": [\"string\"], \"outputs\": [\"string\"]}}",
+            Respond only with JSON: an object {{\"steps\": [...]}} whose steps each contain: 
+            {{\"step_id\": \"string\", \"agent_type\": \"string\", \"action\": \"string\", \"inputs\": [\"string\"], \"outputs\": [\"string\"], \"depends_on\": [\"step_id\"]}}",
             task_description, available_agents
         );
 
-        let response = self.reasoning_request(&prompt, context).await?;
-        let workflow_steps: Vec<WorkflowStep> = serde_json::from_str(&response)
-            .map_err(|e| Error::Custom(format!("Failed to parse workflow plan: {}", e)))?;
-        
-        Ok(workflow_steps)
+        let response = self.complete_prompt(&prompt, context, true).await?;
+        parse_workflow_steps(&response)
+    }
+}
+
+/// Built-in system prompt for a task (`summarization`, `workflow_planning`, anything else is reasoning)
+pub fn default_system_prompt(task: &str) -> &'static str {
+    match task {
+        "summarization" | "summarize" => "You are a professional data analyst specializing in web scraping analysis. Provide concise, actionable insights from the scraped web content.",
+        "workflow_planning" | "plan_workflow" => "You are a workflow planner for a team of distributed agents. Break the task into concrete steps, each assigned to one available agent.",
+        _ => "You are a careful analyst. Reason step by step and state your conclusion clearly.",
+    }
+}
+
+/// Parse a workflow plan out of raw LLM output.
+///
+/// Accepts a bare JSON array, a `{"steps": [...]}` object (JSON mode), output wrapped in
+/// ```json fences, or an array embedded in surrounding prose.
+pub fn parse_workflow_steps(content: &str) -> Result<Vec<WorkflowStep>> {
+    let stripped = strip_code_fences(content);
+
+    let candidate = match serde_json::from_str::<serde_json::Value>(stripped) {
+        Ok(serde_json::Value::Array(items)) => Some(serde_json::Value::Array(items)),
+        Ok(serde_json::Value::Object(map)) => map.get("steps")
+            .filter(|v| v.is_array())
+            .or_else(|| map.values().find(|v| v.is_array()))
+            .cloned(),
+        _ => None,
+    };
+
+    let candidate = match candidate {
+        Some(value) => value,
+        None => {
+            let array_text = extract_first_json_array(stripped)
+                .ok_or_else(|| Error::LLMResponseFormat(format!(
+                    "no JSON array found in workflow plan: {}", response_snippet(content))))?;
+            serde_json::from_str(array_text)
+                .map_err(|e| Error::LLMResponseFormat(format!(
+                    "invalid JSON in workflow plan ({}): {}", e, response_snippet(array_text))))?
+        }
+    };
+
+    serde_json::from_value(candidate)
+        .map_err(|e| Error::LLMResponseFormat(format!(
+            "workflow plan does not match WorkflowStep schema ({}): {}", e, response_snippet(content))))
+}
+
+fn strip_code_fences(content: &str) -> &str {
+    let trimmed = content.trim();
+    if let Some(start) = trimmed.find("```") {
+        let after_fence = &trimmed[start + 3..];
+        // Skip an optional language tag such as `json` on the opening fence line
+        let body_start = after_fence.find('\n').map(|i| i + 1).unwrap_or(0);
+        let body = &after_fence[body_start..];
+        if let Some(end) = body.find("```") {
+            return body[..end].trim();
+        }
+    }
+    trimmed
+}
+
+/// Find the first balanced `[...]` in `content`, ignoring brackets inside JSON strings
+fn extract_first_json_array(content: &str) -> Option<&str> {
+    let start = content.find('[')?;
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (offset, ch) in content[start..].char_indices() {
+        if in_string {
+            match ch {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match ch {
+            '"' => in_string = true,
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(&content[start..start + offset + 1]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn response_snippet(content: &str) -> String {
+    const MAX_SNIPPET_CHARS: usize = 200;
+    let snippet: String = content.chars().take(MAX_SNIPPET_CHARS).collect();
+    if content.chars().count() > MAX_SNIPPET_CHARS {
+        format!("{}...", snippet)
+    } else {
+        snippet
     }
 }
 
@@ -149,6 +644,70 @@ pub struct WorkflowStep {
     pub action: String,
     pub inputs: Vec<String>,
     pub outputs: Vec<String>,
+    /// Ids of steps that must finish before this one starts
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+}
+
+/// Check a plan's dependency graph and return step ids in an order that respects `depends_on`.
+/// Independent steps keep their original relative order. Fails on duplicate ids, dependencies
+/// on unknown steps, and cycles.
+pub fn validate_workflow(steps: &[WorkflowStep]) -> Result<Vec<String>> {
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    for (i, step) in steps.iter().enumerate() {
+        if index.insert(step.step_id.as_str(), i).is_some() {
+            return Err(Error::WorkflowValidation(format!("duplicate step id '{}'", step.step_id)));
+        }
+    }
+
+    let mut remaining_deps = vec![0usize; steps.len()];
+    let mut dependents: Vec<Vec<usize>> = vec![Vec::new(); steps.len()];
+    for (i, step) in steps.iter().enumerate() {
+        for dependency in &step.depends_on {
+            let &dep = index.get(dependency.as_str()).ok_or_else(|| Error::WorkflowValidation(format!(
+                "step '{}' depends on unknown step '{}'", step.step_id, dependency
+            )))?;
+            remaining_deps[i] += 1;
+            dependents[dep].push(i);
+        }
+    }
+
+    // Kahn's algorithm, always picking the earliest ready step
+    let mut ready: std::collections::BTreeSet<usize> = (0..steps.len())
+        .filter(|&i| remaining_deps[i] == 0)
+        .collect();
+    let mut order = Vec::with_capacity(steps.len());
+    while let Some(i) = ready.pop_first() {
+        order.push(steps[i].step_id.clone());
+        for &next in &dependents[i] {
+            remaining_deps[next] -= 1;
+            if remaining_deps[next] == 0 {
+                ready.insert(next);
+            }
+        }
+    }
+
+    if order.len() < steps.len() {
+        let cyclic: Vec<&str> = steps.iter().enumerate()
+            .filter(|(i, _)| remaining_deps[*i] > 0)
+            .map(|(_, step)| step.step_id.as_str())
+            .collect();
+        return Err(Error::WorkflowValidation(format!("dependency cycle among steps: {}", cyclic.join(", "))));
+    }
+
+    Ok(order)
+}
+
+/// Default base URL for the public OpenAI API
+pub const OPENAI_DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+
+/// How the API key is presented to an OpenAI-compatible endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenAIAuthStyle {
+    /// `Authorization: Bearer <key>` (OpenAI, OpenRouter, LiteLLM, ...)
+    Bearer,
+    /// `api-key: <key>` header used by Azure OpenAI
+    ApiKeyHeader,
 }
 
 // OpenAI Provider Implementation
@@ -157,47 +716,125 @@ pub struct OpenAIProvider {
     http_client: Box<dyn HttpClient>,
     api_key: String,
     model: String,
+    base_url: String,
+    auth_style: OpenAIAuthStyle,
+    api_version: Option<String>,
+    name: &'static str,
 }
 
 #[cfg(feature = "llm-openai")]
 impl OpenAIProvider {
     pub fn new(api_key: String, model: String) -> Self {
         Self {
-            http_client: create_http_client(),
+            http_client: create_http_client(HttpClientConfig::default()),
             api_key,
             model,
+            base_url: OPENAI_DEFAULT_BASE_URL.to_string(),
+            auth_style: OpenAIAuthStyle::Bearer,
+            api_version: None,
+            name: "openai",
+        }
+    }
+
+    /// Report a different provider name, e.g. "ollama" for a local OpenAI-compatible server
+    pub fn with_provider_name(mut self, name: &'static str) -> Self {
+        self.name = name;
+        self
+    }
+
+    pub fn with_http_config(mut self, config: HttpClientConfig) -> Self {
+        self.http_client = create_http_client(config);
+        self
+    }
+
+    /// Point the provider at an OpenAI-compatible endpoint (Azure, OpenRouter, local proxy)
+    pub fn with_base_url(mut self, base_url: String) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn with_auth_style(mut self, auth_style: OpenAIAuthStyle) -> Self {
+        self.auth_style = auth_style;
+        self
+    }
+
+    /// Append an `api-version` query parameter to requests (required by Azure OpenAI)
+    pub fn with_api_version(mut self, api_version: String) -> Self {
+        self.api_version = Some(api_version);
+        self
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    fn chat_completions_url(&self) -> String {
+        match &self.api_version {
+            Some(version) => format!("{}/chat/completions?api-version={}", self.base_url, version),
+            None => format!("{}/chat/completions", self.base_url),
         }
     }
+
+    // Listing models costs no tokens but still needs a valid key
+    async fn check_models_endpoint(&self) -> Result<()> {
+        let url = match &self.api_version {
+            Some(version) => format!("{}/models?api-version={}", self.base_url, version),
+            None => format!("{}/models", self.base_url),
+        };
+        get_json(self.http_client.as_ref(), &url, self.auth_headers(), HEALTH_CHECK_TIMEOUT).await
+            .map(|_| ())
+            .map_err(|e| Error::LLMProvider(format!("OpenAI health check failed: {}", e)))
+    }
+
+    fn auth_headers(&self) -> HashMap<String, String> {
+        let mut headers = HashMap::new();
+        match self.auth_style {
+            OpenAIAuthStyle::Bearer => {
+                headers.insert("Authorization".to_string(), format!("Bearer {}", self.api_key));
+            }
+            OpenAIAuthStyle::ApiKeyHeader => {
+                headers.insert("api-key".to_string(), self.api_key.clone());
+            }
+        }
+        headers
+    }
 }
 
 #[cfg(all(feature = "llm-openai", not(target_arch = "wasm32")))]
 #[async_trait::async_trait]
 impl LLMProvider for OpenAIProvider {
     async fn complete(&self, request: LLMRequest) -> Result<LLMResponse> {
-        let openai_request = serde_json::json!({
+        let mut openai_request = serde_json::json!({
             "model": self.model,
-            "messages": [{
-                "role": "user",
-                "content": request.prompt
-            }],
+            "messages": openai_messages(&request, &request.prompt),
             "max_tokens": request.max_tokens.unwrap_or(1000),
             "temperature": request.temperature.unwrap_or(0.7)
         });
+        if request.json_mode {
+            openai_request["response_format"] = serde_json::json!({"type": "json_object"});
+        }
+        if !request.tools.is_empty() {
+            openai_request["tools"] = openai_tools(&request.tools);
+        }
 
-        let mut headers = HashMap::new();
-        headers.insert("Authorization".to_string(), format!("Bearer {}", self.api_key));
+        let headers = self.auth_headers();
 
         let response_data = post_json(
             self.http_client.as_ref(),
-            "https://api.openai.com/v1/chat/completions",
+            &self.chat_completions_url(),
             &openai_request,
             headers,
         ).await?;
 
-        let content = response_data["choices"][0]["message"]["content"]
-            .as_str()
-            .ok_or_else(|| Error::Custom("No content in OpenAI response".to_string()))?
-            .to_string();
+        let message = &response_data["choices"][0]["message"];
+        let tool_calls = parse_openai_tool_calls(message);
+
+        // Content is null when the model answers with tool calls only
+        let content = match message["content"].as_str() {
+            Some(content) => content.to_string(),
+            None if !tool_calls.is_empty() => String::new(),
+            None => return Err(Error::Custom("No content in OpenAI response".to_string())),
+        };
 
         let usage = response_data["usage"].clone();
 
@@ -205,13 +842,18 @@ impl LLMProvider for OpenAIProvider {
             content,
             usage: serde_json::from_value(usage)
                 .unwrap_or_default(),
-            provider: "openai".to_string(),
+            provider: self.name.to_string(),
             model: self.model.clone(),
+            tool_calls,
         })
     }
 
     fn provider_name(&self) -> &'static str {
-        "openai"
+        self.name
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        self.check_models_endpoint().await
     }
 }
 
@@ -219,68 +861,299 @@ impl LLMProvider for OpenAIProvider {
 #[async_trait::async_trait(?Send)]
 impl LLMProvider for OpenAIProvider {
     async fn complete(&self, request: LLMRequest) -> Result<LLMResponse> {
-        let openai_request = serde_json::json!({
+        let user_content = format!("{}\n\nContext: {:?}", request.prompt, request.context);
+        let mut openai_request = serde_json::json!({
             "model": self.model,
-            "messages": [{
-                "role": "user",
-                "content": format!("{}\n\nContext: {:?}", request.prompt, request.context)
-            }],
+            "messages": openai_messages(&request, &user_content),
             "max_tokens": request.max_tokens.unwrap_or(1000),
             "temperature": request.temperature.unwrap_or(0.7)
         });
+        if request.json_mode {
+            openai_request["response_format"] = serde_json::json!({"type": "json_object"});
+        }
+        if !request.tools.is_empty() {
+            openai_request["tools"] = openai_tools(&request.tools);
+        }
 
-        let mut headers = HashMap::new();
-        headers.insert("Authorization".to_string(), format!("Bearer {}", self.api_key));
+        let headers = self.auth_headers();
 
         let openai_response = post_json(
             self.http_client.as_ref(),
-            "https://api.openai.com/v1/chat/completions",
+            &self.chat_completions_url(),
             &openai_request,
             headers,
         ).await?;
 
-        let content = openai_response["choices"][0]["message"]["content"]
-            .as_str()
-            .ok_or_else(|| Error::Custom("Invalid OpenAI response format".to_string()))?;
+        let message = &openai_response["choices"][0]["message"];
+        let tool_calls = parse_openai_tool_calls(message);
+
+        let content = match message["content"].as_str() {
+            Some(content) => content,
+            None if !tool_calls.is_empty() => "",
+            None => return Err(Error::Custom("Invalid OpenAI response format".to_string())),
+        };
 
         Ok(LLMResponse {
             content: content.to_string(),
             usage: LLMUsage::default(),
-            provider: "openai".to_string(),
+            provider: self.name.to_string(),
             model: self.model.clone(),
+            tool_calls,
         })
     }
 
     fn provider_name(&self) -> &'static str {
-        "openai"
+        self.name
     }
-}
 
-// Mock provider for testing and when no LLM features are enabled
-pub struct MockLLMProvider {
-    pub responses: HashMap<String, String>,
+    async fn health_check(&self) -> Result<()> {
+        self.check_models_endpoint().await
+    }
 }
 
-impl MockLLMProvider {
-    pub fn new() -> Self {
-        let mut responses = HashMap::new();
-        responses.insert("summarize".to_string(), "Mock summary: Data analyzed successfully.".to_string());
-        responses.insert("plan_workflow".to_string(), r#"[{"step_id": "1", "agent_type": "mock", "action": "process", "inputs": ["data"], "outputs": ["result"]}]"#.to_string());
-        responses.insert("reason".to_string(), "Mock reasoning: Task completed with mock logic.".to_string());
-        
-        Self { responses }
-    }
+// OpenAI Embeddings Provider Implementation
+#[cfg(feature = "llm-openai")]
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
 
-    pub fn with_response(mut self, key: &str, response: &str) -> Self {
-        self.responses.insert(key.to_string(), response.to_string());
-        self
-    }
-}
+#[cfg(feature = "llm-openai")]
+pub const OPENAI_DEFAULT_EMBEDDING_MODEL: &str = "text-embedding-3-small";
+
+#[cfg(feature = "llm-openai")]
+pub struct OpenAIEmbeddingProvider {
+    http_client: Box<dyn HttpClient>,
+    api_key: String,
+    model: String,
+    base_url: String,
+}
+
+#[cfg(feature = "llm-openai")]
+impl OpenAIEmbeddingProvider {
+    pub fn new(api_key: String) -> Self {
+        Self {
+            http_client: create_http_client(HttpClientConfig::default()),
+            api_key,
+            model: OPENAI_DEFAULT_EMBEDDING_MODEL.to_string(),
+            base_url: OPENAI_DEFAULT_BASE_URL.to_string(),
+        }
+    }
+
+    pub fn with_http_config(mut self, config: HttpClientConfig) -> Self {
+        self.http_client = create_http_client(config);
+        self
+    }
+
+    pub fn with_model(mut self, model: String) -> Self {
+        self.model = model;
+        self
+    }
+
+    pub fn with_base_url(mut self, base_url: String) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    async fn request_embeddings(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        let expected = texts.len();
+        let embedding_request = serde_json::json!({
+            "model": self.model,
+            "input": texts,
+        });
+
+        let mut headers = HashMap::new();
+        headers.insert("Authorization".to_string(), format!("Bearer {}", self.api_key));
+
+        let response_data = post_json(
+            self.http_client.as_ref(),
+            &format!("{}/embeddings", self.base_url),
+            &embedding_request,
+            headers,
+        ).await?;
+
+        let data = response_data["data"].as_array()
+            .ok_or_else(|| Error::LLMResponseFormat("No data in OpenAI embeddings response".to_string()))?;
+
+        // Results carry an explicit index; sort so vectors line up with the input texts
+        let mut indexed: Vec<(usize, Vec<f32>)> = Vec::with_capacity(data.len());
+        for (position, item) in data.iter().enumerate() {
+            let index = item["index"].as_u64().map(|i| i as usize).unwrap_or(position);
+            let embedding: Vec<f32> = serde_json::from_value(item["embedding"].clone())
+                .map_err(|e| Error::LLMResponseFormat(format!("Invalid embedding vector: {}", e)))?;
+            indexed.push((index, embedding));
+        }
+        indexed.sort_by_key(|(index, _)| *index);
+
+        if indexed.len() != expected {
+            return Err(Error::LLMResponseFormat(format!(
+                "Expected {} embeddings, received {}", expected, indexed.len())));
+        }
+
+        Ok(indexed.into_iter().map(|(_, embedding)| embedding).collect())
+    }
+}
+
+#[cfg(all(feature = "llm-openai", not(target_arch = "wasm32")))]
+#[async_trait::async_trait]
+impl EmbeddingProvider for OpenAIEmbeddingProvider {
+    async fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        self.request_embeddings(texts).await
+    }
+
+    fn provider_name(&self) -> &'static str {
+        "openai"
+    }
+}
+
+#[cfg(all(feature = "llm-openai", target_arch = "wasm32"))]
+#[async_trait::async_trait(?Send)]
+impl EmbeddingProvider for OpenAIEmbeddingProvider {
+    async fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        self.request_embeddings(texts).await
+    }
+
+    fn provider_name(&self) -> &'static str {
+        "openai"
+    }
+}
+
+/// Convert tool specs into the OpenAI `tools` request format
+/// Chat history in OpenAI's `messages` format. A request without history becomes its
+/// system prompt (if any) followed by `user_content`.
+#[cfg(any(feature = "llm-openai", test))]
+fn openai_messages(request: &LLMRequest, user_content: &str) -> serde_json::Value {
+    if !request.messages.is_empty() {
+        return serde_json::json!(request.messages);
+    }
+
+    let mut messages = Vec::new();
+    if let Some(system_prompt) = &request.system_prompt {
+        messages.push(ChatMessage::new(ChatRole::System, system_prompt.clone()));
+    }
+    messages.push(ChatMessage::new(ChatRole::User, user_content));
+    serde_json::json!(messages)
+}
+
+#[cfg(any(feature = "llm-openai", test))]
+fn openai_tools(tools: &[ToolSpec]) -> serde_json::Value {
+    serde_json::Value::Array(tools.iter().map(|tool| serde_json::json!({
+        "type": "function",
+        "function": {
+            "name": tool.name,
+            "description": tool.description,
+            "parameters": tool.parameters,
+        }
+    })).collect())
+}
+
+/// Extract `message.tool_calls` from an OpenAI chat completion message.
+/// Arguments arrive as a JSON-encoded string; unparseable arguments are kept as a string value.
+#[cfg(any(feature = "llm-openai", test))]
+fn parse_openai_tool_calls(message: &serde_json::Value) -> Vec<ToolCall> {
+    message["tool_calls"].as_array()
+        .map(|calls| calls.iter().filter_map(|call| {
+            let function = call.get("function")?;
+            let name = function.get("name")?.as_str()?.to_string();
+            let arguments = match function.get("arguments") {
+                Some(serde_json::Value::String(raw)) => serde_json::from_str(raw)
+                    .unwrap_or_else(|_| serde_json::Value::String(raw.clone())),
+                Some(other) => other.clone(),
+                None => serde_json::json!({}),
+            };
+            Some(ToolCall {
+                id: call.get("id").and_then(|v| v.as_str()).unwrap_or_default().to_string(),
+                name,
+                arguments,
+            })
+        }).collect())
+        .unwrap_or_default()
+}
+
+// Mock provider for testing and when no LLM features are enabled
+pub struct MockLLMProvider {
+    pub responses: HashMap<String, String>,
+    /// Tool call returned whenever a request offers tools
+    pub tool_call: Option<ToolCall>,
+    requests: Arc<Mutex<Vec<LLMRequest>>>,
+    matcher: Option<fn(&LLMRequest) -> Option<String>>,
+    latency: Option<Duration>,
+    failure_after: Option<usize>,
+}
+
+impl MockLLMProvider {
+    pub fn new() -> Self {
+        let mut responses = HashMap::new();
+        responses.insert("summarize".to_string(), "Mock summary: Data analyzed successfully.".to_string());
+        responses.insert("plan_workflow".to_string(), r#"[{"step_id": "1", "agent_type": "mock", "action": "process", "inputs": ["data"], "outputs": ["result"]}]"#.to_string());
+        responses.insert("reason".to_string(), "Mock reasoning: Task completed with mock logic.".to_string());
+        
+        Self {
+            responses,
+            tool_call: None,
+            requests: Arc::new(Mutex::new(Vec::new())),
+            matcher: None,
+            latency: None,
+            failure_after: None,
+        }
+    }
+
+    /// Every request this provider has received, shared so tests can inspect it after boxing
+    pub fn request_log(&self) -> Arc<Mutex<Vec<LLMRequest>>> {
+        Arc::clone(&self.requests)
+    }
+
+    pub fn with_response(mut self, key: &str, response: &str) -> Self {
+        self.responses.insert(key.to_string(), response.to_string());
+        self
+    }
+
+    pub fn with_tool_call(mut self, tool_call: ToolCall) -> Self {
+        self.tool_call = Some(tool_call);
+        self
+    }
+
+    /// Route requests to responses with custom logic; `None` falls back to the keyword match
+    pub fn with_matcher(mut self, matcher: fn(&LLMRequest) -> Option<String>) -> Self {
+        self.matcher = Some(matcher);
+        self
+    }
+
+    /// Delay every response, to simulate a slow provider. Without the `tokio` feature
+    /// the delay blocks the caller (see `Clock::sleep`).
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = Some(latency);
+        self
+    }
+
+    /// Succeed for the first `calls` requests, then fail every request with `LLMTimeout`
+    pub fn with_failure_after(mut self, calls: usize) -> Self {
+        self.failure_after = Some(calls);
+        self
+    }
+
+    /// Log the request, apply the configured latency, and fail once past `failure_after`
+    async fn record_call(&self, request: &LLMRequest) -> Result<()> {
+        let calls = {
+            let mut requests = self.requests.lock().unwrap();
+            requests.push(request.clone());
+            requests.len()
+        };
+
+        if let Some(latency) = self.latency {
+            SystemClock::new().sleep(latency).await;
+        }
+
+        match self.failure_after {
+            Some(limit) if calls > limit => Err(Error::LLMTimeout {
+                timeout: self.latency.map_or(0, |latency| latency.as_secs()),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn content_for(&self, request: &LLMRequest) -> String {
+        if let Some(content) = self.matcher.and_then(|matcher| matcher(request)) {
+            return content;
+        }
 
-#[cfg(not(target_arch = "wasm32"))]
-#[async_trait::async_trait]
-impl LLMProvider for MockLLMProvider {
-    async fn complete(&self, request: LLMRequest) -> Result<LLMResponse> {
         // Determine response based on prompt content
         let response_key = if request.prompt.contains("summarize") || request.context.get("task").and_then(|v| v.as_str()) == Some("summarization") {
             "summarize"
@@ -290,12 +1163,32 @@ impl LLMProvider for MockLLMProvider {
             "reason"
         };
 
-        let content = self.responses.get(response_key)
-            .unwrap_or(&"Mock response: Task processed.".to_string())
-            .clone();
+        self.responses.get(response_key)
+            .cloned()
+            .unwrap_or_else(|| "Mock response: Task processed.".to_string())
+    }
+
+    /// The configured tool call, if the request offered a tool with a matching name
+    fn tool_calls_for(&self, request: &LLMRequest) -> Vec<ToolCall> {
+        match &self.tool_call {
+            Some(call) if request.tools.iter().any(|tool| tool.name == call.name) => vec![call.clone()],
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[async_trait::async_trait]
+impl LLMProvider for MockLLMProvider {
+    async fn complete(&self, request: LLMRequest) -> Result<LLMResponse> {
+        self.record_call(&request).await?;
+
+        let content = self.content_for(&request);
+        let tool_calls = self.tool_calls_for(&request);
 
         Ok(LLMResponse {
             content,
+            tool_calls,
             usage: LLMUsage {
                 prompt_tokens: 10,
                 completion_tokens: 20,
@@ -315,74 +1208,644 @@ impl LLMProvider for MockLLMProvider {
 #[async_trait::async_trait(?Send)]
 impl LLMProvider for MockLLMProvider {
     async fn complete(&self, request: LLMRequest) -> Result<LLMResponse> {
-        // Determine response based on prompt content
-        let response_key = if request.prompt.contains("summarize") || request.context.get("task").and_then(|v| v.as_str()) == Some("summarization") {
-            "summarize"
-        } else if request.prompt.contains("workflow") || request.context.get("task").and_then(|v| v.as_str()) == Some("workflow_planning") {
-            "plan_workflow"  
+        self.record_call(&request).await?;
+
+        let content = self.content_for(&request);
+        let tool_calls = self.tool_calls_for(&request);
+
+        Ok(LLMResponse {
+            content,
+            tool_calls,
+            usage: LLMUsage::default(),
+            provider: "mock".to_string(),
+            model: "mock-model".to_string(),
+        })
+    }
+
+    fn provider_name(&self) -> &'static str {
+        "mock"
+    }
+}
+
+/// Tries each provider in order, moving on when one fails with a retryable error,
+/// so a single provider outage doesn't stop the pipeline
+pub struct FallbackProvider {
+    providers: Vec<Box<dyn LLMProvider>>,
+    name: &'static str,
+}
+
+impl FallbackProvider {
+    pub fn new(providers: Vec<Box<dyn LLMProvider>>) -> Self {
+        let chain = providers.iter()
+            .map(|provider| provider.provider_name())
+            .collect::<Vec<_>>()
+            .join("→");
+        // `provider_name` hands out a `&'static str`; chains are built once per client
+        let name = Box::leak(chain.into_boxed_str());
+        Self { providers, name }
+    }
+
+    async fn complete_with_fallback(&self, request: LLMRequest) -> Result<LLMResponse> {
+        let mut failures = Vec::new();
+
+        for provider in &self.providers {
+            match provider.complete(request.clone()).await {
+                Ok(response) => return Ok(response),
+                Err(error) if error.is_retryable() => {
+                    log::warn!("LLM provider {} failed, falling back: {}", provider.provider_name(), error);
+                    failures.push(format!("{}: {}", provider.provider_name(), error));
+                }
+                Err(error) => return Err(error),
+            }
+        }
+
+        if failures.is_empty() {
+            return Err(Error::LLMProvider("fallback chain has no providers".to_string()));
+        }
+        Err(Error::LLMProvider(format!("all providers failed ({})", failures.join("; "))))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[async_trait::async_trait]
+impl LLMProvider for FallbackProvider {
+    async fn complete(&self, request: LLMRequest) -> Result<LLMResponse> {
+        self.complete_with_fallback(request).await
+    }
+
+    fn provider_name(&self) -> &'static str {
+        self.name
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+#[async_trait::async_trait(?Send)]
+impl LLMProvider for FallbackProvider {
+    async fn complete(&self, request: LLMRequest) -> Result<LLMResponse> {
+        self.complete_with_fallback(request).await
+    }
+
+    fn provider_name(&self) -> &'static str {
+        self.name
+    }
+}
+
+/// Mock embedding provider producing deterministic, unit-length pseudo-vectors.
+/// Identical texts always map to identical vectors, so similarity code can be tested offline.
+pub struct MockEmbeddingProvider {
+    pub dimensions: usize,
+}
+
+impl MockEmbeddingProvider {
+    pub fn new(dimensions: usize) -> Self {
+        Self { dimensions }
+    }
+
+    fn pseudo_vector(&self, text: &str) -> Vec<f32> {
+        // FNV-1a seeded per dimension keeps the output stable across builds and platforms
+        let mut vector: Vec<f32> = (0..self.dimensions as u64).map(|dimension| {
+            let mut hash: u64 = 0xcbf29ce484222325 ^ dimension;
+            for byte in text.as_bytes() {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+            (hash % 2001) as f32 / 1000.0 - 1.0
+        }).collect();
+
+        let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            vector.iter_mut().for_each(|v| *v /= norm);
+        }
+        vector
+    }
+}
+
+impl Default for MockEmbeddingProvider {
+    fn default() -> Self {
+        Self::new(64)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[async_trait::async_trait]
+impl EmbeddingProvider for MockEmbeddingProvider {
+    async fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        Ok(texts.iter().map(|text| self.pseudo_vector(text)).collect())
+    }
+
+    fn provider_name(&self) -> &'static str {
+        "mock"
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+#[async_trait::async_trait(?Send)]
+impl EmbeddingProvider for MockEmbeddingProvider {
+    async fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        Ok(texts.iter().map(|text| self.pseudo_vector(text)).collect())
+    }
+
+    fn provider_name(&self) -> &'static str {
+        "mock"
+    }
+}
+
+/// Cosine similarity between two vectors; 0.0 when lengths differ or either vector is zero
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|v| v * v).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
+
+// Factory function for creating LLM clients
+pub fn create_llm_client() -> Result<LLMClient> {
+    let config = LLMConfig::default();
+
+    #[cfg(feature = "llm-openai")]
+    {
+        if let Ok(api_key) = std::env::var("OPENAI_API_KEY") {
+            let model = std::env::var("LLM_MODEL").unwrap_or_else(|_| "gpt-4".to_string());
+            let http_config = HttpClientConfig::with_timeout_secs(config.timeout_seconds);
+            let mut provider = OpenAIProvider::new(api_key, model)
+                .with_http_config(http_config.clone());
+
+            if let Ok(base_url) = std::env::var("OPENAI_BASE_URL") {
+                if !base_url.is_empty() {
+                    log::info!("Using OpenAI-compatible endpoint: {}", base_url);
+                    provider = provider.with_base_url(base_url);
+                }
+            }
+            if let Ok(api_version) = std::env::var("OPENAI_API_VERSION") {
+                provider = provider.with_api_version(api_version);
+            }
+            if std::env::var("OPENAI_AUTH_STYLE").map(|s| s.eq_ignore_ascii_case("api-key")).unwrap_or(false) {
+                provider = provider.with_auth_style(OpenAIAuthStyle::ApiKeyHeader);
+            }
+
+            let mut embedding_provider = OpenAIEmbeddingProvider::new(provider.api_key.clone())
+                .with_http_config(http_config);
+            if let Ok(embedding_model) = std::env::var("OPENAI_EMBEDDING_MODEL") {
+                embedding_provider = embedding_provider.with_model(embedding_model);
+            }
+            embedding_provider = embedding_provider.with_base_url(provider.base_url().to_string());
+
+            return Ok(LLMClient::new(Box::new(provider), config)
+                .with_embedding_provider(Box::new(embedding_provider)));
+        }
+    }
+
+    // Fall back to mock provider for development and testing
+    log::info!("Using mock LLM provider - configure OPENAI_API_KEY and enable llm-openai feature for real LLM integration");
+    Ok(mock_llm_client(config))
+}
+
+/// Default endpoint of a local Ollama server's OpenAI-compatible API
+pub const OLLAMA_DEFAULT_BASE_URL: &str = "http://localhost:11434/v1";
+
+/// Provider for a single agent, overriding the env-based choice in [`create_llm_client`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LLMProviderSpec {
+    /// OpenAI or a compatible endpoint; the API key still comes from `OPENAI_API_KEY`
+    #[serde(rename = "openai")]
+    OpenAI {
+        model: String,
+        #[serde(default)]
+        base_url: Option<String>,
+    },
+    /// Local Ollama server; no API key needed
+    Ollama {
+        model: String,
+        #[serde(default)]
+        base_url: Option<String>,
+    },
+    /// Canned responses; `latency_ms` simulates a slow provider
+    Mock {
+        #[serde(default)]
+        latency_ms: Option<u64>,
+    },
+}
+
+/// Build a client for an explicit provider spec
+pub fn create_llm_client_from_spec(spec: &LLMProviderSpec) -> Result<LLMClient> {
+    let config = LLMConfig::default();
+
+    match spec {
+        LLMProviderSpec::Mock { latency_ms } => {
+            let mut provider = MockLLMProvider::new();
+            if let Some(latency_ms) = latency_ms {
+                provider = provider.with_latency(Duration::from_millis(*latency_ms));
+            }
+            Ok(mock_llm_client_with(provider, config))
+        }
+        #[cfg(feature = "llm-openai")]
+        LLMProviderSpec::OpenAI { model, base_url } => {
+            let api_key = std::env::var("OPENAI_API_KEY")
+                .map_err(|_| Error::LLMProvider("OPENAI_API_KEY is required for the openai provider".to_string()))?;
+            let http_config = HttpClientConfig::with_timeout_secs(config.timeout_seconds);
+            let mut provider = OpenAIProvider::new(api_key, model.clone())
+                .with_http_config(http_config.clone());
+            if let Some(base_url) = base_url {
+                provider = provider.with_base_url(base_url.clone());
+            }
+
+            let embedding_provider = OpenAIEmbeddingProvider::new(provider.api_key.clone())
+                .with_http_config(http_config)
+                .with_base_url(provider.base_url().to_string());
+
+            Ok(LLMClient::new(Box::new(provider), config)
+                .with_embedding_provider(Box::new(embedding_provider)))
+        }
+        #[cfg(feature = "llm-openai")]
+        LLMProviderSpec::Ollama { model, base_url } => {
+            let provider = OpenAIProvider::new("ollama".to_string(), model.clone())
+                .with_http_config(HttpClientConfig::with_timeout_secs(config.timeout_seconds))
+                .with_base_url(base_url.clone().unwrap_or_else(|| OLLAMA_DEFAULT_BASE_URL.to_string()))
+                .with_provider_name("ollama");
+            Ok(LLMClient::new(Box::new(provider), config))
+        }
+        #[cfg(not(feature = "llm-openai"))]
+        other => Err(Error::LLMProvider(format!("{:?} requires the llm-openai feature", other))),
+    }
+}
+
+fn mock_llm_client(config: LLMConfig) -> LLMClient {
+    mock_llm_client_with(MockLLMProvider::new(), config)
+}
+
+fn mock_llm_client_with(provider: MockLLMProvider, config: LLMConfig) -> LLMClient {
+    LLMClient::new(Box::new(provider), config)
+        .with_embedding_provider(Box::new(MockEmbeddingProvider::default()))
+}
+
+/// Like [`create_llm_client`], but health-checks the selected provider first and falls back
+/// to the mock provider if the check fails (e.g. a revoked API key)
+pub async fn create_llm_client_checked() -> Result<LLMClient> {
+    Ok(fallback_if_unhealthy(create_llm_client()?).await)
+}
+
+/// Models `create_llm_client_strict` accepts unless `LLM_ALLOWED_MODELS` lists others
+pub const KNOWN_OPENAI_MODELS: &[&str] = &[
+    "gpt-4", "gpt-4-turbo", "gpt-4o", "gpt-4o-mini", "gpt-4.1", "gpt-4.1-mini", "gpt-3.5-turbo",
+];
+
+// Shortest string that can pass for an API key
+const MIN_API_KEY_LEN: usize = 20;
+
+/// OpenAI settings checked by [`create_llm_client_strict`] before a client is built
+#[derive(Debug, Clone)]
+pub struct StrictLLMConfig {
+    pub api_key: Option<String>,
+    pub model: String,
+    pub base_url: Option<String>,
+    pub allowed_models: Vec<String>,
+}
+
+impl StrictLLMConfig {
+    /// Read `OPENAI_API_KEY`, `LLM_MODEL`, `OPENAI_BASE_URL` and the comma-separated `LLM_ALLOWED_MODELS`
+    pub fn from_env() -> Self {
+        Self {
+            api_key: std::env::var("OPENAI_API_KEY").ok(),
+            model: std::env::var("LLM_MODEL").unwrap_or_else(|_| "gpt-4".to_string()),
+            base_url: std::env::var("OPENAI_BASE_URL").ok().filter(|url| !url.is_empty()),
+            allowed_models: std::env::var("LLM_ALLOWED_MODELS")
+                .map(|models| models.split(',').map(|m| m.trim().to_string()).filter(|m| !m.is_empty()).collect())
+                .unwrap_or_else(|_| KNOWN_OPENAI_MODELS.iter().map(|m| m.to_string()).collect()),
+        }
+    }
+
+    /// Check the key is present and looks like a key, and the model is on the allowed list.
+    /// Keys for custom endpoints (Azure, proxies) need not carry OpenAI's `sk-` prefix.
+    pub fn validate(&self) -> Result<()> {
+        let api_key = self.api_key.as_deref()
+            .ok_or_else(|| Error::LLMProvider("OPENAI_API_KEY is not set".to_string()))?;
+        if api_key.len() < MIN_API_KEY_LEN || api_key.chars().any(char::is_whitespace) {
+            return Err(Error::LLMProvider("OPENAI_API_KEY does not look like an API key".to_string()));
+        }
+        if self.base_url.is_none() && !api_key.starts_with("sk-") {
+            return Err(Error::LLMProvider("OPENAI_API_KEY should start with \"sk-\"".to_string()));
+        }
+        if !self.allowed_models.iter().any(|model| *model == self.model) {
+            return Err(Error::LLMProvider(format!(
+                "model {} is not in the allowed list ({})", self.model, self.allowed_models.join(", ")
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Like [`create_llm_client`], but fails with `Error::LLMProvider` instead of falling back
+/// to the mock provider when no valid OpenAI key and model are configured
+pub fn create_llm_client_strict() -> Result<LLMClient> {
+    create_llm_client_strict_from(&StrictLLMConfig::from_env())
+}
+
+/// [`create_llm_client_strict`] with explicit settings
+pub fn create_llm_client_strict_from(strict: &StrictLLMConfig) -> Result<LLMClient> {
+    strict.validate()?;
+
+    #[cfg(feature = "llm-openai")]
+    {
+        let config = LLMConfig::default();
+        let http_config = HttpClientConfig::with_timeout_secs(config.timeout_seconds);
+        let mut provider = OpenAIProvider::new(strict.api_key.clone().unwrap_or_default(), strict.model.clone())
+            .with_http_config(http_config.clone());
+        if let Some(base_url) = &strict.base_url {
+            provider = provider.with_base_url(base_url.clone());
+        }
+        log::info!("Using validated OpenAI model {}", strict.model);
+
+        let embedding_provider = OpenAIEmbeddingProvider::new(provider.api_key.clone())
+            .with_http_config(http_config)
+            .with_base_url(provider.base_url().to_string());
+        Ok(LLMClient::new(Box::new(provider), config)
+            .with_embedding_provider(Box::new(embedding_provider)))
+    }
+
+    #[cfg(not(feature = "llm-openai"))]
+    Err(Error::LLMProvider("a real LLM provider requires the llm-openai feature".to_string()))
+}
+
+async fn fallback_if_unhealthy(client: LLMClient) -> LLMClient {
+    match client.health_check().await {
+        Ok(()) => client,
+        Err(e) => {
+            log::warn!("LLM provider {} failed its health check, falling back to mock provider: {}", client.provider_name(), e);
+            mock_llm_client(client.default_config.clone())
+        }
+    }
+}
+
+/// Time source for client-side throttling
+pub trait Clock: Send + Sync {
+    /// Monotonic time since an arbitrary fixed point
+    fn now(&self) -> Duration;
+    /// Wait for `duration`. `SystemClock` only yields to the executor with the `tokio`
+    /// feature; without it the wait blocks synchronously inside this call.
+    fn sleep(&self, duration: Duration) -> futures::future::BoxFuture<'_, ()>;
+}
+
+pub struct SystemClock {
+    start: std::time::Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self { start: std::time::Instant::now() }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) -> futures::future::BoxFuture<'_, ()> {
+        #[cfg(feature = "tokio")]
+        {
+            Box::pin(tokio::time::sleep(duration))
+        }
+
+        // No async timer: block the calling process/thread now, before returning an
+        // already-complete future, so the wait is plainly synchronous
+        #[cfg(all(not(feature = "tokio"), target_arch = "wasm32"))]
+        {
+            lunatic::sleep(duration);
+            Box::pin(futures::future::ready(()))
+        }
+
+        #[cfg(all(not(feature = "tokio"), not(target_arch = "wasm32")))]
+        {
+            std::thread::sleep(duration);
+            Box::pin(futures::future::ready(()))
+        }
+    }
+}
+
+/// Token bucket that hands out one permit per request.
+///
+/// Reservations may drive the balance negative, so concurrent callers queue up
+/// behind each other instead of all waking at the same instant.
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    last_refill: Duration,
+}
+
+impl TokenBucket {
+    /// Evenly spaced requests with no burst allowance
+    fn per_minute(requests_per_minute: u32) -> Self {
+        Self {
+            capacity: 1.0,
+            tokens: 1.0,
+            refill_per_sec: requests_per_minute as f64 / 60.0,
+            last_refill: Duration::ZERO,
+        }
+    }
+
+    /// Take a permit at time `now` and return how long the caller must wait before using it
+    fn reserve(&mut self, now: Duration) -> Duration {
+        let elapsed = now.saturating_sub(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
         } else {
-            "reason"
-        };
+            Duration::from_secs_f64(-self.tokens / self.refill_per_sec)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive retryable failures before the circuit opens
+    pub failure_threshold: u32,
+    /// How long the circuit stays open before a probe request is let through
+    pub cooldown: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+/// Circuit breaker for LLM provider calls.
+///
+/// Closed: calls pass through, retryable failures are counted.
+/// Open: calls fail fast with `Error::LLMProvider("circuit open")` until the cooldown elapses.
+/// HalfOpen: a single probe call is allowed; success closes the circuit, failure re-opens it.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    state: CircuitState,
+    consecutive_failures: u32,
+    opened_at: Duration,
+    probe_in_flight: bool,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            state: CircuitState::Closed,
+            consecutive_failures: 0,
+            opened_at: Duration::ZERO,
+            probe_in_flight: false,
+        }
+    }
 
-        let content = self.responses.get(response_key)
-            .unwrap_or(&"Mock response: Task processed.".to_string())
-            .clone();
+    pub fn state(&self) -> CircuitState {
+        self.state
+    }
 
-        Ok(LLMResponse {
-            content,
-            usage: LLMUsage::default(),
-            provider: "mock".to_string(),
-            model: "mock-model".to_string(),
-        })
+    /// Check whether a call may proceed at time `now`
+    pub fn try_acquire(&mut self, now: Duration) -> Result<()> {
+        match self.state {
+            CircuitState::Closed => Ok(()),
+            CircuitState::Open => {
+                if now.saturating_sub(self.opened_at) >= self.config.cooldown {
+                    log::info!("LLM circuit breaker half-open, probing provider");
+                    self.state = CircuitState::HalfOpen;
+                    self.probe_in_flight = true;
+                    Ok(())
+                } else {
+                    Err(Error::LLMProvider("circuit open".to_string()))
+                }
+            }
+            CircuitState::HalfOpen => {
+                if self.probe_in_flight {
+                    Err(Error::LLMProvider("circuit open".to_string()))
+                } else {
+                    self.probe_in_flight = true;
+                    Ok(())
+                }
+            }
+        }
     }
 
-    fn provider_name(&self) -> &'static str {
-        "mock"
+    /// Record the outcome of a call. Non-retryable errors mean the provider answered,
+    /// so they count towards health rather than against it.
+    pub fn record<T>(&mut self, result: &Result<T>, now: Duration) {
+        match result {
+            Err(error) if error.is_retryable() => self.record_failure(now),
+            _ => self.record_success(),
+        }
+    }
+
+    fn record_success(&mut self) {
+        if self.state != CircuitState::Closed {
+            log::info!("LLM circuit breaker closed after successful probe");
+        }
+        self.state = CircuitState::Closed;
+        self.consecutive_failures = 0;
+        self.probe_in_flight = false;
+    }
+
+    fn record_failure(&mut self, now: Duration) {
+        self.consecutive_failures += 1;
+        self.probe_in_flight = false;
+
+        if self.state == CircuitState::HalfOpen || self.consecutive_failures >= self.config.failure_threshold {
+            if self.state != CircuitState::Open {
+                log::warn!("LLM circuit breaker opened after {} consecutive failures", self.consecutive_failures);
+            }
+            self.state = CircuitState::Open;
+            self.opened_at = now;
+        }
     }
 }
 
-// Factory function for creating LLM clients
-pub fn create_llm_client() -> Result<LLMClient> {
-    let config = LLMConfig::default();
+/// Retry limits and delays for [`retry_llm_operation`]. Delays left as `None` come from
+/// [`Error::retry_delay_ms`]; the default policy matches the built-in behaviour.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    pub timeout_delay_ms: Option<u64>,
+    pub ratelimit_delay_ms: Option<u64>,
+    pub nats_delay_ms: Option<u64>,
+    pub max_retries: u32,
+    /// Each retry waits this many times longer than the previous one
+    pub backoff_multiplier: f64,
+}
 
-    #[cfg(feature = "llm-openai")]
-    {
-        if let Ok(api_key) = std::env::var("OPENAI_API_KEY") {
-            let model = std::env::var("LLM_MODEL").unwrap_or_else(|_| "gpt-4".to_string());
-            let provider = Box::new(OpenAIProvider::new(api_key, model));
-            return Ok(LLMClient::new(provider, config));
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            timeout_delay_ms: None,
+            ratelimit_delay_ms: None,
+            nats_delay_ms: None,
+            max_retries: 3,
+            backoff_multiplier: 1.0,
         }
     }
+}
 
-    // Fall back to mock provider for development and testing
-    log::info!("Using mock LLM provider - configure OPENAI_API_KEY and enable llm-openai feature for real LLM integration");
-    let provider = Box::new(MockLLMProvider::new());
-    Ok(LLMClient::new(provider, config))
+impl RetryPolicy {
+    /// Delay before retrying after `error` on the given zero-based attempt
+    pub fn delay_ms(&self, error: &Error, attempt: u32) -> u64 {
+        let configured = match error {
+            Error::LLMTimeout { .. } => self.timeout_delay_ms,
+            Error::LLMRateLimit(_) => self.ratelimit_delay_ms,
+            Error::Nats(_) | Error::NatsClient { .. } => self.nats_delay_ms,
+            _ => None,
+        };
+        let base = configured.unwrap_or_else(|| error.retry_delay_ms());
+        (base as f64 * self.backoff_multiplier.max(0.0).powi(attempt as i32)) as u64
+    }
 }
 
 // Retry logic for LLM operations
 pub async fn retry_llm_operation<F, T, Fut>(
     operation: F,
-    max_retries: u32,
+    policy: &RetryPolicy,
 ) -> Result<T>
 where
     F: Fn() -> Fut,
     Fut: std::future::Future<Output = Result<T>>,
 {
+    let max_retries = policy.max_retries;
     let mut last_error = Error::Custom("No attempts made".to_string());
     
     for attempt in 0..=max_retries {
         match operation().await {
             Ok(result) => return Ok(result),
             Err(error) if attempt < max_retries && error.is_retryable() => {
-                let delay_ms = error.retry_delay_ms();
+                let delay_ms = policy.delay_ms(&error, attempt);
                 log::warn!("LLM operation attempt {} failed: {}. Retrying in {}ms", 
                           attempt + 1, error, delay_ms);
                 
-                #[cfg(feature = "nats")]
+                #[cfg(feature = "tokio")]
                 tokio::time::sleep(tokio::time::Duration::from_millis(delay_ms)).await;
                 
-                #[cfg(not(feature = "nats"))]
+                #[cfg(not(feature = "tokio"))]
                 {
                     // For WASM builds without tokio, we'll just continue without delay
                     log::debug!("Retrying immediately (no tokio sleep available)");
@@ -401,7 +1864,8 @@ where
 pub async fn safe_llm_operation<F, T, Fut>(
     operation_name: &str,
     agent_id: &str,
-    operation: F
+    operation: F,
+    policy: &RetryPolicy,
 ) -> Result<T>
 where
     F: Fn() -> Fut,
@@ -409,7 +1873,7 @@ where
 {
     let start_time = std::time::Instant::now();
     
-    match retry_llm_operation(operation, 3).await {
+    match retry_llm_operation(operation, policy).await {
         Ok(result) => {
             let duration = start_time.elapsed();
             log::info!("Agent {} completed {} in {:?}", agent_id, operation_name, duration);
@@ -427,6 +1891,37 @@ where
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_retry_policy_delays_override_defaults() {
+        let rate_limited = Error::LLMRateLimit("slow down".to_string());
+        let policy = RetryPolicy {
+            ratelimit_delay_ms: Some(30_000),
+            backoff_multiplier: 2.0,
+            ..RetryPolicy::default()
+        };
+
+        assert_eq!(policy.delay_ms(&rate_limited, 0), 30_000);
+        assert_eq!(policy.delay_ms(&rate_limited, 2), 120_000);
+        // Unset delays still come from the error
+        assert_eq!(policy.delay_ms(&Error::LLMTimeout { timeout: 30 }, 0), 1000);
+        assert_eq!(RetryPolicy::default().delay_ms(&rate_limited, 3), 5000);
+    }
+
+    #[tokio::test]
+    async fn test_retry_policy_limits_attempts() {
+        let attempts = std::sync::atomic::AtomicU32::new(0);
+        let counter = &attempts;
+        let policy = RetryPolicy { max_retries: 2, nats_delay_ms: Some(0), ..RetryPolicy::default() };
+
+        let result: Result<()> = safe_llm_operation("flaky", "retry_agent", || async move {
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Err(Error::Nats("connection reset".to_string()))
+        }, &policy).await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts.load(std::sync::atomic::Ordering::SeqCst), 3);
+    }
+
     #[tokio::test]
     async fn test_mock_llm_provider() {
         let provider = MockLLMProvider::new();
@@ -436,6 +1931,10 @@ mod tests {
             context: HashMap::from([("task".to_string(), serde_json::json!("summarization"))]),
             max_tokens: Some(100),
             temperature: Some(0.7),
+            json_mode: false,
+            tools: Vec::new(),
+            messages: Vec::new(),
+            system_prompt: None,
         };
 
         let response = provider.complete(request).await.unwrap();
@@ -469,6 +1968,456 @@ mod tests {
         assert!(!workflow[0].step_id.is_empty());
     }
 
+    #[cfg(feature = "llm-openai")]
+    #[test]
+    fn test_openai_provider_base_url() {
+        let provider = OpenAIProvider::new("key".to_string(), "gpt-4".to_string());
+        assert_eq!(provider.chat_completions_url(), "https://api.openai.com/v1/chat/completions");
+        assert_eq!(provider.auth_headers().get("Authorization").unwrap(), "Bearer key");
+
+        let azure = OpenAIProvider::new("key".to_string(), "gpt-4".to_string())
+            .with_base_url("https://res.openai.azure.com/openai/deployments/gpt4/".to_string())
+            .with_api_version("2024-02-01".to_string())
+            .with_auth_style(OpenAIAuthStyle::ApiKeyHeader);
+        assert_eq!(
+            azure.chat_completions_url(),
+            "https://res.openai.azure.com/openai/deployments/gpt4/chat/completions?api-version=2024-02-01"
+        );
+        assert_eq!(azure.auth_headers().get("api-key").unwrap(), "key");
+        assert!(azure.auth_headers().get("Authorization").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_mock_tool_call() {
+        let dispatch = ToolCall {
+            id: "call_1".to_string(),
+            name: "dispatch_agent".to_string(),
+            arguments: serde_json::json!({"agent_type": "summarizer"}),
+        };
+        let client = LLMClient::new(
+            Box::new(MockLLMProvider::new().with_tool_call(dispatch.clone())),
+            LLMConfig::default(),
+        );
+
+        let tools = vec![ToolSpec {
+            name: "dispatch_agent".to_string(),
+            description: "Send the task to an agent".to_string(),
+            parameters: serde_json::json!({
+                "type": "object",
+                "properties": {"agent_type": {"type": "string"}},
+                "required": ["agent_type"]
+            }),
+        }];
+
+        let response = client.complete_with_tools("Which agent should handle this?", HashMap::new(), tools).await.unwrap();
+        assert_eq!(response.tool_calls, vec![dispatch]);
+
+        // Without tools on the request the mock does not invent calls
+        let response = client.complete_with_tools("Which agent?", HashMap::new(), Vec::new()).await.unwrap();
+        assert!(response.tool_calls.is_empty());
+    }
+
+    /// Clock whose time only moves when something sleeps on it
+    struct ManualClock {
+        now: Mutex<Duration>,
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Duration {
+            *self.now.lock().unwrap()
+        }
+
+        fn sleep(&self, duration: Duration) -> futures::future::BoxFuture<'_, ()> {
+            *self.now.lock().unwrap() += duration;
+            Box::pin(async {})
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_spaces_requests() {
+        let clock = Arc::new(ManualClock { now: Mutex::new(Duration::ZERO) });
+        let config = LLMConfig {
+            requests_per_minute: Some(60),
+            ..LLMConfig::default()
+        };
+        let client = LLMClient::new(Box::new(MockLLMProvider::new()), config)
+            .with_clock(clock.clone());
+
+        let mut completed_at = Vec::new();
+        for _ in 0..5 {
+            client.reasoning_request("reason about this", HashMap::new()).await.unwrap();
+            completed_at.push(clock.now());
+        }
+
+        let expected: Vec<Duration> = (0..5).map(Duration::from_secs).collect();
+        assert_eq!(completed_at, expected);
+    }
+
+    /// Provider that fails with a retryable timeout while `failing` is set
+    struct FlakyProvider {
+        failing: std::sync::atomic::AtomicBool,
+        calls: std::sync::atomic::AtomicU32,
+    }
+
+    #[async_trait::async_trait]
+    impl LLMProvider for Arc<FlakyProvider> {
+        async fn complete(&self, _request: LLMRequest) -> Result<LLMResponse> {
+            use std::sync::atomic::Ordering;
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if self.failing.load(Ordering::SeqCst) {
+                return Err(Error::LLMTimeout { timeout: 30 });
+            }
+            Ok(LLMResponse {
+                content: "ok".to_string(),
+                usage: LLMUsage::default(),
+                provider: "flaky".to_string(),
+                model: "flaky-model".to_string(),
+                tool_calls: Vec::new(),
+            })
+        }
+
+        fn provider_name(&self) -> &'static str {
+            "flaky"
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fallback_provider_advances_on_retryable_error() {
+        use std::sync::atomic::Ordering;
+
+        let flaky = Arc::new(FlakyProvider {
+            failing: std::sync::atomic::AtomicBool::new(true),
+            calls: std::sync::atomic::AtomicU32::new(0),
+        });
+        let fallback = FallbackProvider::new(vec![
+            Box::new(flaky.clone()),
+            Box::new(MockLLMProvider::new()),
+        ]);
+        assert_eq!(fallback.provider_name(), "flaky→mock");
+
+        let response = fallback.complete(plain_request("summarize this")).await.unwrap();
+        assert_eq!(response.provider, "mock");
+        assert_eq!(flaky.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_transitions() {
+        use std::sync::atomic::Ordering;
+
+        let provider = Arc::new(FlakyProvider {
+            failing: std::sync::atomic::AtomicBool::new(true),
+            calls: std::sync::atomic::AtomicU32::new(0),
+        });
+        let clock = Arc::new(ManualClock { now: Mutex::new(Duration::ZERO) });
+        let client = LLMClient::new(Box::new(provider.clone()), LLMConfig::default())
+            .with_circuit_breaker(CircuitBreakerConfig {
+                failure_threshold: 3,
+                cooldown: Duration::from_secs(10),
+            })
+            .with_clock(clock.clone());
+
+        // Three retryable failures open the circuit
+        for _ in 0..3 {
+            assert!(matches!(client.reasoning_request("x", HashMap::new()).await, Err(Error::LLMTimeout { .. })));
+        }
+        assert_eq!(client.circuit_state(), Some(CircuitState::Open));
+
+        // While open, calls short-circuit without reaching the provider
+        let err = client.reasoning_request("x", HashMap::new()).await.unwrap_err();
+        assert!(matches!(err, Error::LLMProvider(ref msg) if msg == "circuit open"));
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 3);
+
+        // After the cooldown a failing probe re-opens the circuit
+        *clock.now.lock().unwrap() = Duration::from_secs(10);
+        assert!(client.reasoning_request("x", HashMap::new()).await.is_err());
+        assert_eq!(client.circuit_state(), Some(CircuitState::Open));
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 4);
+
+        // A successful probe after the next cooldown closes it again
+        provider.failing.store(false, Ordering::SeqCst);
+        *clock.now.lock().unwrap() = Duration::from_secs(20);
+        assert_eq!(client.reasoning_request("x", HashMap::new()).await.unwrap(), "ok");
+        assert_eq!(client.circuit_state(), Some(CircuitState::Closed));
+    }
+
+    #[test]
+    fn test_circuit_breaker_half_open_allows_single_probe() {
+        let mut breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 1,
+            cooldown: Duration::from_secs(1),
+        });
+        breaker.record::<()>(&Err(Error::Nats("down".to_string())), Duration::ZERO);
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        assert!(breaker.try_acquire(Duration::from_secs(1)).is_ok());
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(breaker.try_acquire(Duration::from_secs(1)).is_err());
+
+        // Non-retryable errors do not count against the provider
+        breaker.record::<()>(&Err(Error::Custom("bad input".to_string())), Duration::from_secs(1));
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn test_token_bucket_refills_when_idle() {
+        let mut bucket = TokenBucket::per_minute(60);
+        assert_eq!(bucket.reserve(Duration::ZERO), Duration::ZERO);
+        // After an idle period longer than the interval no wait is needed
+        assert_eq!(bucket.reserve(Duration::from_secs(5)), Duration::ZERO);
+        assert_eq!(bucket.reserve(Duration::from_secs(5)), Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_mock_embeddings_deterministic() {
+        let client = create_llm_client().unwrap();
+
+        let texts = vec!["rust blog".to_string(), "rust blog".to_string(), "hacker news".to_string()];
+        let vectors = client.embed(texts).await.unwrap();
+
+        assert_eq!(vectors.len(), 3);
+        assert_eq!(vectors[0], vectors[1]);
+        assert!((cosine_similarity(&vectors[0], &vectors[1]) - 1.0).abs() < 1e-5);
+        assert!(cosine_similarity(&vectors[0], &vectors[2]) < 0.999);
+    }
+
+    #[tokio::test]
+    async fn test_embed_without_provider() {
+        let client = LLMClient::new(Box::new(MockLLMProvider::new()), LLMConfig::default());
+        let result = client.embed(vec!["text".to_string()]).await;
+        assert!(matches!(result, Err(Error::LLMProvider(_))));
+    }
+
+    #[tokio::test]
+    async fn test_chat_keeps_history() {
+        let client = LLMClient::new(Box::new(MockLLMProvider::new()), LLMConfig::default());
+
+        let mut conversation = LLMConversation::new();
+        conversation.system_prompt("You coordinate scraping agents.");
+        conversation.push_user("Which sites are queued?");
+
+        let first = client.chat(&mut conversation).await.unwrap();
+        assert_eq!(conversation.messages.len(), 3);
+        assert_eq!(conversation.messages[2], ChatMessage::new(ChatRole::Assistant, first));
+
+        conversation.push_user("Plan the next step.");
+        client.chat(&mut conversation).await.unwrap();
+
+        let roles: Vec<ChatRole> = conversation.messages.iter().map(|m| m.role).collect();
+        assert_eq!(roles, vec![
+            ChatRole::System,
+            ChatRole::User,
+            ChatRole::Assistant,
+            ChatRole::User,
+            ChatRole::Assistant,
+        ]);
+        assert_eq!(conversation.last_user_message(), Some("Plan the next step."));
+    }
+
+    struct UnhealthyProvider;
+
+    #[async_trait::async_trait]
+    impl LLMProvider for UnhealthyProvider {
+        async fn complete(&self, _request: LLMRequest) -> Result<LLMResponse> {
+            panic!("complete called on a provider that failed its health check")
+        }
+
+        fn provider_name(&self) -> &'static str {
+            "unhealthy"
+        }
+
+        async fn health_check(&self) -> Result<()> {
+            Err(Error::LLMProvider("invalid API key".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_health_check_falls_back_to_mock() {
+        let client = LLMClient::new(Box::new(UnhealthyProvider), LLMConfig::default());
+        assert!(client.health_check().await.is_err());
+
+        let client = fallback_if_unhealthy(client).await;
+        assert_eq!(client.provider_name(), "mock");
+        assert!(!client.reasoning_request("still works?", HashMap::new()).await.unwrap().is_empty());
+    }
+
+    fn strict_config(api_key: Option<&str>, model: &str) -> StrictLLMConfig {
+        StrictLLMConfig {
+            api_key: api_key.map(str::to_string),
+            model: model.to_string(),
+            base_url: None,
+            allowed_models: KNOWN_OPENAI_MODELS.iter().map(|m| m.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_strict_client_rejects_missing_or_malformed_config() {
+        let error = create_llm_client_strict_from(&strict_config(None, "gpt-4")).unwrap_err();
+        assert!(matches!(error, Error::LLMProvider(_)));
+        assert!(error.to_string().contains("OPENAI_API_KEY is not set"));
+
+        assert!(strict_config(Some("sk-short"), "gpt-4").validate().is_err());
+        assert!(strict_config(Some("not-an-openai-key-0123456789"), "gpt-4").validate().is_err());
+        assert!(strict_config(Some("sk-0123456789abcdefghij"), "gpt-9000").validate().is_err());
+
+        // Custom endpoints may use keys without the sk- prefix
+        let mut azure = strict_config(Some("0123456789abcdef0123456789abcdef"), "gpt-4");
+        azure.base_url = Some("https://res.openai.azure.com/openai/deployments/gpt4/".to_string());
+        assert!(azure.validate().is_ok());
+    }
+
+    #[cfg(feature = "llm-openai")]
+    #[test]
+    fn test_strict_client_builds_openai_for_valid_config() {
+        let client = create_llm_client_strict_from(&strict_config(Some("sk-0123456789abcdefghij"), "gpt-4o")).unwrap();
+        assert_eq!(client.provider_name(), "openai");
+    }
+
+    #[tokio::test]
+    async fn test_healthy_provider_is_kept() {
+        let client = fallback_if_unhealthy(LLMClient::new(Box::new(MockLLMProvider::new()), LLMConfig::default())).await;
+        assert_eq!(client.provider_name(), "mock");
+        assert!(client.health_check().await.is_ok());
+    }
+
+    fn plain_request(prompt: &str) -> LLMRequest {
+        LLMRequest {
+            prompt: prompt.to_string(),
+            context: HashMap::new(),
+            max_tokens: None,
+            temperature: None,
+            json_mode: false,
+            tools: Vec::new(),
+            messages: Vec::new(),
+            system_prompt: None,
+        }
+    }
+
+    #[test]
+    fn test_openai_messages_map_roles() {
+        let mut request = plain_request("ignored");
+        request.messages = vec![
+            ChatMessage::new(ChatRole::System, "be brief"),
+            ChatMessage::new(ChatRole::User, "hi"),
+            ChatMessage::new(ChatRole::Assistant, "hello"),
+        ];
+        assert_eq!(openai_messages(&request, "ignored"), serde_json::json!([
+            {"role": "system", "content": "be brief"},
+            {"role": "user", "content": "hi"},
+            {"role": "assistant", "content": "hello"},
+        ]));
+        assert_eq!(openai_messages(&plain_request("hi"), "hi"), serde_json::json!([{"role": "user", "content": "hi"}]));
+
+        let mut with_system = plain_request("hi");
+        with_system.system_prompt = Some("use bullet points".to_string());
+        assert_eq!(openai_messages(&with_system, "hi"), serde_json::json!([
+            {"role": "system", "content": "use bullet points"},
+            {"role": "user", "content": "hi"},
+        ]));
+    }
+
+    #[tokio::test]
+    async fn test_configured_system_prompt_reaches_provider() {
+        let provider = MockLLMProvider::new();
+        let requests = provider.request_log();
+        let client = LLMClient::new(Box::new(provider), LLMConfig::default())
+            .with_system_prompt(Some("Answer in bullet points.".to_string()));
+
+        client.summarize_data(vec![serde_json::json!({"title": "a"})]).await.unwrap();
+        let sent = requests.lock().unwrap().last().cloned().unwrap();
+        assert_eq!(sent.system_prompt.as_deref(), Some("Answer in bullet points."));
+    }
+
+    #[tokio::test]
+    async fn test_summarize_data_chunked_map_reduce() {
+        let provider = MockLLMProvider::new();
+        let requests = provider.request_log();
+        let client = LLMClient::new(Box::new(provider), LLMConfig::default());
+        let data: Vec<_> = (0..50).map(|i| serde_json::json!({"title": format!("item {}", i)})).collect();
+
+        let result = client.summarize_data_chunked(data, 10).await.unwrap();
+
+        assert_eq!(result.chunk_summaries.len(), 5);
+        assert!(!result.summary.is_empty());
+        let sent = requests.lock().unwrap();
+        assert_eq!(sent.len(), 6);
+        assert!(sent[5].prompt.contains("5 partial summaries"));
+    }
+
+    #[test]
+    fn test_budget_chunks_respects_size_and_budget() {
+        let items: Vec<String> = (0..7).map(|i| format!("{:08}", i)).collect();
+        assert_eq!(budget_chunks(&items, 3, 1000).len(), 3);
+        // Each item serializes to 10 chars, so a 25-char budget fits two
+        assert!(budget_chunks(&items, 10, 25).iter().all(|chunk| chunk.len() <= 2));
+        assert_eq!(budget_chunks(&items, 10, 1).len(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_default_system_prompt_per_task() {
+        let provider = MockLLMProvider::new();
+        let requests = provider.request_log();
+        let client = LLMClient::new(Box::new(provider), LLMConfig::default());
+
+        client.plan_workflow("scrape", vec!["agent1".to_string()]).await.unwrap();
+        client.reasoning_request("why?", HashMap::new()).await.unwrap();
+
+        let sent = requests.lock().unwrap();
+        assert_eq!(sent[0].system_prompt.as_deref(), Some(default_system_prompt("workflow_planning")));
+        assert_eq!(sent[1].system_prompt.as_deref(), Some(default_system_prompt("reasoning")));
+    }
+
+    #[test]
+    fn test_parse_openai_tool_calls() {
+        let message = serde_json::json!({
+            "content": null,
+            "tool_calls": [{
+                "id": "call_abc",
+                "type": "function",
+                "function": {"name": "dispatch_agent", "arguments": "{\"agent_type\": \"scraper\"}"}
+            }]
+        });
+
+        let calls = parse_openai_tool_calls(&message);
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].id, "call_abc");
+        assert_eq!(calls[0].arguments["agent_type"], "scraper");
+        assert_eq!(openai_tools(&[ToolSpec {
+            name: "dispatch_agent".to_string(),
+            description: String::new(),
+            parameters: serde_json::json!({}),
+        }])[0]["function"]["name"], "dispatch_agent");
+    }
+
+    #[test]
+    fn test_parse_workflow_steps_fenced() {
+        let content = "```json\n[{\"step_id\": \"1\", \"agent_type\": \"collector\", \"action\": \"collect\", \"inputs\": [], \"outputs\": [\"raw\"]}]\n```";
+        let steps = parse_workflow_steps(content).unwrap();
+        assert_eq!(steps.len(), 1);
+        assert_eq!(steps[0].agent_type, "collector");
+    }
+
+    #[test]
+    fn test_parse_workflow_steps_leading_prose() {
+        // The closing bracket inside the step_id string must not end the array early
+        let content = "Sure! Here is the plan:\n[{\"step_id\": \"a]\", \"agent_type\": \"summarizer\", \"action\": \"summarize\", \"inputs\": [\"raw\"], \"outputs\": [\"summary\"]}] Let me know if you need more.";
+        let steps = parse_workflow_steps(content).unwrap();
+        assert_eq!(steps.len(), 1);
+        assert_eq!(steps[0].step_id, "a]");
+    }
+
+    #[test]
+    fn test_parse_workflow_steps_json_object() {
+        let content = r#"{"steps": [{"step_id": "1", "agent_type": "mock", "action": "process", "inputs": [], "outputs": []}]}"#;
+        let steps = parse_workflow_steps(content).unwrap();
+        assert_eq!(steps[0].action, "process");
+    }
+
+    #[test]
+    fn test_parse_workflow_steps_invalid() {
+        let err = parse_workflow_steps("I could not come up with a plan.").unwrap_err();
+        assert!(matches!(err, Error::LLMResponseFormat(ref msg) if msg.contains("could not come up")));
+    }
+
     #[test]
     fn test_workflow_step_serialization() {
         let step = WorkflowStep {
@@ -477,6 +2426,7 @@ mod tests {
             action: "process_data".to_string(),
             inputs: vec!["input1".to_string()],
             outputs: vec!["output1".to_string()],
+            depends_on: Vec::new(),
         };
 
         let serialized = serde_json::to_string(&step).unwrap();
@@ -485,4 +2435,138 @@ mod tests {
         assert_eq!(step.step_id, deserialized.step_id);
         assert_eq!(step.agent_type, deserialized.agent_type);
     }
-}
\ No newline at end of file
+
+    fn step(id: &str, depends_on: &[&str]) -> WorkflowStep {
+        WorkflowStep {
+            step_id: id.to_string(),
+            agent_type: "agent".to_string(),
+            action: "act".to_string(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            depends_on: depends_on.iter().map(|d| d.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_validate_workflow_orders_dag() {
+        let steps = vec![
+            step("summarize", &["scrape_a", "scrape_b"]),
+            step("scrape_a", &[]),
+            step("report", &["summarize"]),
+            step("scrape_b", &[]),
+        ];
+        let order = validate_workflow(&steps).unwrap();
+        assert_eq!(order, vec!["scrape_a", "scrape_b", "summarize", "report"]);
+    }
+
+    #[test]
+    fn test_validate_workflow_detects_cycle() {
+        let steps = vec![step("a", &["c"]), step("b", &["a"]), step("c", &["b"]), step("d", &[])];
+        let err = validate_workflow(&steps).unwrap_err();
+        assert!(matches!(err, Error::WorkflowValidation(ref msg) if msg.contains("cycle") && msg.contains("a, b, c")));
+    }
+
+    #[test]
+    fn test_validate_workflow_dangling_dependency() {
+        let steps = vec![step("a", &[]), step("b", &["missing"])];
+        let err = validate_workflow(&steps).unwrap_err();
+        assert!(matches!(err, Error::WorkflowValidation(ref msg) if msg.contains("unknown step 'missing'")));
+    }
+
+    #[test]
+    fn test_workflow_step_without_depends_on_deserializes() {
+        let step: WorkflowStep = serde_json::from_str(
+            r#"{"step_id": "1", "agent_type": "mock", "action": "process", "inputs": [], "outputs": []}"#
+        ).unwrap();
+        assert!(step.depends_on.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_mock_matcher_routes_requests() {
+        let provider = MockLLMProvider::new().with_matcher(|request| {
+            request.prompt.starts_with("route:").then(|| "routed".to_string())
+        });
+
+        let routed = provider.complete(plain_request("route: anything")).await.unwrap();
+        assert_eq!(routed.content, "routed");
+
+        // Unmatched requests keep the keyword behaviour
+        let fallback = provider.complete(plain_request("please summarize")).await.unwrap();
+        assert!(fallback.content.contains("Mock summary"));
+    }
+
+    #[tokio::test]
+    async fn test_mock_latency_delays_response() {
+        let provider = MockLLMProvider::new().with_latency(Duration::from_millis(50));
+
+        let start = std::time::Instant::now();
+        provider.complete(plain_request("reason")).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn test_mock_failure_after() {
+        let provider = MockLLMProvider::new().with_failure_after(2);
+
+        assert!(provider.complete(plain_request("one")).await.is_ok());
+        assert!(provider.complete(plain_request("two")).await.is_ok());
+        for _ in 0..2 {
+            let err = provider.complete(plain_request("three")).await.unwrap_err();
+            assert!(matches!(err, Error::LLMTimeout { .. }));
+        }
+        assert_eq!(provider.request_log().lock().unwrap().len(), 4);
+    }
+
+    // The mock sleeps through its latency without tokio, so only this build can race it
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_slow_provider_times_out() {
+        let config = LLMConfig { timeout_seconds: 1, ..LLMConfig::default() };
+        let client = LLMClient::new(
+            Box::new(MockLLMProvider::new().with_latency(Duration::from_secs(5))),
+            config,
+        );
+
+        let start = std::time::Instant::now();
+        let err = client.reasoning_request("take your time", HashMap::new()).await.unwrap_err();
+        assert!(matches!(err, Error::LLMTimeout { timeout: 1 }));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    // Never answers, so only the client's own timer can end the request
+    #[cfg(all(not(feature = "tokio"), not(target_arch = "wasm32")))]
+    struct StalledProvider;
+
+    #[cfg(all(not(feature = "tokio"), not(target_arch = "wasm32")))]
+    #[async_trait::async_trait]
+    impl LLMProvider for StalledProvider {
+        async fn complete(&self, _request: LLMRequest) -> Result<LLMResponse> {
+            futures::future::pending().await
+        }
+
+        fn provider_name(&self) -> &'static str {
+            "stalled"
+        }
+    }
+
+    #[cfg(all(not(feature = "tokio"), not(target_arch = "wasm32")))]
+    #[test]
+    fn test_stalled_provider_times_out_without_tokio() {
+        let config = LLMConfig { timeout_seconds: 1, ..LLMConfig::default() };
+        let client = LLMClient::new(Box::new(StalledProvider), config);
+
+        let start = std::time::Instant::now();
+        let err = futures::executor::block_on(client.reasoning_request("hello?", HashMap::new())).unwrap_err();
+        assert!(matches!(err, Error::LLMTimeout { timeout: 1 }));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_prompt_char_budget() {
+        let config = LLMConfig { max_tokens: 1000, context_window_tokens: 4000, ..LLMConfig::default() };
+        assert_eq!(config.prompt_char_budget(), 2800 * CHARS_PER_TOKEN);
+
+        let tiny = LLMConfig { max_tokens: 1000, context_window_tokens: 500, ..LLMConfig::default() };
+        assert_eq!(tiny.prompt_char_budget(), 0);
+    }
+}
@@ -6,16 +6,21 @@ use std::time::Duration;
 
 // Include the library modules
 mod agent;
-mod http_client;  // Add missing http_client module
+mod http_client;
 mod llm_client;  
 mod memory; 
+#[cfg(feature = "metrics")]
+mod metrics;
 mod nats_comm;
+mod scraping;
 mod supervisor;
+mod telemetry;
 mod wasm_nats;
 
 // Re-export commonly used items
-use agent::{AgentId, Message, StateAction};
+use agent::{encode_message, AgentId, Message, StateAction};
 use nats_comm::{NatsConfig, NatsConnection};
+use scraping::ScrapeErrorKind;
 use supervisor::{
     AgentConfig, MemoryBackendType, AgentType,
     spawn_agent_supervisor, spawn_single_agent,
@@ -26,6 +31,8 @@ use supervisor::{
 // Common result type
 type Result<T> = std::result::Result<T, Error>;
 
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
 // Common error type
 #[derive(Debug, thiserror::Error)]
 enum Error {
@@ -56,22 +63,55 @@ enum Error {
     
     #[error("Workflow validation error: {0}")]
     WorkflowValidation(String),
+
+    #[error("Invalid message: {0}")]
+    InvalidMessage(String),
+
+    #[error("Scraping {url} failed: {kind}")]
+    Scraping { url: String, kind: ScrapeErrorKind },
+
+    // Same message as `Nats`, but keeps the client error reachable through `source()`
+    #[error("NATS error: {message}")]
+    NatsClient { message: String, #[source] source: BoxError },
+
+    #[error("HTTP request failed: {message}")]
+    Http { message: String, #[source] source: BoxError },
 }
 
 impl Error {
+    /// Wrap a NATS client error, keeping it as the source
+    pub fn nats<E>(message: impl Into<String>, source: E) -> Self
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        Error::NatsClient { message: message.into(), source: Box::new(source) }
+    }
+
+    /// Wrap an HTTP client error, keeping it as the source
+    pub fn http<E>(source: E) -> Self
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        Error::Http { message: source.to_string(), source: Box::new(source) }
+    }
+
     pub fn is_retryable(&self) -> bool {
-        matches!(self, 
-            Error::LLMTimeout { .. } | 
+        match self {
+            Error::LLMTimeout { .. } |
             Error::LLMRateLimit(_) |
-            Error::Nats(_)
-        )
+            Error::Nats(_) |
+            Error::NatsClient { .. } => true,
+            Error::Scraping { kind, .. } => kind.is_retryable(),
+            _ => false,
+        }
     }
 
     pub fn retry_delay_ms(&self) -> u64 {
         match self {
             Error::LLMTimeout { .. } => 1000,
             Error::LLMRateLimit(_) => 5000,
-            Error::Nats(_) => 500,
+            Error::Nats(_) | Error::NatsClient { .. } => 500,
+            Error::Scraping { kind, .. } => kind.retry_delay_ms(),
             _ => 0,
         }
     }
@@ -106,6 +146,7 @@ async fn nats_main() -> Result<()> {
             nats_enabled: true,
             llm_enabled: false,
             agent_type: AgentType::Generic,
+            ..Default::default()
         },
         AgentConfig {
             id: AgentId("worker_agent_2".to_string()),
@@ -113,6 +154,7 @@ async fn nats_main() -> Result<()> {
             nats_enabled: true,
             llm_enabled: false,
             agent_type: AgentType::Generic,
+            ..Default::default()
         },
         AgentConfig {
             id: AgentId("monitor_agent".to_string()),
@@ -120,6 +162,7 @@ async fn nats_main() -> Result<()> {
             nats_enabled: false,
             llm_enabled: false,
             agent_type: AgentType::Generic,
+            ..Default::default()
         },
     ];
 
@@ -138,7 +181,10 @@ async fn nats_main() -> Result<()> {
         url: std::env::var("NATS_URL").unwrap_or_else(|_| "nats://localhost:4222".to_string()),
         timeout: Duration::from_secs(10),
         max_reconnects: Some(10),
-        reconnect_delay: Duration::from_secs(1),
+        reconnect_backoff: nats_comm::BackoffStrategy::default(),
+        compress_threshold_bytes: nats_comm::DEFAULT_COMPRESS_THRESHOLD_BYTES,
+        max_payload_bytes: None,
+        max_message_bytes: nats_comm::DEFAULT_MAX_MESSAGE_BYTES,
     };
 
     // Try to connect to NATS (system works without it)
@@ -181,6 +227,10 @@ async fn demonstrate_supervisor_api(configs: Vec<AgentConfig>) -> Result<()> {
             "priority": "high"
         }),
         timestamp: chrono::Utc::now().timestamp() as u64,
+        expires_at: None,
+        correlation_id: None,
+        reply_to: None,
+        signature: None,
     };
 
     info!("Example message: {:?}", test_message);
@@ -219,24 +269,29 @@ async fn demonstrate_nats_integration(nats_conn: NatsConnection) -> Result<()> {
 
     // Check connection stats
     let stats = nats_conn.get_stats();
+    #[cfg(feature = "metrics")]
+    metrics::record_nats_stats(&stats);
     info!("NATS connection stats: sent={}, received={}, reconnects={}", 
           stats.messages_sent, stats.messages_received, stats.reconnects);
 
     // Demonstrate agent-to-agent messaging pattern
-    let agent_message = serde_json::json!({
-        "id": format!("msg_{}", chrono::Utc::now().timestamp_nanos()),
-        "from": {"0": "worker_agent_1"},
-        "to": {"0": "worker_agent_2"},
-        "payload": {
+    let agent_message = Message {
+        id: format!("msg_{}", chrono::Utc::now().timestamp_nanos()),
+        from: AgentId("worker_agent_1".to_string()),
+        to: AgentId("worker_agent_2".to_string()),
+        payload: serde_json::json!({
             "type": "work_result",
             "data": "completed_task_123",
             "success": true
-        },
-        "timestamp": chrono::Utc::now().timestamp()
-    });
+        }),
+        timestamp: chrono::Utc::now().timestamp() as u64,
+        expires_at: None,
+        correlation_id: None,
+        reply_to: None,
+        signature: None,
+    };
 
-    if let Err(e) = nats_conn.publish("agent.worker_agent_2", 
-                                     serde_json::to_string(&agent_message)?.as_bytes()).await {
+    if let Err(e) = nats_conn.publish("agent.worker_agent_2", &encode_message(&agent_message)?).await {
         error!("Failed to publish agent message: {}", e);
     } else {
         info!("Published inter-agent message via NATS");
@@ -260,6 +315,7 @@ fn wasm_main() -> Result<()> {
             nats_enabled: true, // Can enable NATS via WebSocket in WASM mode
             llm_enabled: false,
             agent_type: AgentType::Generic,
+            ..Default::default()
         },
         AgentConfig {
             id: AgentId("worker_agent_2".to_string()),
@@ -267,6 +323,7 @@ fn wasm_main() -> Result<()> {
             nats_enabled: true,
             llm_enabled: false,
             agent_type: AgentType::Generic,
+            ..Default::default()
         },
         AgentConfig {
             id: AgentId("monitor_agent".to_string()),
@@ -274,6 +331,7 @@ fn wasm_main() -> Result<()> {
             nats_enabled: false,
             llm_enabled: false,
             agent_type: AgentType::Generic,
+            ..Default::default()
         },
     ];
 
@@ -315,6 +373,10 @@ fn wasm_demonstrate_supervisor_api(configs: Vec<AgentConfig>) -> Result<()> {
             "priority": "high"
         }),
         timestamp: chrono::Utc::now().timestamp() as u64,
+        expires_at: None,
+        correlation_id: None,
+        reply_to: None,
+        signature: None,
     };
 
     log::info!("Example message: {:?}", test_message);
@@ -343,7 +405,8 @@ fn wasm_demonstrate_websocket_nats() -> Result<()> {
         websocket_url: "ws://localhost:8080/nats".to_string(),
         timeout: std::time::Duration::from_secs(10),
         max_reconnects: Some(5),
-        reconnect_delay: std::time::Duration::from_secs(2),
+        reconnect_backoff: nats_comm::BackoffStrategy::Fixed(std::time::Duration::from_secs(2)),
+        default_content_type: None,
     };
     
     log::info!("WebSocket NATS configuration: {:?}", wasm_nats_config);
@@ -399,6 +462,7 @@ async fn run_integration_tests() -> Result<()> {
         nats_enabled: false,
         llm_enabled: false,
         agent_type: AgentType::Generic,
+        ..Default::default()
     };
 
     info!("Test agent config: {:?}", test_config);
@@ -425,6 +489,7 @@ mod integration_tests {
             nats_enabled: true,
             llm_enabled: false,
             agent_type: AgentType::Generic,
+            ..Default::default()
         };
         
         assert_eq!(config.id.0, "test_agent");
@@ -439,6 +504,10 @@ mod integration_tests {
             to: AgentId("receiver".to_string()),
             payload: serde_json::json!({"type": "test", "data": "hello"}),
             timestamp: 12345,
+            expires_at: None,
+            correlation_id: None,
+            reply_to: None,
+            signature: None,
         };
 
         let serialized = serde_json::to_string(&message).unwrap();
@@ -476,7 +545,10 @@ mod integration_tests {
             url: std::env::var("NATS_URL").unwrap_or_else(|_| "nats://localhost:4222".to_string()),
             timeout: Duration::from_secs(10),
             max_reconnects: Some(10),
-            reconnect_delay: Duration::from_secs(1),
+            reconnect_backoff: nats_comm::BackoffStrategy::default(),
+            compress_threshold_bytes: nats_comm::DEFAULT_COMPRESS_THRESHOLD_BYTES,
+            max_payload_bytes: None,
+            max_message_bytes: nats_comm::DEFAULT_MAX_MESSAGE_BYTES,
         };
         
         assert_eq!(config.url, "nats://test:4222");
@@ -11,6 +11,62 @@ pub trait MemoryBackend: Send + Sync + std::fmt::Debug {
     async fn delete(&mut self, key: &str) -> Result<bool>;
     async fn list_keys(&self, prefix: Option<&str>) -> Result<Vec<String>>;
     async fn clear(&mut self) -> Result<()>;
+
+    /// Up to `limit` keys in ascending order, plus a cursor for the next page (`None` when done).
+    /// Pass the returned cursor back unchanged. This default sorts the full `list_keys` result;
+    /// backends with a native cursor (SQL, Redis) should override it.
+    async fn list_keys_paged(&self, prefix: Option<&str>, cursor: Option<String>, limit: usize) -> Result<(Vec<String>, Option<String>)> {
+        let mut keys = self.list_keys(prefix).await?;
+        keys.sort();
+
+        let start = match &cursor {
+            Some(after) => keys.partition_point(|key| key <= after),
+            None => 0,
+        };
+        let end = (start + limit.max(1)).min(keys.len());
+        let page = keys[start..end].to_vec();
+        let next = (end < keys.len()).then(|| page[page.len() - 1].clone());
+        Ok((page, next))
+    }
+}
+
+/// Keys fetched per `list_keys_paged` call while migrating
+const MIGRATION_PAGE_SIZE: usize = 500;
+
+/// Copy every key under `prefix` from `src` to `dst`, returning how many were written.
+/// Without `overwrite`, keys already in `dst` are left alone, so an interrupted migration
+/// can simply be run again. `src` is `&mut` only because `retrieve` takes `&mut self`.
+pub async fn migrate_backend(
+    src: &mut dyn MemoryBackend,
+    dst: &mut dyn MemoryBackend,
+    prefix: Option<&str>,
+    overwrite: bool,
+) -> Result<usize> {
+    let mut migrated = 0;
+    let mut cursor = None;
+
+    loop {
+        let (keys, next) = src.list_keys_paged(prefix, cursor, MIGRATION_PAGE_SIZE).await?;
+        for key in keys {
+            if !overwrite && dst.retrieve(&key).await?.is_some() {
+                continue;
+            }
+            // Deleted from the source since it was listed
+            let Some(value) = src.retrieve(&key).await? else {
+                continue;
+            };
+            dst.store(&key, &value).await?;
+            migrated += 1;
+        }
+
+        match next {
+            Some(next) => cursor = Some(next),
+            None => break,
+        }
+    }
+
+    log::info!("Migrated {} keys between memory backends", migrated);
+    Ok(migrated)
 }
 
 #[derive(Debug, Clone)]
@@ -109,36 +165,69 @@ pub mod persistent {
                 .map_err(|e| crate::Error::Io(e))? {
                 
                 let path = entry.path();
-                if path.extension().and_then(|s| s.to_str()) == Some("json") {
-                    let key = path.file_stem()
-                        .and_then(|s| s.to_str())
-                        .unwrap_or("")
-                        .to_string();
-
-                    let mut file = fs::File::open(&path).await
-                        .map_err(|e| crate::Error::Io(e))?;
-                    
-                    let mut contents = String::new();
-                    file.read_to_string(&mut contents).await
-                        .map_err(|e| crate::Error::Io(e))?;
-
-                    let value: Value = serde_json::from_str(&contents)?;
-                    self.in_memory.store(&key, &value).await?;
+                match path.extension().and_then(|s| s.to_str()) {
+                    Some("json") => {}
+                    // Left behind by a write that never reached its rename
+                    Some("tmp") => {
+                        log::warn!("Removing incomplete write {}", path.display());
+                        let _ = fs::remove_file(&path).await;
+                        continue;
+                    }
+                    _ => continue,
                 }
+
+                let key = path.file_stem()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or("")
+                    .to_string();
+
+                let value = match Self::read_value(&path).await {
+                    Ok(value) => value,
+                    Err(e) => {
+                        // One bad file shouldn't keep the agent from starting
+                        log::error!("Skipping unreadable state file {}: {}", path.display(), e);
+                        let corrupt_path = path.with_extension("json.corrupt");
+                        if let Err(e) = fs::rename(&path, &corrupt_path).await {
+                            log::warn!("Could not move {} aside: {}", path.display(), e);
+                        }
+                        continue;
+                    }
+                };
+                self.in_memory.store(&key, &value).await?;
             }
 
             Ok(())
         }
 
+        async fn read_value(path: &Path) -> Result<Value> {
+            let mut file = fs::File::open(path).await
+                .map_err(|e| crate::Error::Io(e))?;
+
+            let mut contents = String::new();
+            file.read_to_string(&mut contents).await
+                .map_err(|e| crate::Error::Io(e))?;
+
+            Ok(serde_json::from_str(&contents)?)
+        }
+
+        // Write to `<key>.json.tmp` and rename over `<key>.json`, so a crash mid-write
+        // leaves the previous version in place instead of a truncated file
         async fn save_to_disk(&self, key: &str, value: &Value) -> Result<()> {
             let file_path = self.base_path.join(format!("{}.json", key));
+            let tmp_path = self.base_path.join(format!("{}.json.tmp", key));
             let content = serde_json::to_string_pretty(value)?;
             
-            let mut file = fs::File::create(file_path).await
+            let mut file = fs::File::create(&tmp_path).await
                 .map_err(|e| crate::Error::Io(e))?;
             
             file.write_all(content.as_bytes()).await
                 .map_err(|e| crate::Error::Io(e))?;
+            file.sync_all().await
+                .map_err(|e| crate::Error::Io(e))?;
+            drop(file);
+
+            fs::rename(&tmp_path, &file_path).await
+                .map_err(|e| crate::Error::Io(e))?;
             
             Ok(())
         }
@@ -151,6 +240,81 @@ pub mod persistent {
             }
             Ok(())
         }
+
+        /// Read every entry under `base_path` without an async runtime, for callers such
+        /// as Lunatic process handlers. Same file layout and recovery rules as `new`.
+        pub fn load_blocking<P: AsRef<Path>>(base_path: P) -> Result<HashMap<String, Value>> {
+            let base_path = base_path.as_ref();
+            let mut values = HashMap::new();
+            if !base_path.exists() {
+                return Ok(values);
+            }
+
+            for entry in std::fs::read_dir(base_path).map_err(crate::Error::Io)? {
+                let path = entry.map_err(crate::Error::Io)?.path();
+                match path.extension().and_then(|s| s.to_str()) {
+                    Some("json") => {}
+                    Some("tmp") => {
+                        log::warn!("Removing incomplete write {}", path.display());
+                        let _ = std::fs::remove_file(&path);
+                        continue;
+                    }
+                    _ => continue,
+                }
+
+                let key = path.file_stem()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or("")
+                    .to_string();
+
+                let parsed = std::fs::read_to_string(&path)
+                    .map_err(crate::Error::Io)
+                    .and_then(|contents| Ok(serde_json::from_str::<Value>(&contents)?));
+                match parsed {
+                    Ok(value) => {
+                        values.insert(key, value);
+                    }
+                    Err(e) => {
+                        log::error!("Skipping unreadable state file {}: {}", path.display(), e);
+                        let corrupt_path = path.with_extension("json.corrupt");
+                        if let Err(e) = std::fs::rename(&path, &corrupt_path) {
+                            log::warn!("Could not move {} aside: {}", path.display(), e);
+                        }
+                    }
+                }
+            }
+
+            Ok(values)
+        }
+
+        /// Blocking counterpart of `store`, using the same tmp-then-rename write
+        pub fn store_blocking<P: AsRef<Path>>(base_path: P, key: &str, value: &Value) -> Result<()> {
+            use std::io::Write;
+
+            let base_path = base_path.as_ref();
+            std::fs::create_dir_all(base_path).map_err(crate::Error::Io)?;
+            let file_path = base_path.join(format!("{}.json", key));
+            let tmp_path = base_path.join(format!("{}.json.tmp", key));
+            let content = serde_json::to_string_pretty(value)?;
+
+            let mut file = std::fs::File::create(&tmp_path).map_err(crate::Error::Io)?;
+            file.write_all(content.as_bytes()).map_err(crate::Error::Io)?;
+            file.sync_all().map_err(crate::Error::Io)?;
+            drop(file);
+
+            std::fs::rename(&tmp_path, &file_path).map_err(crate::Error::Io)?;
+            Ok(())
+        }
+
+        /// Blocking counterpart of `delete`; returns whether the key was on disk
+        pub fn delete_blocking<P: AsRef<Path>>(base_path: P, key: &str) -> Result<bool> {
+            let file_path = base_path.as_ref().join(format!("{}.json", key));
+            if !file_path.exists() {
+                return Ok(false);
+            }
+            std::fs::remove_file(file_path).map_err(crate::Error::Io)?;
+            Ok(true)
+        }
     }
 
     #[async_trait]
@@ -188,6 +352,166 @@ pub mod persistent {
     }
 }
 
+#[cfg(feature = "wasm-storage")]
+pub mod web_storage {
+    use super::*;
+    use wasm_bindgen::JsValue;
+    use web_sys::Storage;
+
+    /// `MemoryBackend` over the browser's `localStorage`, so WASM agents keep their state
+    /// across page reloads. Values are JSON-encoded under `<namespace>:<key>`.
+    ///
+    /// IndexedDB isn't used: its futures aren't `Send`, which `MemoryBackend` requires, so
+    /// values are bounded by the origin's `localStorage` quota (typically ~5 MB).
+    #[derive(Debug, Clone)]
+    pub struct WebStorageBackend {
+        namespace: String,
+    }
+
+    impl WebStorageBackend {
+        /// Fails when `localStorage` isn't reachable (no window, or storage disabled)
+        pub fn new(namespace: impl Into<String>) -> Result<Self> {
+            let backend = Self { namespace: namespace.into() };
+            backend.storage()?;
+            Ok(backend)
+        }
+
+        // Looked up per call: `Storage` isn't `Send`, so it can't live in the backend
+        fn storage(&self) -> Result<Storage> {
+            web_sys::window()
+                .ok_or_else(|| crate::Error::Custom("Web storage requires a browser window".to_string()))?
+                .local_storage()
+                .map_err(js_error)?
+                .ok_or_else(|| crate::Error::Custom("localStorage is unavailable".to_string()))
+        }
+
+        fn storage_key(&self, key: &str) -> String {
+            format!("{}:{}", self.namespace, key)
+        }
+
+        // Keys in this namespace, with the namespace stripped
+        fn namespaced_keys(&self) -> Result<Vec<String>> {
+            let storage = self.storage()?;
+            let prefix = self.storage_key("");
+            let length = storage.length().map_err(js_error)?;
+            let mut keys = Vec::new();
+            for index in 0..length {
+                if let Some(key) = storage.key(index).map_err(js_error)? {
+                    if let Some(key) = key.strip_prefix(&prefix) {
+                        keys.push(key.to_string());
+                    }
+                }
+            }
+            Ok(keys)
+        }
+
+        fn store_sync(&self, key: &str, value: &Value) -> Result<()> {
+            let encoded = serde_json::to_string(value)?;
+            self.storage()?.set_item(&self.storage_key(key), &encoded).map_err(js_error)
+        }
+
+        fn retrieve_sync(&self, key: &str) -> Result<Option<Value>> {
+            match self.storage()?.get_item(&self.storage_key(key)).map_err(js_error)? {
+                Some(encoded) => Ok(Some(serde_json::from_str(&encoded)?)),
+                None => Ok(None),
+            }
+        }
+
+        fn delete_sync(&self, key: &str) -> Result<bool> {
+            let storage = self.storage()?;
+            let storage_key = self.storage_key(key);
+            let existed = storage.get_item(&storage_key).map_err(js_error)?.is_some();
+            storage.remove_item(&storage_key).map_err(js_error)?;
+            Ok(existed)
+        }
+
+        fn clear_sync(&self) -> Result<()> {
+            // Only this namespace; other backends may share the origin's storage
+            let storage = self.storage()?;
+            for key in self.namespaced_keys()? {
+                storage.remove_item(&self.storage_key(&key)).map_err(js_error)?;
+            }
+            Ok(())
+        }
+    }
+
+    fn js_error(error: JsValue) -> crate::Error {
+        crate::Error::Custom(format!("Web storage error: {:?}", error))
+    }
+
+    #[async_trait]
+    impl MemoryBackend for WebStorageBackend {
+        async fn store(&mut self, key: &str, value: &Value) -> Result<()> {
+            self.store_sync(key, value)
+        }
+
+        async fn retrieve(&mut self, key: &str) -> Result<Option<Value>> {
+            self.retrieve_sync(key)
+        }
+
+        async fn delete(&mut self, key: &str) -> Result<bool> {
+            self.delete_sync(key)
+        }
+
+        async fn list_keys(&self, prefix: Option<&str>) -> Result<Vec<String>> {
+            let keys = self.namespaced_keys()?;
+            Ok(match prefix {
+                Some(prefix) => keys.into_iter().filter(|key| key.starts_with(prefix)).collect(),
+                None => keys,
+            })
+        }
+
+        async fn clear(&mut self) -> Result<()> {
+            self.clear_sync()
+        }
+    }
+}
+
+#[cfg(all(test, target_arch = "wasm32", feature = "wasm-storage"))]
+mod web_storage_tests {
+    use super::web_storage::WebStorageBackend;
+    use super::*;
+    use serde_json::json;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_web_storage_store_and_retrieve() {
+        let mut backend = WebStorageBackend::new("test-store").unwrap();
+        backend.clear().await.unwrap();
+
+        backend.store("config", &json!({"depth": 2})).await.unwrap();
+        assert_eq!(backend.retrieve("config").await.unwrap(), Some(json!({"depth": 2})));
+        assert_eq!(backend.retrieve("missing").await.unwrap(), None);
+
+        assert!(backend.delete("config").await.unwrap());
+        assert!(!backend.delete("config").await.unwrap());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_web_storage_list_keys_is_namespaced() {
+        let mut backend = WebStorageBackend::new("test-list").unwrap();
+        let mut other = WebStorageBackend::new("test-list-other").unwrap();
+        backend.clear().await.unwrap();
+        other.clear().await.unwrap();
+
+        backend.store("scraped:1", &json!(1)).await.unwrap();
+        backend.store("scraped:2", &json!(2)).await.unwrap();
+        backend.store("summary", &json!("ok")).await.unwrap();
+        other.store("scraped:3", &json!(3)).await.unwrap();
+
+        let mut keys = backend.list_keys(Some("scraped:")).await.unwrap();
+        keys.sort();
+        assert_eq!(keys, vec!["scraped:1".to_string(), "scraped:2".to_string()]);
+        assert_eq!(backend.list_keys(None).await.unwrap().len(), 3);
+
+        backend.clear().await.unwrap();
+        assert!(backend.list_keys(None).await.unwrap().is_empty());
+        assert_eq!(other.list_keys(None).await.unwrap(), vec!["scraped:3".to_string()]);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -236,6 +560,48 @@ mod tests {
         assert!(system_keys.contains(&"system:config".to_string()));
     }
 
+    #[cfg(feature = "nats")]
+    #[tokio::test]
+    async fn test_list_keys_paged() {
+        let mut backend = InMemoryBackend::new();
+        for key in ["agent1:e", "agent1:a", "agent1:d", "agent1:b", "agent1:c", "agent2:a"] {
+            backend.store(key, &json!(1)).await.unwrap();
+        }
+
+        let (first, cursor) = backend.list_keys_paged(Some("agent1:"), None, 3).await.unwrap();
+        assert_eq!(first, vec!["agent1:a", "agent1:b", "agent1:c"]);
+        assert!(cursor.is_some());
+
+        let (second, cursor) = backend.list_keys_paged(Some("agent1:"), cursor, 3).await.unwrap();
+        assert_eq!(second, vec!["agent1:d", "agent1:e"]);
+        assert_eq!(cursor, None);
+    }
+
+    #[cfg(feature = "nats")]
+    #[tokio::test]
+    async fn test_migrate_backend_copies_and_resumes() {
+        let mut src = InMemoryBackend::new();
+        for i in 0..1200 {
+            src.store(&format!("agent1:key{:04}", i), &json!(i)).await.unwrap();
+        }
+        src.store("other:key", &json!("skip me")).await.unwrap();
+
+        // A previous, interrupted run already copied one key with an older value
+        let mut dst = InMemoryBackend::new();
+        dst.store("agent1:key0000", &json!("stale")).await.unwrap();
+
+        let migrated = migrate_backend(&mut src, &mut dst, Some("agent1:"), false).await.unwrap();
+        assert_eq!(migrated, 1199);
+        assert_eq!(dst.list_keys(Some("agent1:")).await.unwrap().len(), 1200);
+        assert_eq!(dst.retrieve("agent1:key0000").await.unwrap(), Some(json!("stale")));
+        assert_eq!(dst.retrieve("agent1:key1199").await.unwrap(), Some(json!(1199)));
+        assert_eq!(dst.retrieve("other:key").await.unwrap(), None);
+
+        let migrated = migrate_backend(&mut src, &mut dst, Some("agent1:"), true).await.unwrap();
+        assert_eq!(migrated, 1200);
+        assert_eq!(dst.retrieve("agent1:key0000").await.unwrap(), Some(json!(0)));
+    }
+
     #[cfg(feature = "persistence")]
     mod persistent_tests {
         use super::*;
@@ -252,5 +618,43 @@ mod tests {
             let retrieved = backend.retrieve("test_key").await.unwrap();
             assert_eq!(retrieved, Some(test_value));
         }
+
+        #[tokio::test]
+        async fn test_truncated_file_is_skipped_on_load() {
+            let temp_dir = tempdir().unwrap();
+            {
+                let mut backend = persistent::FileBackend::new(temp_dir.path()).await.unwrap();
+                backend.store("agent:good", &json!({"pages": 3})).await.unwrap();
+            }
+            std::fs::write(temp_dir.path().join("agent:bad.json"), "{\"pages\": ").unwrap();
+            std::fs::write(temp_dir.path().join("agent:half.json.tmp"), "{\"pa").unwrap();
+
+            let mut backend = persistent::FileBackend::new(temp_dir.path()).await.unwrap();
+            assert_eq!(backend.retrieve("agent:good").await.unwrap(), Some(json!({"pages": 3})));
+            assert_eq!(backend.list_keys(None).await.unwrap(), vec!["agent:good".to_string()]);
+
+            assert!(temp_dir.path().join("agent:bad.json.corrupt").exists());
+            assert!(!temp_dir.path().join("agent:bad.json").exists());
+            assert!(!temp_dir.path().join("agent:half.json.tmp").exists());
+        }
+
+        #[tokio::test]
+        async fn test_blocking_helpers_share_the_async_layout() {
+            let temp_dir = tempdir().unwrap();
+            persistent::FileBackend::store_blocking(temp_dir.path(), "agent:pages", &json!(3)).unwrap();
+            std::fs::write(temp_dir.path().join("agent:bad.json"), "{\"pages\": ").unwrap();
+
+            let mut backend = persistent::FileBackend::new(temp_dir.path()).await.unwrap();
+            assert_eq!(backend.retrieve("agent:pages").await.unwrap(), Some(json!(3)));
+            backend.store("agent:depth", &json!(2)).await.unwrap();
+
+            let loaded = persistent::FileBackend::load_blocking(temp_dir.path()).unwrap();
+            assert_eq!(loaded.len(), 2);
+            assert_eq!(loaded.get("agent:depth"), Some(&json!(2)));
+
+            assert!(persistent::FileBackend::delete_blocking(temp_dir.path(), "agent:pages").unwrap());
+            assert!(!persistent::FileBackend::delete_blocking(temp_dir.path(), "agent:pages").unwrap());
+            assert_eq!(persistent::FileBackend::load_blocking(temp_dir.path()).unwrap().len(), 1);
+        }
     }
 }
\ No newline at end of file
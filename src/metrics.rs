@@ -0,0 +1,222 @@
+//! Prometheus-style counters for agent throughput, LLM operations, scraping and NATS traffic.
+//!
+//! Counters live in a process-wide registry. Lunatic processes don't share memory, so each
+//! agent process records only its own series; `AgentSupervisor::metrics_snapshot` gathers
+//! them through `GetAgentMetrics` and renders them together with the caller's registry.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use crate::nats_comm::ConnectionStats;
+
+struct MetricInfo {
+    name: &'static str,
+    kind: &'static str,
+    help: &'static str,
+}
+
+const MESSAGES_PROCESSED: MetricInfo = MetricInfo {
+    name: "agent_messages_processed_total",
+    kind: "counter",
+    help: "Messages received by each agent",
+};
+const LLM_OPERATIONS: MetricInfo = MetricInfo {
+    name: "agent_llm_operations_total",
+    kind: "counter",
+    help: "Finished LLM operations by agent and status",
+};
+const SCRAPES: MetricInfo = MetricInfo {
+    name: "agent_scrapes_total",
+    kind: "counter",
+    help: "Scraping tasks by agent and outcome",
+};
+const NATS_MESSAGES_SENT: MetricInfo = MetricInfo {
+    name: "nats_messages_sent_total",
+    kind: "counter",
+    help: "Messages published on the NATS connection",
+};
+const NATS_MESSAGES_RECEIVED: MetricInfo = MetricInfo {
+    name: "nats_messages_received_total",
+    kind: "counter",
+    help: "Messages received on the NATS connection",
+};
+const NATS_BYTES_SENT: MetricInfo = MetricInfo {
+    name: "nats_bytes_sent_total",
+    kind: "counter",
+    help: "Payload bytes published on the NATS connection",
+};
+const NATS_BYTES_RECEIVED: MetricInfo = MetricInfo {
+    name: "nats_bytes_received_total",
+    kind: "counter",
+    help: "Payload bytes received on the NATS connection",
+};
+const NATS_RECONNECTS: MetricInfo = MetricInfo {
+    name: "nats_reconnects",
+    kind: "gauge",
+    help: "Reconnects since the NATS connection was first established",
+};
+const NATS_MESSAGES_DROPPED: MetricInfo = MetricInfo {
+    name: "nats_messages_dropped_total",
+    kind: "counter",
+    help: "Messages dropped because a bounded subscription's buffer was full",
+};
+
+const ALL_METRICS: [&MetricInfo; 9] = [
+    &MESSAGES_PROCESSED,
+    &LLM_OPERATIONS,
+    &SCRAPES,
+    &NATS_MESSAGES_SENT,
+    &NATS_MESSAGES_RECEIVED,
+    &NATS_BYTES_SENT,
+    &NATS_BYTES_RECEIVED,
+    &NATS_RECONNECTS,
+    &NATS_MESSAGES_DROPPED,
+];
+
+// (metric name, sorted label pairs) -> value
+type Series = BTreeMap<(&'static str, Vec<(&'static str, String)>), u64>;
+
+static REGISTRY: Mutex<Series> = Mutex::new(BTreeMap::new());
+
+fn add(metric: &MetricInfo, labels: Vec<(&'static str, String)>, delta: u64) {
+    *REGISTRY.lock().unwrap().entry((metric.name, labels)).or_insert(0) += delta;
+}
+
+fn set(metric: &MetricInfo, value: u64) {
+    REGISTRY.lock().unwrap().insert((metric.name, Vec::new()), value);
+}
+
+pub fn record_message_processed(agent_id: &str) {
+    add(&MESSAGES_PROCESSED, vec![("agent_id", agent_id.to_string())], 1);
+}
+
+pub fn record_llm_operation(agent_id: &str, status: &str) {
+    add(&LLM_OPERATIONS, vec![("agent_id", agent_id.to_string()), ("status", status.to_string())], 1);
+}
+
+pub fn record_scrape(agent_id: &str, success: bool) {
+    let outcome = if success { "success" } else { "failure" };
+    add(&SCRAPES, vec![("agent_id", agent_id.to_string()), ("outcome", outcome.to_string())], 1);
+}
+
+/// Copy the connection's cumulative counters into the registry
+pub fn record_nats_stats(stats: &ConnectionStats) {
+    set(&NATS_MESSAGES_SENT, stats.messages_sent);
+    set(&NATS_MESSAGES_RECEIVED, stats.messages_received);
+    set(&NATS_BYTES_SENT, stats.bytes_sent);
+    set(&NATS_BYTES_RECEIVED, stats.bytes_received);
+    set(&NATS_RECONNECTS, stats.reconnects);
+    set(&NATS_MESSAGES_DROPPED, stats.dropped_messages);
+}
+
+/// One recorded series, in a form that can be sent between processes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetricSample {
+    pub name: String,
+    pub labels: Vec<(String, String)>,
+    pub value: u64,
+}
+
+/// Every series recorded in this process
+pub fn export_samples() -> Vec<MetricSample> {
+    REGISTRY.lock().unwrap().iter()
+        .map(|((name, labels), value)| MetricSample {
+            name: name.to_string(),
+            labels: labels.iter().map(|(key, value)| (key.to_string(), value.clone())).collect(),
+            value: *value,
+        })
+        .collect()
+}
+
+/// The series this process recorded for `agent_id`
+pub fn agent_samples(agent_id: &str) -> Vec<MetricSample> {
+    export_samples().into_iter()
+        .filter(|sample| sample.labels.iter().any(|(key, value)| key == "agent_id" && value == agent_id))
+        .collect()
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Render every series recorded in this process in the Prometheus text exposition format
+pub fn metrics_snapshot() -> String {
+    render_samples(export_samples())
+}
+
+/// Render samples gathered from one or more processes. A series reported more than once,
+/// e.g. by agents sharing a native registry, keeps the last value.
+pub fn render_samples(samples: impl IntoIterator<Item = MetricSample>) -> String {
+    let registry: BTreeMap<(String, Vec<(String, String)>), u64> = samples.into_iter()
+        .map(|sample| ((sample.name, sample.labels), sample.value))
+        .collect();
+    let mut output = String::new();
+
+    for metric in ALL_METRICS {
+        let mut series = registry.iter()
+            .filter(|((name, _), _)| name == metric.name)
+            .peekable();
+        if series.peek().is_none() {
+            continue;
+        }
+
+        let _ = writeln!(output, "# HELP {} {}", metric.name, metric.help);
+        let _ = writeln!(output, "# TYPE {} {}", metric.name, metric.kind);
+        for ((name, labels), value) in series {
+            if labels.is_empty() {
+                let _ = writeln!(output, "{} {}", name, value);
+            } else {
+                let labels: Vec<String> = labels.iter()
+                    .map(|(key, value)| format!("{}=\"{}\"", key, escape_label(value)))
+                    .collect();
+                let _ = writeln!(output, "{}{{{}}} {}", name, labels.join(","), value);
+            }
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_renders_nats_stats() {
+        record_nats_stats(&ConnectionStats {
+            messages_sent: 7,
+            messages_received: 3,
+            bytes_sent: 700,
+            bytes_received: 300,
+            connects: 1,
+            reconnects: 0,
+            last_reconnect_at: None,
+            dropped_messages: 0,
+        });
+
+        let snapshot = metrics_snapshot();
+        assert!(snapshot.contains("# TYPE nats_messages_sent_total counter\nnats_messages_sent_total 7\n"));
+        assert!(snapshot.contains("nats_reconnects 0\n"));
+    }
+
+    #[test]
+    fn test_label_values_are_escaped() {
+        record_scrape("quote\"agent", false);
+        assert!(metrics_snapshot().contains(r#"agent_scrapes_total{agent_id="quote\"agent",outcome="failure"} 1"#));
+    }
+
+    #[test]
+    fn test_render_merges_samples_from_several_processes() {
+        let sample = |agent_id: &str, value: u64| MetricSample {
+            name: MESSAGES_PROCESSED.name.to_string(),
+            labels: vec![("agent_id".to_string(), agent_id.to_string())],
+            value,
+        };
+
+        let rendered = render_samples(vec![sample("a", 2), sample("b", 5), sample("a", 2)]);
+        assert_eq!(rendered.matches("# TYPE agent_messages_processed_total").count(), 1);
+        assert!(rendered.contains("agent_messages_processed_total{agent_id=\"a\"} 2\n"));
+        assert!(rendered.contains("agent_messages_processed_total{agent_id=\"b\"} 5\n"));
+    }
+}
@@ -7,28 +7,92 @@ use async_nats::{Client, ConnectOptions, Message as NatsMessage};
 #[cfg(feature = "nats")]
 use futures::StreamExt;
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
-#[cfg(feature = "nats")]
-use std::sync::atomic::Ordering;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 #[cfg(feature = "nats")]
 use bytes::Bytes;
 use crate::{Result, Error};
+use crate::agent::{AgentId, Message, Serializer};
+use crate::supervisor::AgentType;
 
 #[derive(Debug, Clone)]
 pub struct NatsConfig {
     pub url: String,
     pub timeout: Duration,
     pub max_reconnects: Option<usize>,
-    pub reconnect_delay: Duration,
+    pub reconnect_backoff: BackoffStrategy,
+    // Payloads larger than this are gzip-compressed before publishing
+    pub compress_threshold_bytes: usize,
+    // Chunk payloads over this size; the server's max_payload applies when lower or unset
+    pub max_payload_bytes: Option<usize>,
+    // Largest payload that will be chunked or reassembled
+    pub max_message_bytes: usize,
+}
+
+/// Delay before each reconnect attempt, used by both `NatsConnection` and `WasmNatsConnection`.
+/// Attempts count from 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BackoffStrategy {
+    /// The same delay before every attempt
+    Fixed(Duration),
+    /// `step` times the attempt number, up to `max`
+    Linear { step: Duration, max: Duration },
+    /// `base` doubled on every attempt, up to `max`. With `jitter` the delay is picked at
+    /// random between zero and that value, so clients dropped together don't reconnect together.
+    Exponential { base: Duration, max: Duration, jitter: bool },
+}
+
+impl Default for BackoffStrategy {
+    // One more second per attempt, capped at 30s
+    fn default() -> Self {
+        BackoffStrategy::Linear { step: Duration::from_secs(1), max: Duration::from_secs(30) }
+    }
+}
+
+impl BackoffStrategy {
+    /// Delay before reconnect attempt `attempt`
+    pub fn delay(&self, attempt: usize) -> Duration {
+        // The low 53 bits of a v4 UUID are random, giving a uniform fraction in [0, 1)
+        let random = (uuid::Uuid::new_v4().as_u128() & ((1 << 53) - 1)) as f64 / (1u64 << 53) as f64;
+        self.delay_with_jitter(attempt, random)
+    }
+
+    // `delay` with the random fraction passed in, so tests get a fixed sequence
+    fn delay_with_jitter(&self, attempt: usize, random: f64) -> Duration {
+        match *self {
+            BackoffStrategy::Fixed(delay) => delay,
+            BackoffStrategy::Linear { step, max } => {
+                step.saturating_mul(u32::try_from(attempt).unwrap_or(u32::MAX)).min(max)
+            }
+            BackoffStrategy::Exponential { base, max, jitter } => {
+                let doublings = u32::try_from(attempt.saturating_sub(1)).unwrap_or(u32::MAX).min(31);
+                let delay = base.saturating_mul(1u32 << doublings).min(max);
+                if jitter {
+                    delay.mul_f64(random.clamp(0.0, 1.0))
+                } else {
+                    delay
+                }
+            }
+        }
+    }
 }
 
+pub const DEFAULT_COMPRESS_THRESHOLD_BYTES: usize = 256 * 1024;
+pub const DEFAULT_MAX_MESSAGE_BYTES: usize = 64 * 1024 * 1024;
+
 impl Default for NatsConfig {
     fn default() -> Self {
         Self {
             url: "nats://localhost:4222".to_string(),
             timeout: Duration::from_secs(10),
             max_reconnects: Some(10),
-            reconnect_delay: Duration::from_secs(1),
+            reconnect_backoff: BackoffStrategy::default(),
+            compress_threshold_bytes: DEFAULT_COMPRESS_THRESHOLD_BYTES,
+            max_payload_bytes: None,
+            max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
         }
     }
 }
@@ -47,29 +111,512 @@ impl NatsConfig {
             max_reconnects: std::env::var("NATS_MAX_RECONNECTS")
                 .ok()
                 .and_then(|s| s.parse().ok()),
-            reconnect_delay: Duration::from_secs(
-                std::env::var("NATS_RECONNECT_DELAY_SECONDS")
-                    .unwrap_or_else(|_| "1".to_string())
-                    .parse()
-                    .unwrap_or(1)
-            ),
+            // A fixed delay when set, otherwise the default backoff
+            reconnect_backoff: std::env::var("NATS_RECONNECT_DELAY_SECONDS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .map(|secs| BackoffStrategy::Fixed(Duration::from_secs(secs)))
+                .unwrap_or_default(),
+            compress_threshold_bytes: std::env::var("NATS_COMPRESS_THRESHOLD_BYTES")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(DEFAULT_COMPRESS_THRESHOLD_BYTES),
+            max_payload_bytes: std::env::var("NATS_MAX_PAYLOAD_BYTES")
+                .ok()
+                .and_then(|s| s.parse().ok()),
+            max_message_bytes: std::env::var("NATS_MAX_MESSAGE_BYTES")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(DEFAULT_MAX_MESSAGE_BYTES),
         })
     }
 }
 
+/// Header marking a gzip-compressed payload; a leading NUL never starts JSON or a tagged message
+const COMPRESSED_MAGIC: &[u8] = b"\0NZ1";
+
+/// Gzip `data` behind the compressed-payload header
+pub fn compress_payload(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = flate2::write::GzEncoder::new(COMPRESSED_MAGIC.to_vec(), flate2::Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Undo [`compress_payload`]; payloads without the header are returned unchanged
+pub fn decompress_payload(data: &[u8]) -> Result<Vec<u8>> {
+    let Some(compressed) = data.strip_prefix(COMPRESSED_MAGIC) else {
+        return Ok(data.to_vec());
+    };
+    let mut decompressed = Vec::new();
+    flate2::read::GzDecoder::new(compressed).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+/// Header marking one chunk of an oversized payload: `CHUNK_MAGIC`, a JSON `ChunkHeader`, `\n`, then the bytes
+const CHUNK_MAGIC: &[u8] = b"\0NC1";
+/// Header marking the manifest published after a payload's chunks
+const MANIFEST_MAGIC: &[u8] = b"\0NM1";
+
+/// Subject carrying chunk `index` of a payload published to `subject`
+pub fn chunk_subject(subject: &str, index: usize) -> String {
+    format!("{}.chunk.{}", subject, index)
+}
+
+/// Describes a payload that was split into chunks
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkManifest {
+    pub message_id: String,
+    pub chunks: usize,
+    pub total_bytes: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ChunkHeader {
+    message_id: String,
+    index: usize,
+}
+
+/// Split `data` into chunk frames no larger than `max_payload` bytes, plus the framed manifest
+pub fn chunk_payload(message_id: &str, data: &[u8], max_payload: usize) -> Result<(Vec<Vec<u8>>, Vec<u8>)> {
+    let mut chunks = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let mut frame = CHUNK_MAGIC.to_vec();
+        serde_json::to_writer(&mut frame, &ChunkHeader { message_id: message_id.to_string(), index: chunks.len() })?;
+        frame.push(b'\n');
+        if frame.len() >= max_payload {
+            return Err(Error::Nats(format!("max_payload of {} bytes cannot fit a chunk header", max_payload)));
+        }
+        let (head, tail) = rest.split_at(rest.len().min(max_payload - frame.len()));
+        frame.extend_from_slice(head);
+        chunks.push(frame);
+        rest = tail;
+    }
+
+    let manifest = ChunkManifest { message_id: message_id.to_string(), chunks: chunks.len(), total_bytes: data.len() };
+    let mut manifest_frame = MANIFEST_MAGIC.to_vec();
+    serde_json::to_writer(&mut manifest_frame, &manifest)?;
+    Ok((chunks, manifest_frame))
+}
+
+/// How long a partially received payload is kept before its chunks are given up on
+pub const DEFAULT_CHUNK_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct PendingPayload {
+    started_at: Instant,
+    manifest: Option<ChunkManifest>,
+    chunks: HashMap<usize, Vec<u8>>,
+}
+
+impl PendingPayload {
+    fn new() -> Self {
+        Self { started_at: Instant::now(), manifest: None, chunks: HashMap::new() }
+    }
+}
+
+/// Rebuilds chunked payloads. Chunks and manifests arrive on separate subjects, so
+/// either may come first; everything is buffered by message id until complete.
+/// Payloads whose chunks were lost are dropped after the TTL, or oldest first when
+/// the buffer needs room for newer chunks.
+#[derive(Debug)]
+pub struct ChunkAssembler {
+    pending: HashMap<String, PendingPayload>,
+    buffered_bytes: usize,
+    max_message_bytes: usize,
+    ttl: Duration,
+}
+
+impl ChunkAssembler {
+    /// `max_message_bytes` bounds both a single payload and everything buffered at once
+    pub fn new(max_message_bytes: usize) -> Self {
+        Self { pending: HashMap::new(), buffered_bytes: 0, max_message_bytes, ttl: DEFAULT_CHUNK_TTL }
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Feed one received payload. Ordinary payloads come straight back; chunk frames and
+    /// manifests return `None` until the payload they belong to is complete.
+    pub fn accept(&mut self, payload: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.evict_expired();
+        if let Some(manifest) = payload.strip_prefix(MANIFEST_MAGIC) {
+            let manifest: ChunkManifest = serde_json::from_slice(manifest)?;
+            if manifest.total_bytes > self.max_message_bytes {
+                self.discard(&manifest.message_id);
+                return Err(Error::Nats(format!(
+                    "Chunked message {} is {} bytes, over the limit of {} bytes",
+                    manifest.message_id, manifest.total_bytes, self.max_message_bytes
+                )));
+            }
+            let id = manifest.message_id.clone();
+            self.pending.entry(id.clone()).or_insert_with(PendingPayload::new).manifest = Some(manifest);
+            return Ok(self.complete(&id));
+        }
+
+        let Some(frame) = payload.strip_prefix(CHUNK_MAGIC) else {
+            return Ok(Some(payload.to_vec()));
+        };
+        let split = frame.iter().position(|&b| b == b'\n')
+            .ok_or_else(|| Error::Nats("Chunk frame has no header terminator".to_string()))?;
+        let header: ChunkHeader = serde_json::from_slice(&frame[..split])?;
+        let data = &frame[split + 1..];
+        // Make room by giving up on the oldest other partial payloads first
+        while self.buffered_bytes + data.len() > self.max_message_bytes {
+            let oldest = self.pending.iter()
+                .filter(|(id, _)| **id != header.message_id)
+                .min_by_key(|(_, pending)| pending.started_at)
+                .map(|(id, _)| id.clone());
+            let Some(oldest) = oldest else { break };
+            log::warn!("Evicting partial chunked message {} to buffer {}", oldest, header.message_id);
+            self.discard(&oldest);
+        }
+        if self.buffered_bytes + data.len() > self.max_message_bytes {
+            self.discard(&header.message_id);
+            return Err(Error::Nats(format!(
+                "Dropping chunked message {}: buffered chunks would exceed {} bytes",
+                header.message_id, self.max_message_bytes
+            )));
+        }
+        let pending = self.pending.entry(header.message_id.clone()).or_insert_with(PendingPayload::new);
+        if pending.chunks.insert(header.index, data.to_vec()).is_none() {
+            self.buffered_bytes += data.len();
+        }
+        Ok(self.complete(&header.message_id))
+    }
+
+    /// Number of payloads still waiting for chunks or a manifest
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn complete(&mut self, message_id: &str) -> Option<Vec<u8>> {
+        let pending = self.pending.get(message_id)?;
+        let chunks = pending.manifest.as_ref()?.chunks;
+        if (0..chunks).any(|index| !pending.chunks.contains_key(&index)) {
+            return None;
+        }
+        let mut pending = self.pending.remove(message_id)?;
+        let mut data = Vec::new();
+        for index in 0..chunks {
+            data.extend(pending.chunks.remove(&index)?);
+        }
+        self.buffered_bytes -= data.len() + pending.chunks.values().map(Vec::len).sum::<usize>();
+        Some(data)
+    }
+
+    fn discard(&mut self, message_id: &str) {
+        if let Some(pending) = self.pending.remove(message_id) {
+            self.buffered_bytes -= pending.chunks.values().map(Vec::len).sum::<usize>();
+        }
+    }
+
+    // Drop payloads that have waited longer than the TTL for their remaining chunks
+    fn evict_expired(&mut self) {
+        let expired: Vec<String> = self.pending.iter()
+            .filter(|(_, pending)| pending.started_at.elapsed() >= self.ttl)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in expired {
+            log::warn!("Chunked message {} expired before all chunks arrived", id);
+            self.discard(&id);
+        }
+    }
+}
+
+/// Reject ids that would turn a subject into a wildcard or an invalid subject.
+/// Dots are allowed and split the id into several tokens, but none may be empty.
+pub fn validate_subject_id(id: &str) -> Result<()> {
+    if id.is_empty() {
+        return Err(Error::Nats("subject id is empty".to_string()));
+    }
+    if let Some(c) = id.chars().find(|c| *c == '*' || *c == '>' || c.is_whitespace()) {
+        return Err(Error::Nats(format!("subject id {:?} contains invalid character {:?}", id, c)));
+    }
+    if id.split('.').any(str::is_empty) {
+        return Err(Error::Nats(format!("subject id {:?} contains an empty token", id)));
+    }
+    Ok(())
+}
+
+/// Subject an agent receives direct messages on
+pub fn subject_for_agent(id: &AgentId) -> Result<String> {
+    validate_subject_id(&id.0)?;
+    Ok(format!("agent.{}", id.0))
+}
+
+/// Subject shared by every agent of a type, e.g. `type.datacollector`
+pub fn type_subject(agent_type: &AgentType) -> String {
+    format!("type.{}", format!("{:?}", agent_type).to_lowercase())
+}
+
+pub const DISCOVERY_REGISTER_SUBJECT: &str = "discovery.register";
+pub const DISCOVERY_DEREGISTER_SUBJECT: &str = "discovery.deregister";
+
+/// How often a live registration is re-announced on `discovery.register`
+pub const DISCOVERY_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Subject a node answers location queries for one of its agents on
+pub fn discovery_query_subject(id: &AgentId) -> Result<String> {
+    validate_subject_id(&id.0)?;
+    Ok(format!("discovery.query.{}", id.0))
+}
+
+/// Where an agent lives in a multi-node deployment
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AgentLocation {
+    pub agent_id: String,
+    pub node_id: String,
+    pub agent_type: String,
+    pub subjects: Vec<String>,
+}
+
+/// Announces this node's agents on `discovery.register` and answers
+/// `discovery.query.<agent_id>` requests for them
+#[derive(Debug, Clone)]
+pub struct DiscoveryService {
+    node_id: String,
+}
+
+impl DiscoveryService {
+    pub fn new(node_id: impl Into<String>) -> Self {
+        Self { node_id: node_id.into() }
+    }
+
+    /// Node id from `NODE_ID`, falling back to `local`
+    pub fn from_env() -> Self {
+        Self::new(std::env::var("NODE_ID").unwrap_or_else(|_| "local".to_string()))
+    }
+
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    pub fn location(&self, id: &AgentId, agent_type: &AgentType) -> Result<AgentLocation> {
+        Ok(AgentLocation {
+            agent_id: id.0.clone(),
+            node_id: self.node_id.clone(),
+            agent_type: format!("{:?}", agent_type),
+            subjects: vec![subject_for_agent(id)?, type_subject(agent_type)],
+        })
+    }
+
+    /// Publish the registration and start answering queries for the agent. The registration
+    /// is refreshed until it is dropped or a matching `discovery.deregister` arrives.
+    pub async fn register(&self, nats: &NatsConnection, id: &AgentId, agent_type: &AgentType) -> Result<AgentRegistration> {
+        let location = self.location(id, agent_type)?;
+        let data = serde_json::to_vec(&location)?;
+        nats.publish(DISCOVERY_REGISTER_SUBJECT, &data).await?;
+        let registration = nats.serve_location(&location).await?;
+
+        log::info!("Registered agent {} on node {}", id.0, self.node_id);
+        Ok(registration)
+    }
+
+    /// Announce that the agent is gone; the node serving it stops answering queries
+    pub async fn deregister(&self, nats: &NatsConnection, id: &AgentId, agent_type: &AgentType) -> Result<()> {
+        let data = serde_json::to_vec(&self.location(id, agent_type)?)?;
+        nats.publish(DISCOVERY_DEREGISTER_SUBJECT, &data).await?;
+
+        log::info!("Deregistered agent {} on node {}", id.0, self.node_id);
+        Ok(())
+    }
+}
+
+/// A live discovery registration. Queries are answered and the registration refreshed
+/// until the agent deregisters or this handle is dropped.
+#[cfg(feature = "nats")]
+#[derive(Debug)]
+pub struct AgentRegistration {
+    location: AgentLocation,
+    task: tokio::task::JoinHandle<()>,
+}
+
+#[cfg(feature = "nats")]
+impl AgentRegistration {
+    pub fn location(&self) -> &AgentLocation {
+        &self.location
+    }
+
+    /// Resolves once the registration stops being served
+    pub async fn closed(&mut self) {
+        let _ = (&mut self.task).await;
+    }
+}
+
+#[cfg(feature = "nats")]
+impl Drop for AgentRegistration {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(not(feature = "nats"))]
+#[derive(Debug)]
+pub struct AgentRegistration {
+    location: AgentLocation,
+}
+
+#[cfg(not(feature = "nats"))]
+impl AgentRegistration {
+    pub fn location(&self) -> &AgentLocation {
+        &self.location
+    }
+
+    pub async fn closed(&mut self) {
+        futures::future::pending::<()>().await
+    }
+}
+
+/// Live subscription delivering agent messages from one or more subjects
+#[cfg(feature = "nats")]
+pub struct AgentSubscription {
+    subjects: Vec<String>,
+    stream: futures::stream::SelectAll<async_nats::Subscriber>,
+    assembler: ChunkAssembler,
+}
+
+#[cfg(feature = "nats")]
+impl AgentSubscription {
+    pub fn subjects(&self) -> &[String] {
+        &self.subjects
+    }
+
+    /// Next agent message from any subject, reassembling chunked payloads;
+    /// payloads that don't parse are skipped
+    pub async fn next(&mut self) -> Option<Message> {
+        while let Some(msg) = self.stream.next().await {
+            let payload = match self.assembler.accept(&msg.payload) {
+                Ok(Some(payload)) => payload,
+                Ok(None) => continue,
+                Err(e) => {
+                    log::warn!("Dropping chunk on {}: {}", msg.subject, e);
+                    continue;
+                }
+            };
+            match decompress_payload(&payload).and_then(|data| Message::decode(&data)) {
+                Ok(parsed) => return Some(parsed),
+                Err(e) => log::warn!("Dropping unparsable message on {}: {}", msg.subject, e),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(not(feature = "nats"))]
+#[derive(Debug)]
+pub struct AgentSubscription {
+    subjects: Vec<String>,
+}
+
+#[cfg(not(feature = "nats"))]
+impl AgentSubscription {
+    pub fn subjects(&self) -> &[String] {
+        &self.subjects
+    }
+
+    pub async fn next(&mut self) -> Option<Message> {
+        None
+    }
+}
+
+/// Producer side of a bounded subscription buffer. Once the consumer is `capacity` messages
+/// behind, new messages are dropped and counted, the way the server treats a slow consumer.
+#[derive(Debug)]
+pub struct BoundedSender<T> {
+    sender: futures::channel::mpsc::Sender<T>,
+    dropped: Arc<AtomicU64>,
+    // Shared with the connection so its stats cover every bounded subscription
+    connection_dropped: Arc<AtomicU64>,
+}
+
+impl<T> BoundedSender<T> {
+    /// Queue `item`, or drop and count it when the buffer is full. Returns whether it was queued.
+    pub fn offer(&mut self, item: T) -> bool {
+        match self.sender.try_send(item) {
+            Ok(()) => true,
+            Err(e) if e.is_full() => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                self.connection_dropped.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+            // Receiver gone; nobody is counting any more
+            Err(_) => false,
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.sender.is_closed()
+    }
+}
+
+/// Consumer side of a bounded subscription
+#[derive(Debug)]
+pub struct BoundedReceiver<T> {
+    receiver: futures::channel::mpsc::Receiver<T>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl<T> BoundedReceiver<T> {
+    /// Next buffered item, waiting for one; `None` once the subscription ends
+    pub async fn next(&mut self) -> Option<T> {
+        futures::StreamExt::next(&mut self.receiver).await
+    }
+
+    /// Next buffered item without waiting
+    pub fn try_next(&mut self) -> Option<T> {
+        self.receiver.try_next().ok().flatten()
+    }
+
+    /// Messages this subscription dropped because the buffer was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// A channel holding at most `capacity` items (at least 1). Drops are added to
+/// `connection_dropped` as well as the receiver's own counter.
+pub fn bounded_channel<T>(capacity: usize, connection_dropped: Arc<AtomicU64>) -> (BoundedSender<T>, BoundedReceiver<T>) {
+    // An mpsc channel holds `buffer` items plus one slot per sender, and there is only one sender
+    let (sender, receiver) = futures::channel::mpsc::channel(capacity.max(1) - 1);
+    let dropped = Arc::new(AtomicU64::new(0));
+    (
+        BoundedSender { sender, dropped: dropped.clone(), connection_dropped },
+        BoundedReceiver { receiver, dropped },
+    )
+}
+
 #[cfg(feature = "nats")]
 #[derive(Debug)]
 pub struct NatsConnection {
     client: Client,
     config: NatsConfig,
+    // Set once `drain` starts; later publishes are rejected
+    draining: AtomicBool,
+    serializer: Serializer,
+    // Unix seconds of the most recent `Connected` event, 0 if none seen
+    last_connected_at: Arc<AtomicU64>,
+    // Messages dropped by this client's bounded subscriptions
+    dropped_messages: Arc<AtomicU64>,
 }
 
 #[cfg(not(feature = "nats"))]
 #[derive(Debug)]
 pub struct NatsConnection {
     config: NatsConfig,
+    // Lets tests simulate a broken connection
+    fail_publish: bool,
+    draining: AtomicBool,
+    serializer: Serializer,
+    dropped_messages: Arc<AtomicU64>,
 }
 
+/// How often `wait_ready` re-checks the connection state
+#[cfg(feature = "nats")]
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
 #[cfg(feature = "nats")]
 impl NatsConnection {
     pub async fn new(config: NatsConfig) -> Result<Self> {
@@ -79,42 +626,149 @@ impl NatsConnection {
             connect_options = connect_options.max_reconnects(max_reconnects);
         }
         
+        let last_connected_at = Arc::new(AtomicU64::new(0));
+        let connected_at = last_connected_at.clone();
+        let backoff = config.reconnect_backoff;
+
         connect_options = connect_options
             .connection_timeout(config.timeout)
-            .reconnect_delay_callback(move |attempts| {
-                std::cmp::min(Duration::from_secs(attempts as u64), Duration::from_secs(30))
+            .reconnect_delay_callback(move |attempts| backoff.delay(attempts))
+            .event_callback(move |event| {
+                let connected_at = connected_at.clone();
+                async move {
+                    if let async_nats::Event::Connected = event {
+                        connected_at.store(chrono::Utc::now().timestamp() as u64, Ordering::Relaxed);
+                    }
+                }
             });
 
         let client = connect_options.connect(&config.url).await
-            .map_err(|e| Error::Nats(format!("Failed to connect to NATS: {}", e)))?;
+            .map_err(|e| Error::nats(format!("Failed to connect to NATS: {}", e), e))?;
 
-        log::info!("Successfully connected to NATS at {}", config.url);
-
-        Ok(Self {
+        let connection = Self {
             client,
             config,
+            draining: AtomicBool::new(false),
+            serializer: Serializer::default(),
+            last_connected_at,
+            dropped_messages: Arc::new(AtomicU64::new(0)),
+        };
+        // `connect` can resolve before the handshake finishes; publishing then would race it
+        connection.wait_ready(connection.config.timeout).await?;
+
+        log::info!("Successfully connected to NATS at {}", connection.config.url);
+        Ok(connection)
+    }
+
+    /// Wait until the client reports `Connected`, failing with `Error::Nats` after `timeout`
+    pub async fn wait_ready(&self, timeout: Duration) -> Result<()> {
+        let ready = async {
+            while !self.is_connected() {
+                tokio::time::sleep(READY_POLL_INTERVAL).await;
+            }
+        };
+        tokio::time::timeout(timeout, ready).await.map_err(|_| {
+            Error::Nats(format!("NATS connection to {} not ready after {:?}", self.config.url, timeout))
         })
     }
 
+    /// Encoding used for agent messages published through this connection
+    pub fn with_serializer(mut self, serializer: Serializer) -> Self {
+        self.serializer = serializer;
+        self
+    }
+
+    /// Another handle on the same underlying client. Handles publish independently,
+    /// but draining any of them drains the shared client.
+    pub fn handle(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            config: self.config.clone(),
+            draining: AtomicBool::new(false),
+            serializer: self.serializer,
+            last_connected_at: self.last_connected_at.clone(),
+            dropped_messages: self.dropped_messages.clone(),
+        }
+    }
+
+    /// Encode an agent message with the connection's serializer and publish it
+    pub async fn publish_message(&self, subject: &str, message: &Message) -> Result<()> {
+        let data = message.encode(self.serializer)?;
+        self.publish(subject, &data).await
+    }
+
     pub async fn publish(&self, subject: &str, data: &[u8]) -> Result<()> {
-        let data_bytes = Bytes::copy_from_slice(data);
-        self.client.publish(subject.to_string(), data_bytes).await
-            .map_err(|e| Error::Nats(format!("Failed to publish: {}", e)))?;
+        if self.draining.load(Ordering::Acquire) {
+            return Err(Error::Nats(format!("Cannot publish to {}: connection is draining", subject)));
+        }
+
+        let payload = if data.len() > self.config.compress_threshold_bytes {
+            let compressed = compress_payload(data)?;
+            log::debug!("Compressed payload for {} from {} to {} bytes", subject, data.len(), compressed.len());
+            compressed
+        } else {
+            data.to_vec()
+        };
+
+        // The server drops oversized publishes without telling the publisher, so split them
+        let max_payload = self.max_payload();
+        if payload.len() > max_payload {
+            return self.publish_chunked(subject, &payload, max_payload).await;
+        }
+
+        self.client.publish(subject.to_string(), Bytes::from(payload)).await
+            .map_err(|e| Error::nats(format!("Failed to publish: {}", e), e))?;
         
         log::debug!("Published message to subject: {}", subject);
         Ok(())
     }
 
+    /// Largest single publish: the configured limit, capped by the server's max_payload
+    pub fn max_payload(&self) -> usize {
+        let server_max = self.client.server_info().max_payload;
+        match (self.config.max_payload_bytes, server_max) {
+            (Some(configured), 0) => configured,
+            (Some(configured), server) => configured.min(server),
+            (None, 0) => usize::MAX,
+            (None, server) => server,
+        }
+    }
+
+    // Publish each chunk to `<subject>.chunk.<n>`, then the manifest to `subject`
+    async fn publish_chunked(&self, subject: &str, payload: &[u8], max_payload: usize) -> Result<()> {
+        if payload.len() > self.config.max_message_bytes {
+            return Err(Error::Nats(format!(
+                "Payload for {} is {} bytes after compression, over the max_message_bytes of {} bytes",
+                subject, payload.len(), self.config.max_message_bytes
+            )));
+        }
+
+        let message_id = uuid::Uuid::new_v4().to_string();
+        let (chunks, manifest) = chunk_payload(&message_id, payload, max_payload)?;
+        let count = chunks.len();
+        for (index, chunk) in chunks.into_iter().enumerate() {
+            self.client.publish(chunk_subject(subject, index), Bytes::from(chunk)).await
+                .map_err(|e| Error::nats(format!("Failed to publish chunk: {}", e), e))?;
+        }
+        self.client.publish(subject.to_string(), Bytes::from(manifest)).await
+            .map_err(|e| Error::nats(format!("Failed to publish chunk manifest: {}", e), e))?;
+
+        log::debug!("Published {} byte payload to {} in {} chunks", payload.len(), subject, count);
+        Ok(())
+    }
+
+    /// Poll `subject` once for up to 100ms. Chunked messages are not reassembled here;
+    /// use `subscribe_messages` or `subscribe_bounded` for subjects that may carry them.
     pub async fn subscribe(&self, subject: &str) -> Result<Vec<crate::agent::Message>> {
         let mut subscriber = self.client.subscribe(subject.to_string()).await
-            .map_err(|e| Error::Nats(format!("Failed to subscribe: {}", e)))?;
+            .map_err(|e| Error::nats(format!("Failed to subscribe: {}", e), e))?;
 
         let mut messages = Vec::new();
         
         // Non-blocking check for messages with timeout
         match tokio::time::timeout(Duration::from_millis(100), subscriber.next()).await {
             Ok(Some(msg)) => {
-                match serde_json::from_slice::<crate::agent::Message>(&msg.payload) {
+                match decompress_payload(&msg.payload).and_then(|data| Message::decode(&data)) {
                     Ok(parsed_msg) => {
                         messages.push(parsed_msg);
                         log::debug!("Received message from subject: {}", subject);
@@ -134,31 +788,179 @@ impl NatsConnection {
         Ok(messages)
     }
 
+    /// Subscribe to `subject`, buffering at most `capacity` decoded messages. While the
+    /// buffer is full, newer messages are dropped and counted in `get_stats().dropped_messages`.
+    /// Chunked messages are reassembled before they count against the buffer.
+    pub async fn subscribe_bounded(&self, subject: &str, capacity: usize) -> Result<BoundedReceiver<Message>> {
+        let mut stream = futures::stream::SelectAll::new();
+        for subscribed in [subject.to_string(), format!("{}.chunk.*", subject)] {
+            let subscriber = self.client.subscribe(subscribed).await
+                .map_err(|e| Error::nats(format!("Failed to subscribe: {}", e), e))?;
+            stream.push(subscriber);
+        }
+        let (mut sender, receiver) = bounded_channel(capacity, self.dropped_messages.clone());
+        let mut assembler = ChunkAssembler::new(self.config.max_message_bytes);
+
+        let subscribed = subject.to_string();
+        tokio::spawn(async move {
+            // Dropping the subscribers when the receiver goes away unsubscribes
+            while let Some(msg) = stream.next().await {
+                if sender.is_closed() {
+                    break;
+                }
+                let payload = match assembler.accept(&msg.payload) {
+                    Ok(Some(payload)) => payload,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        log::warn!("Dropping chunk on {}: {}", msg.subject, e);
+                        continue;
+                    }
+                };
+                match decompress_payload(&payload).and_then(|data| Message::decode(&data)) {
+                    Ok(parsed) => {
+                        if !sender.offer(parsed) {
+                            log::debug!("Bounded subscription on {} is full, dropped a message", subscribed);
+                        }
+                    }
+                    Err(e) => log::warn!("Dropping unparsable message on {}: {}", subscribed, e),
+                }
+            }
+        });
+
+        log::debug!("Subscribed to {} with a {} message buffer", subject, capacity);
+        Ok(receiver)
+    }
+
     pub async fn request(&self, subject: &str, data: &[u8]) -> Result<Vec<u8>> {
         let data_bytes = Bytes::copy_from_slice(data);
         let response = self.client
             .request(subject.to_string(), data_bytes).await
-            .map_err(|e| Error::Nats(format!("Failed to send request: {}", e)))?;
+            .map_err(|e| Error::nats(format!("Failed to send request: {}", e), e))?;
         
         log::debug!("Received response from request to subject: {}", subject);
         Ok(response.payload.to_vec())
     }
 
+    /// Subscribe to agent messages on every subject in `subjects`, plus the chunk subjects
+    /// that oversized messages are split across
+    pub async fn subscribe_messages(&self, subjects: Vec<String>) -> Result<AgentSubscription> {
+        let mut stream = futures::stream::SelectAll::new();
+        for subject in &subjects {
+            for subscribed in [subject.clone(), format!("{}.chunk.*", subject)] {
+                let subscriber = self.client.subscribe(subscribed).await
+                    .map_err(|e| Error::nats(format!("Failed to subscribe: {}", e), e))?;
+                stream.push(subscriber);
+            }
+        }
+
+        log::debug!("Subscribed to agent messages on {:?}", subjects);
+        Ok(AgentSubscription { subjects, stream, assembler: ChunkAssembler::new(self.config.max_message_bytes) })
+    }
+
+    /// Subscribe to `agent.<id>` and the subject for the agent's type
+    pub async fn subscribe_agent(&self, id: &AgentId, agent_type: &AgentType) -> Result<AgentSubscription> {
+        self.subscribe_messages(vec![subject_for_agent(id)?, type_subject(agent_type)]).await
+    }
+
+    /// Publish a message to every agent of the given type
+    pub async fn publish_to_type(&self, agent_type: &AgentType, message: &Message) -> Result<()> {
+        self.publish_message(&type_subject(agent_type), message).await
+    }
+
+    /// Reply to discovery queries for `location` and re-announce it every
+    /// `DISCOVERY_REFRESH_INTERVAL`, until it is deregistered or the registration is dropped
+    pub async fn serve_location(&self, location: &AgentLocation) -> Result<AgentRegistration> {
+        let subject = discovery_query_subject(&AgentId(location.agent_id.clone()))?;
+        let mut queries = self.client.subscribe(subject.clone()).await
+            .map_err(|e| Error::nats(format!("Failed to subscribe: {}", e), e))?;
+        let mut deregistrations = self.client.subscribe(DISCOVERY_DEREGISTER_SUBJECT.to_string()).await
+            .map_err(|e| Error::nats(format!("Failed to subscribe: {}", e), e))?;
+        let client = self.client.clone();
+        let served = location.clone();
+        let reply = Bytes::from(serde_json::to_vec(location)?);
+
+        let task = tokio::spawn(async move {
+            let mut refresh = tokio::time::interval(DISCOVERY_REFRESH_INTERVAL);
+            // The first tick is immediate and register has just announced the agent
+            refresh.tick().await;
+            loop {
+                tokio::select! {
+                    query = queries.next() => {
+                        let Some(query) = query else { break };
+                        let Some(reply_to) = query.reply else { continue };
+                        if let Err(e) = client.publish(reply_to, reply.clone()).await {
+                            log::warn!("Failed to answer discovery query on {}: {}", subject, e);
+                        }
+                    }
+                    departed = deregistrations.next() => {
+                        let Some(departed) = departed else { break };
+                        match serde_json::from_slice::<AgentLocation>(&departed.payload) {
+                            Ok(departed) if departed.agent_id == served.agent_id && departed.node_id == served.node_id => break,
+                            Ok(_) => {}
+                            Err(e) => log::warn!("Ignoring unparsable deregistration: {}", e),
+                        }
+                    }
+                    _ = refresh.tick() => {
+                        if let Err(e) = client.publish(DISCOVERY_REGISTER_SUBJECT.to_string(), reply.clone()).await {
+                            log::warn!("Failed to refresh registration for {}: {}", served.agent_id, e);
+                        }
+                    }
+                }
+            }
+            log::debug!("Stopped serving discovery queries on {}", subject);
+        });
+        Ok(AgentRegistration { location: location.clone(), task })
+    }
+
+    /// Ask the cluster which node hosts `agent_id`; `None` if no node answers
+    pub async fn discover_agent(&self, agent_id: &AgentId) -> Result<Option<AgentLocation>> {
+        let subject = discovery_query_subject(agent_id)?;
+        let request = self.client.request(subject, Bytes::new());
+        match tokio::time::timeout(self.config.timeout, request).await {
+            Ok(Ok(response)) => Ok(Some(serde_json::from_slice(&response.payload)?)),
+            Ok(Err(e)) if e.kind() == async_nats::RequestErrorKind::NoResponders => Ok(None),
+            Ok(Err(e)) if e.kind() == async_nats::RequestErrorKind::TimedOut => Ok(None),
+            Ok(Err(e)) => Err(Error::nats(format!("Discovery request failed: {}", e), e)),
+            Err(_) => Ok(None),
+        }
+    }
+
     pub fn is_connected(&self) -> bool {
         self.client.connection_state() == async_nats::connection::State::Connected
     }
 
     pub async fn flush(&self) -> Result<()> {
         self.client.flush().await
-            .map_err(|e| Error::Nats(format!("Failed to flush: {}", e)))?;
+            .map_err(|e| Error::nats(format!("Failed to flush: {}", e), e))?;
         
         log::debug!("Flushed NATS connection");
         Ok(())
     }
 
+    /// Stop accepting publishes, flush what is outstanding, let subscriptions
+    /// finish their buffered messages, then close the connection
     pub async fn drain(&self) -> Result<()> {
-        // async-nats doesn't have a direct drain method, but we can close gracefully
-        log::debug!("Draining NATS connection (closing gracefully)");
+        self.draining.store(true, Ordering::Release);
+        log::debug!("Draining NATS connection");
+
+        match tokio::time::timeout(self.config.timeout, self.client.flush()).await {
+            Ok(result) => result.map_err(|e| Error::nats(format!("Failed to flush: {}", e), e))?,
+            Err(_) => {
+                return Err(Error::Nats(format!(
+                    "Flush timed out after {:?} while draining", self.config.timeout
+                )));
+            }
+        }
+
+        // Drains every subscription, then closes the connection
+        match tokio::time::timeout(self.config.timeout, self.client.drain()).await {
+            Ok(result) => result.map_err(|e| Error::nats(format!("Failed to drain: {}", e), e))?,
+            Err(_) => log::warn!(
+                "Subscriptions still draining after {:?}; closing anyway", self.config.timeout
+            ),
+        }
+
+        log::info!("Drained and closed NATS connection to {}", self.config.url);
         Ok(())
     }
 
@@ -170,12 +972,195 @@ impl NatsConnection {
 
     pub fn get_stats(&self) -> ConnectionStats {
         let stats = self.client.statistics();
+        let connects = stats.connects.load(Ordering::Relaxed);
+        let reconnects = connects.saturating_sub(1);
         ConnectionStats {
             messages_sent: stats.out_messages.load(Ordering::Relaxed),
             messages_received: stats.in_messages.load(Ordering::Relaxed),
             bytes_sent: stats.out_bytes.load(Ordering::Relaxed),
             bytes_received: stats.in_bytes.load(Ordering::Relaxed),
-            reconnects: stats.connects.load(Ordering::Relaxed),
+            connects,
+            reconnects,
+            // Only a reconnect once the first connect is behind us
+            last_reconnect_at: match self.last_connected_at.load(Ordering::Relaxed) {
+                at if reconnects > 0 && at > 0 => Some(at),
+                _ => None,
+            },
+            dropped_messages: self.dropped_messages.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// JetStream KV bucket holding the records behind `NatsConnection::try_acquire_lock`
+pub const LOCK_BUCKET: &str = "agent_locks";
+
+// Stored under the lock name. Expiry is wall-clock, so holders' clocks must roughly agree.
+#[cfg(feature = "jetstream")]
+#[derive(Debug, Serialize, Deserialize)]
+struct LockRecord {
+    holder: String,
+    expires_at_ms: i64,
+}
+
+#[cfg(feature = "jetstream")]
+impl LockRecord {
+    fn encode(holder: &str, ttl: Duration) -> Result<Bytes> {
+        let record = LockRecord {
+            holder: holder.to_string(),
+            expires_at_ms: chrono::Utc::now().timestamp_millis() + ttl.as_millis() as i64,
+        };
+        Ok(Bytes::from(serde_json::to_vec(&record)?))
+    }
+
+    // Unreadable records count as expired so a corrupt entry can't wedge the lock
+    fn is_expired(data: &[u8]) -> bool {
+        serde_json::from_slice::<LockRecord>(data)
+            .map(|record| record.expires_at_ms <= chrono::Utc::now().timestamp_millis())
+            .unwrap_or(true)
+    }
+}
+
+#[cfg(feature = "jetstream")]
+impl NatsConnection {
+    /// Claim the cluster-wide lock `name` for `ttl`, or `None` if someone else holds it.
+    /// The claim is an atomic KV create (or a revision-checked takeover of an expired
+    /// record), so of several contenders exactly one wins.
+    pub async fn try_acquire_lock(&self, name: &str, ttl: Duration) -> Result<Option<LockGuard>> {
+        use async_nats::jetstream::kv;
+
+        validate_subject_id(name)?;
+        let store = self.lock_store().await?;
+        let holder = uuid::Uuid::new_v4().to_string();
+        let record = LockRecord::encode(&holder, ttl)?;
+
+        let revision = match store.create(name, record.clone()).await {
+            Ok(revision) => revision,
+            Err(e) if e.kind() == kv::CreateErrorKind::AlreadyExists => {
+                let entry = store.entry(name).await
+                    .map_err(|e| Error::nats(format!("Failed to read lock {}: {}", name, e), e))?;
+                match entry {
+                    // The previous holder stopped renewing; take over unless another contender beats us
+                    Some(entry) if entry.operation != kv::Operation::Put || LockRecord::is_expired(&entry.value) => {
+                        match store.update(name, record, entry.revision).await {
+                            Ok(revision) => revision,
+                            Err(_) => return Ok(None),
+                        }
+                    }
+                    _ => return Ok(None),
+                }
+            }
+            Err(e) => return Err(Error::nats(format!("Failed to claim lock {}: {}", name, e), e)),
+        };
+
+        log::info!("Acquired lock {} (holder {})", name, holder);
+        Ok(Some(LockGuard::start(store, name.to_string(), holder, revision, ttl)))
+    }
+
+    async fn lock_store(&self) -> Result<async_nats::jetstream::kv::Store> {
+        let jetstream = async_nats::jetstream::new(self.client.clone());
+        if let Ok(store) = jetstream.get_key_value(LOCK_BUCKET).await {
+            return Ok(store);
+        }
+        jetstream.create_key_value(async_nats::jetstream::kv::Config {
+            bucket: LOCK_BUCKET.to_string(),
+            history: 1,
+            ..Default::default()
+        }).await
+            .map_err(|e| Error::nats(format!("Failed to create lock bucket {}: {}", LOCK_BUCKET, e), e))
+    }
+}
+
+/// A held distributed lock. It is renewed every third of its TTL and released on drop;
+/// if renewal fails the lock is considered lost and `is_held` turns false.
+#[cfg(feature = "jetstream")]
+#[derive(Debug)]
+pub struct LockGuard {
+    store: async_nats::jetstream::kv::Store,
+    name: String,
+    holder: String,
+    revision: Arc<AtomicU64>,
+    lost: Arc<AtomicBool>,
+    renewer: tokio::task::JoinHandle<()>,
+    released: bool,
+}
+
+#[cfg(feature = "jetstream")]
+impl LockGuard {
+    fn start(store: async_nats::jetstream::kv::Store, name: String, holder: String, revision: u64, ttl: Duration) -> Self {
+        let revision = Arc::new(AtomicU64::new(revision));
+        let lost = Arc::new(AtomicBool::new(false));
+
+        let renewer = {
+            let (store, name, holder) = (store.clone(), name.clone(), holder.clone());
+            let (revision, lost) = (revision.clone(), lost.clone());
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(ttl / 3).await;
+                    let renewed = match LockRecord::encode(&holder, ttl) {
+                        Ok(record) => store.update(&name, record, revision.load(Ordering::Acquire)).await
+                            .map_err(|e| e.to_string()),
+                        Err(e) => Err(e.to_string()),
+                    };
+                    match renewed {
+                        Ok(next) => revision.store(next, Ordering::Release),
+                        Err(e) => {
+                            log::warn!("Lost lock {}: renewal failed: {}", name, e);
+                            lost.store(true, Ordering::Release);
+                            break;
+                        }
+                    }
+                }
+            })
+        };
+
+        Self { store, name, holder, revision, lost, renewer, released: false }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Unique id of this claim, as stored in the lock record
+    pub fn holder(&self) -> &str {
+        &self.holder
+    }
+
+    pub fn is_held(&self) -> bool {
+        !self.released && !self.lost.load(Ordering::Acquire)
+    }
+
+    /// Release now and wait for the record to be deleted. Dropping the guard does the
+    /// same in the background; without a runtime the record simply expires.
+    pub async fn release(mut self) -> Result<()> {
+        self.renewer.abort();
+        self.released = true;
+        if self.lost.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        let revision = self.revision.load(Ordering::Acquire);
+        self.store.delete_expect_revision(&self.name, Some(revision)).await
+            .map_err(|e| Error::nats(format!("Failed to release lock {}: {}", self.name, e), e))
+    }
+}
+
+#[cfg(feature = "jetstream")]
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        if self.released {
+            return;
+        }
+        self.renewer.abort();
+        if self.lost.load(Ordering::Acquire) {
+            return;
+        }
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let (store, name) = (self.store.clone(), self.name.clone());
+            let revision = self.revision.load(Ordering::Acquire);
+            runtime.spawn(async move {
+                if let Err(e) = store.delete_expect_revision(&name, Some(revision)).await {
+                    log::warn!("Failed to release lock {}: {}", name, e);
+                }
+            });
         }
     }
 }
@@ -184,10 +1169,44 @@ impl NatsConnection {
 impl NatsConnection {
     pub async fn new(config: NatsConfig) -> Result<Self> {
         log::warn!("NATS feature not enabled - creating stub connection");
-        Ok(Self { config })
+        Ok(Self { config, fail_publish: false, draining: AtomicBool::new(false), serializer: Serializer::default(), dropped_messages: Arc::new(AtomicU64::new(0)) })
+    }
+
+    /// Encoding used for agent messages published through this connection
+    pub fn with_serializer(mut self, serializer: Serializer) -> Self {
+        self.serializer = serializer;
+        self
+    }
+
+    /// Encode an agent message with the connection's serializer and publish it
+    pub async fn publish_message(&self, subject: &str, message: &Message) -> Result<()> {
+        let data = message.encode(self.serializer)?;
+        self.publish(subject, &data).await
+    }
+
+    pub fn handle(&self) -> Self {
+        Self {
+            config: self.config.clone(),
+            fail_publish: self.fail_publish,
+            draining: AtomicBool::new(false),
+            serializer: self.serializer,
+            dropped_messages: self.dropped_messages.clone(),
+        }
+    }
+
+    /// Stub connection whose publishes always fail
+    #[cfg(test)]
+    pub(crate) fn failing(config: NatsConfig) -> Self {
+        Self { config, fail_publish: true, draining: AtomicBool::new(false), serializer: Serializer::default(), dropped_messages: Arc::new(AtomicU64::new(0)) }
     }
 
     pub async fn publish(&self, subject: &str, _data: &[u8]) -> Result<()> {
+        if self.fail_publish {
+            return Err(Error::Nats(format!("NATS stub: publish to {} failed", subject)));
+        }
+        if self.draining.load(Ordering::Acquire) {
+            return Err(Error::Nats(format!("Cannot publish to {}: connection is draining", subject)));
+        }
         log::debug!("NATS stub: would publish to subject: {}", subject);
         Ok(())
     }
@@ -197,21 +1216,57 @@ impl NatsConnection {
         Ok(Vec::new())
     }
 
+    pub async fn subscribe_bounded(&self, subject: &str, capacity: usize) -> Result<BoundedReceiver<Message>> {
+        log::debug!("NATS stub: would subscribe to subject: {}", subject);
+        // The sender is dropped straight away, so the subscription ends immediately
+        let (_sender, receiver) = bounded_channel(capacity, self.dropped_messages.clone());
+        Ok(receiver)
+    }
+
     pub async fn request(&self, subject: &str, _data: &[u8]) -> Result<Vec<u8>> {
         log::debug!("NATS stub: would send request to subject: {}", subject);
         Ok(Vec::new())
     }
 
+    pub async fn subscribe_messages(&self, subjects: Vec<String>) -> Result<AgentSubscription> {
+        log::debug!("NATS stub: would subscribe to agent messages on {:?}", subjects);
+        Ok(AgentSubscription { subjects })
+    }
+
+    pub async fn subscribe_agent(&self, id: &AgentId, agent_type: &AgentType) -> Result<AgentSubscription> {
+        self.subscribe_messages(vec![subject_for_agent(id)?, type_subject(agent_type)]).await
+    }
+
+    pub async fn publish_to_type(&self, agent_type: &AgentType, message: &Message) -> Result<()> {
+        self.publish_message(&type_subject(agent_type), message).await
+    }
+
+    pub async fn serve_location(&self, location: &AgentLocation) -> Result<AgentRegistration> {
+        log::debug!("NATS stub: would answer discovery queries for {}", location.agent_id);
+        Ok(AgentRegistration { location: location.clone() })
+    }
+
+    pub async fn discover_agent(&self, agent_id: &AgentId) -> Result<Option<AgentLocation>> {
+        log::debug!("NATS stub: cannot discover agent {}", agent_id.0);
+        Ok(None)
+    }
+
     pub fn is_connected(&self) -> bool {
         false
     }
 
+    pub async fn wait_ready(&self, _timeout: Duration) -> Result<()> {
+        log::debug!("NATS stub: wait_ready called");
+        Ok(())
+    }
+
     pub async fn flush(&self) -> Result<()> {
         log::debug!("NATS stub: flush called");
         Ok(())
     }
 
     pub async fn drain(&self) -> Result<()> {
+        self.draining.store(true, Ordering::Release);
         log::debug!("NATS stub: drain called");
         Ok(())
     }
@@ -227,18 +1282,101 @@ impl NatsConnection {
             messages_received: 0,
             bytes_sent: 0,
             bytes_received: 0,
+            connects: 0,
             reconnects: 0,
+            last_reconnect_at: None,
+            dropped_messages: self.dropped_messages.load(Ordering::Relaxed),
         }
     }
 }
 
+pub const DEFAULT_NATS_POOL_SIZE: usize = 4;
+
+/// Shared NATS connections for the agents spawned from one process. Connections are opened on demand
+/// up to `size`; after that `get` hands out handles to them in turn.
+#[derive(Debug)]
+pub struct NatsConnectionPool {
+    config: NatsConfig,
+    size: usize,
+    connections: Mutex<Vec<NatsConnection>>,
+    next: AtomicUsize,
+}
+
+impl NatsConnectionPool {
+    pub fn new(config: NatsConfig, size: usize) -> Self {
+        Self {
+            config,
+            size: size.max(1),
+            connections: Mutex::new(Vec::new()),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// A handle on a pooled connection, opening a new one while the pool is below `size`
+    pub async fn get(&self) -> Result<NatsConnection> {
+        if let Some(handle) = self.next_handle() {
+            return Ok(handle);
+        }
+
+        // Connect without holding the lock; if the pool filled meanwhile, this one is dropped
+        let connection = NatsConnection::new(self.config.clone()).await?;
+        let mut connections = self.connections.lock().unwrap();
+        if connections.len() < self.size {
+            let handle = connection.handle();
+            connections.push(connection);
+            log::debug!("Opened pooled NATS connection {} of {}", connections.len(), self.size);
+            return Ok(handle);
+        }
+        Ok(self.round_robin(&connections))
+    }
+
+    /// Number of underlying connections opened so far
+    pub fn connection_count(&self) -> usize {
+        self.connections.lock().unwrap().len()
+    }
+
+    fn next_handle(&self) -> Option<NatsConnection> {
+        let connections = self.connections.lock().unwrap();
+        (connections.len() >= self.size).then(|| self.round_robin(&connections))
+    }
+
+    fn round_robin(&self, connections: &[NatsConnection]) -> NatsConnection {
+        let slot = self.next.fetch_add(1, Ordering::Relaxed) % connections.len();
+        connections[slot].handle()
+    }
+}
+
+// Lives in the memory of one process. Lunatic processes don't share memory, so agent
+// processes never see it; only the NATS routing `spawn_llm_enabled_agent` sets up in the
+// calling process does.
+static SHARED_POOL: OnceLock<NatsConnectionPool> = OnceLock::new();
+
+/// Install the pool `spawn_llm_enabled_agent` takes connections from. Pooling only works
+/// within one process: agents spawned from this process share its connections for their
+/// subscriptions, while agent processes themselves (e.g. heartbeats) connect on their own.
+/// Only the first install succeeds; returns whether this one did.
+pub fn install_shared_pool(pool: NatsConnectionPool) -> bool {
+    SHARED_POOL.set(pool).is_ok()
+}
+
+/// The pool set with [`install_shared_pool`], if any
+pub fn shared_pool() -> Option<&'static NatsConnectionPool> {
+    SHARED_POOL.get()
+}
+
 #[derive(Debug, Clone)]
 pub struct ConnectionStats {
     pub messages_sent: u64,
     pub messages_received: u64,
     pub bytes_sent: u64,
     pub bytes_received: u64,
+    // Total successful connects, including the initial one
+    pub connects: u64,
     pub reconnects: u64,
+    // Unix seconds of the latest reconnect
+    pub last_reconnect_at: Option<u64>,
+    // Messages dropped because a bounded subscription's buffer was full
+    pub dropped_messages: u64,
 }
 
 // Helper trait for better error handling
@@ -280,7 +1418,7 @@ mod tests {
         assert_eq!(config.url, "nats://localhost:4222");
         assert_eq!(config.timeout, Duration::from_secs(10));
         assert_eq!(config.max_reconnects, Some(10));
-        assert_eq!(config.reconnect_delay, Duration::from_secs(1));
+        assert_eq!(config.reconnect_backoff, BackoffStrategy::Linear { step: Duration::from_secs(1), max: Duration::from_secs(30) });
     }
 
     #[test]
@@ -289,12 +1427,359 @@ mod tests {
             url: "nats://custom:4222".to_string(),
             timeout: Duration::from_secs(5),
             max_reconnects: Some(5),
-            reconnect_delay: Duration::from_secs(2),
+            reconnect_backoff: BackoffStrategy::Fixed(Duration::from_secs(2)),
+            compress_threshold_bytes: 1024,
+            max_payload_bytes: Some(512),
+            max_message_bytes: 4096,
         };
         assert_eq!(config.url, "nats://custom:4222");
         assert_eq!(config.timeout, Duration::from_secs(5));
         assert_eq!(config.max_reconnects, Some(5));
-        assert_eq!(config.reconnect_delay, Duration::from_secs(2));
+        assert_eq!(config.reconnect_backoff, BackoffStrategy::Fixed(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn test_compress_round_trip_large_payload() {
+        let payload: Vec<u8> = serde_json::to_vec(&serde_json::json!({
+            "content": "scraped paragraph text ".repeat(2 * 1024 * 1024 / 23),
+        })).unwrap();
+        assert!(payload.len() >= 2 * 1024 * 1024 - 64);
+
+        let compressed = compress_payload(&payload).unwrap();
+        assert!(compressed.starts_with(COMPRESSED_MAGIC));
+        assert!(compressed.len() < payload.len() / 10);
+        assert_eq!(decompress_payload(&compressed).unwrap(), payload);
+
+        // Uncompressed payloads pass straight through
+        assert_eq!(decompress_payload(b"{\"plain\":true}").unwrap(), b"{\"plain\":true}");
+    }
+
+    #[test]
+    fn test_chunk_and_reassemble_oversized_payload() {
+        let payload: Vec<u8> = (0..5000u32).flat_map(|i| i.to_le_bytes()).collect();
+        let (chunks, manifest) = chunk_payload("msg-1", &payload, 1024).unwrap();
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 1024));
+        assert_eq!(chunk_subject("agent.a", 3), "agent.a.chunk.3");
+
+        // Chunks may arrive on either side of the manifest and out of order
+        let mut assembler = ChunkAssembler::new(DEFAULT_MAX_MESSAGE_BYTES);
+        let (last, rest) = chunks.split_last().unwrap();
+        for chunk in rest.iter().rev() {
+            assert_eq!(assembler.accept(chunk).unwrap(), None);
+        }
+        assert_eq!(assembler.accept(&manifest).unwrap(), None);
+        assert_eq!(assembler.accept(last).unwrap(), Some(payload));
+        assert_eq!(assembler.pending(), 0);
+
+        // Ordinary payloads pass straight through
+        assert_eq!(assembler.accept(b"{\"plain\":true}").unwrap(), Some(b"{\"plain\":true}".to_vec()));
+    }
+
+    #[test]
+    fn test_chunk_assembler_enforces_size_limit() {
+        let payload = vec![7u8; 3000];
+        let (chunks, manifest) = chunk_payload("big", &payload, 512).unwrap();
+
+        let mut assembler = ChunkAssembler::new(2000);
+        assert!(assembler.accept(&manifest).is_err());
+        assert_eq!(assembler.pending(), 0);
+
+        let mut assembler = ChunkAssembler::new(2000);
+        let results: Vec<_> = chunks.iter().map(|chunk| assembler.accept(chunk)).collect();
+        assert!(results.iter().any(|result| result.is_err()));
+        assert!(chunk_payload("tiny", &payload, 8).is_err());
+    }
+
+    #[test]
+    fn test_chunk_assembler_evicts_lost_chunks() {
+        let payload = vec![3u8; 1500];
+        let (lost, lost_manifest) = chunk_payload("lost", &payload, 512).unwrap();
+        let (chunks, manifest) = chunk_payload("next", &payload, 512).unwrap();
+
+        // One chunk of "lost" never arrives; the rest are given up on to make room
+        let mut assembler = ChunkAssembler::new(2000);
+        assert_eq!(assembler.accept(&lost_manifest).unwrap(), None);
+        for chunk in &lost[1..] {
+            assert_eq!(assembler.accept(chunk).unwrap(), None);
+        }
+        assert_eq!(assembler.pending(), 1);
+        assert_eq!(assembler.accept(&manifest).unwrap(), None);
+        let (last, rest) = chunks.split_last().unwrap();
+        for chunk in rest {
+            assert_eq!(assembler.accept(chunk).unwrap(), None);
+        }
+        assert_eq!(assembler.accept(last).unwrap(), Some(payload.clone()));
+        assert_eq!(assembler.pending(), 0);
+
+        // Partial payloads past the TTL are dropped on the next payload received
+        let mut assembler = ChunkAssembler::new(DEFAULT_MAX_MESSAGE_BYTES).with_ttl(Duration::ZERO);
+        for chunk in &lost[1..] {
+            assert_eq!(assembler.accept(chunk).unwrap(), None);
+        }
+        assert_eq!(assembler.accept(b"{}").unwrap(), Some(b"{}".to_vec()));
+        assert_eq!(assembler.pending(), 0);
+    }
+
+    #[test]
+    fn test_agent_subjects() {
+        let id = AgentId("collector_1".to_string());
+        assert_eq!(subject_for_agent(&id).unwrap(), "agent.collector_1");
+        assert_eq!(subject_for_agent(&AgentId("region.scraper-2".to_string())).unwrap(), "agent.region.scraper-2");
+        assert_eq!(type_subject(&AgentType::DataCollector), "type.datacollector");
+        assert_eq!(type_subject(&AgentType::Summarizer), "type.summarizer");
+    }
+
+    #[test]
+    fn test_backoff_delay_sequences() {
+        let secs = Duration::from_secs;
+        let sequence = |strategy: BackoffStrategy, random: f64| -> Vec<Duration> {
+            (1..=7).map(|attempt| strategy.delay_with_jitter(attempt, random)).collect()
+        };
+
+        assert_eq!(sequence(BackoffStrategy::Fixed(secs(2)), 0.5), vec![secs(2); 7]);
+        assert_eq!(
+            sequence(BackoffStrategy::Linear { step: secs(1), max: secs(5) }, 0.5),
+            vec![secs(1), secs(2), secs(3), secs(4), secs(5), secs(5), secs(5)],
+        );
+
+        let exponential = BackoffStrategy::Exponential { base: secs(1), max: secs(30), jitter: false };
+        assert_eq!(
+            sequence(exponential, 0.5),
+            vec![secs(1), secs(2), secs(4), secs(8), secs(16), secs(30), secs(30)],
+        );
+        // Huge attempt counts saturate at the cap rather than overflowing
+        assert_eq!(exponential.delay(usize::MAX), secs(30));
+
+        let jittered = BackoffStrategy::Exponential { base: secs(1), max: secs(30), jitter: true };
+        assert_eq!(
+            sequence(jittered, 0.5),
+            vec![Duration::from_millis(500), secs(1), secs(2), secs(4), secs(8), secs(15), secs(15)],
+        );
+        assert_eq!(jittered.delay_with_jitter(4, 0.0), Duration::ZERO);
+        for attempt in 1..=10 {
+            assert!(jittered.delay(attempt) <= secs(30));
+        }
+    }
+
+    #[test]
+    fn test_bounded_channel_counts_burst_drops() {
+        let connection_dropped = Arc::new(AtomicU64::new(0));
+        let (mut sender, mut receiver) = bounded_channel(2, connection_dropped.clone());
+
+        let queued: Vec<bool> = (0..10).map(|i| sender.offer(i)).collect();
+        assert_eq!(queued.iter().filter(|queued| **queued).count(), 2);
+        assert_eq!(receiver.dropped(), 8);
+        assert_eq!(connection_dropped.load(Ordering::Relaxed), 8);
+
+        // The oldest messages survive; draining makes room again
+        assert_eq!(receiver.try_next(), Some(0));
+        assert_eq!(receiver.try_next(), Some(1));
+        assert_eq!(receiver.try_next(), None);
+        assert!(sender.offer(10));
+        assert_eq!(receiver.try_next(), Some(10));
+        assert_eq!(receiver.dropped(), 8);
+
+        // A zero capacity still holds one message
+        let (mut sender, _receiver) = bounded_channel(0, connection_dropped.clone());
+        assert!(sender.offer(1));
+        assert!(!sender.offer(2));
+        assert_eq!(connection_dropped.load(Ordering::Relaxed), 9);
+    }
+
+    #[cfg(not(feature = "nats"))]
+    #[test]
+    fn test_fresh_connection_has_no_reconnects() {
+        let connection = futures::executor::block_on(NatsConnection::new(NatsConfig::default())).unwrap();
+        let stats = connection.get_stats();
+        assert_eq!(stats.reconnects, 0);
+        assert_eq!(stats.last_reconnect_at, None);
+        assert_eq!(stats.dropped_messages, 0);
+    }
+
+    #[cfg(feature = "nats")]
+    #[tokio::test]
+    #[ignore = "requires a running NATS server"]
+    async fn test_fresh_connection_has_no_reconnects() {
+        let connection = NatsConnection::new(NatsConfig::from_env().unwrap()).await.unwrap();
+        let stats = connection.get_stats();
+        assert_eq!(stats.connects, 1);
+        assert_eq!(stats.reconnects, 0);
+        assert_eq!(stats.last_reconnect_at, None);
+    }
+
+    #[cfg(feature = "nats")]
+    #[tokio::test]
+    #[ignore = "requires a running NATS server"]
+    async fn test_bounded_subscription_drops_burst() {
+        let connection = NatsConnection::new(NatsConfig::from_env().unwrap()).await.unwrap();
+        let mut firehose = connection.subscribe_bounded("test.firehose", 2).await.unwrap();
+
+        let message = Message {
+            id: "firehose_msg".to_string(),
+            from: AgentId("sender".to_string()),
+            to: AgentId("monitor".to_string()),
+            payload: serde_json::json!({"type": "data_update"}),
+            timestamp: 12345,
+            expires_at: None,
+            correlation_id: None,
+            reply_to: None,
+            signature: None,
+        };
+        for _ in 0..20 {
+            connection.publish_message("test.firehose", &message).await.unwrap();
+        }
+        connection.flush().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        assert_eq!(firehose.dropped(), 18);
+        assert_eq!(connection.get_stats().dropped_messages, 18);
+        assert!(firehose.try_next().is_some());
+        assert!(firehose.try_next().is_some());
+        assert!(firehose.try_next().is_none());
+    }
+
+    #[cfg(not(feature = "nats"))]
+    #[test]
+    fn test_pool_shares_connections_between_agents() {
+        let pool = NatsConnectionPool::new(NatsConfig::default(), 2);
+        futures::executor::block_on(async {
+            for _ in 0..5 {
+                pool.get().await.unwrap();
+            }
+        });
+        assert_eq!(pool.connection_count(), 2);
+    }
+
+    #[cfg(feature = "nats")]
+    #[tokio::test]
+    #[ignore = "requires a running NATS server"]
+    async fn test_pool_shares_connections_between_agents() {
+        let pool = NatsConnectionPool::new(NatsConfig::from_env().unwrap(), 2);
+        for _ in 0..5 {
+            let handle = pool.get().await.unwrap();
+            assert!(handle.is_connected());
+        }
+        assert_eq!(pool.connection_count(), 2);
+    }
+
+    #[test]
+    fn test_discovery_location() {
+        let discovery = DiscoveryService::new("node-a");
+        let location = discovery.location(&AgentId("summarizer_1".to_string()), &AgentType::Summarizer).unwrap();
+        assert_eq!(location.node_id, "node-a");
+        assert_eq!(location.agent_type, "Summarizer");
+        assert_eq!(location.subjects, vec!["agent.summarizer_1", "type.summarizer"]);
+        assert_eq!(discovery_query_subject(&AgentId("summarizer_1".to_string())).unwrap(), "discovery.query.summarizer_1");
+    }
+
+    #[test]
+    fn test_invalid_agent_ids_rejected() {
+        for id in ["", "all.>", "*", "scraper *", "my agent", "tab\tagent", ".leading", "trailing.", "double..dot"] {
+            let error = subject_for_agent(&AgentId(id.to_string())).unwrap_err();
+            assert!(matches!(error, Error::Nats(_)), "{:?} should be rejected", id);
+        }
+        assert!(DiscoveryService::new("node-a").location(&AgentId("a>b".to_string()), &AgentType::Generic).is_err());
+    }
+
+    #[cfg(feature = "nats")]
+    #[tokio::test]
+    #[ignore = "requires a running NATS server"]
+    async fn test_register_then_discover_agent() {
+        let config = NatsConfig::from_env().unwrap();
+        let host = NatsConnection::new(config.clone()).await.unwrap();
+        let coordinator = NatsConnection::new(config).await.unwrap();
+        let id = AgentId("discoverable_agent".to_string());
+
+        assert_eq!(coordinator.discover_agent(&id).await.unwrap(), None);
+
+        let mut registered = DiscoveryService::new("node-b")
+            .register(&host, &id, &AgentType::DataCollector)
+            .await
+            .unwrap();
+        host.flush().await.unwrap();
+
+        let found = coordinator.discover_agent(&id).await.unwrap();
+        assert_eq!(found.as_ref(), Some(registered.location()));
+
+        // Once deregistered, the node stops answering for the agent
+        DiscoveryService::new("node-b").deregister(&host, &id, &AgentType::DataCollector).await.unwrap();
+        host.flush().await.unwrap();
+        registered.closed().await;
+        assert_eq!(coordinator.discover_agent(&id).await.unwrap(), None);
+    }
+
+    #[cfg(feature = "nats")]
+    #[tokio::test]
+    #[ignore = "requires a running NATS server"]
+    async fn test_connected_as_soon_as_new_returns() {
+        let connection = NatsConnection::new(NatsConfig::from_env().unwrap()).await.unwrap();
+        assert!(connection.is_connected());
+        connection.wait_ready(Duration::from_millis(1)).await.unwrap();
+    }
+
+    #[cfg(feature = "nats")]
+    #[tokio::test]
+    #[ignore = "requires a running NATS server"]
+    async fn test_type_publish_reaches_subscribed_agent() {
+        let connection = NatsConnection::new(NatsConfig::from_env().unwrap()).await.unwrap();
+        let id = AgentId("type_route_agent".to_string());
+        let mut subscription = connection.subscribe_agent(&id, &AgentType::Summarizer).await.unwrap();
+        assert_eq!(subscription.subjects(), ["agent.type_route_agent", "type.summarizer"]);
+
+        let message = Message {
+            id: "type_msg_1".to_string(),
+            from: AgentId("tester".to_string()),
+            to: id.clone(),
+            payload: serde_json::json!({"type": "data_update", "data": 1}),
+            timestamp: 12345,
+            expires_at: None,
+            correlation_id: None,
+            reply_to: None,
+            signature: None,
+        };
+        connection.publish_to_type(&AgentType::Summarizer, &message).await.unwrap();
+        connection.flush().await.unwrap();
+
+        let received = tokio::time::timeout(Duration::from_secs(5), subscription.next())
+            .await
+            .expect("timed out waiting for type-routed message")
+            .expect("subscription closed");
+        assert_eq!(received.id, "type_msg_1");
+    }
+
+    #[cfg(not(feature = "nats"))]
+    #[test]
+    fn test_publish_rejected_after_drain() {
+        futures::executor::block_on(async {
+            let connection = NatsConnection::new(NatsConfig::default()).await.unwrap();
+            connection.publish("test.subject", b"before").await.unwrap();
+
+            connection.drain().await.unwrap();
+            let err = connection.publish("test.subject", b"after").await.unwrap_err();
+            assert!(err.to_string().contains("draining"));
+        });
+    }
+
+    #[cfg(feature = "nats")]
+    #[tokio::test]
+    #[ignore = "requires a running NATS server"]
+    async fn test_drain_delivers_pending_publishes() {
+        let config = NatsConfig::from_env().unwrap();
+        let receiver = NatsConnection::new(config.clone()).await.unwrap();
+        let mut subscriber = receiver.client.subscribe("test.drain").await.unwrap();
+        receiver.flush().await.unwrap();
+
+        let sender = NatsConnection::new(config).await.unwrap();
+        sender.publish("test.drain", b"last words").await.unwrap();
+        sender.drain().await.unwrap();
+        assert!(sender.publish("test.drain", b"too late").await.is_err());
+
+        let msg = tokio::time::timeout(Duration::from_secs(5), subscriber.next())
+            .await
+            .expect("drained publish never arrived")
+            .unwrap();
+        assert_eq!(&msg.payload[..], b"last words");
     }
 
     // Integration tests would require a running NATS server
@@ -0,0 +1,1002 @@
+//! Helpers for turning fetched pages into content the agents can work with
+
+use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+use std::time::Duration;
+use crate::{Result, Error};
+use crate::http_client::HttpResponse;
+
+pub const DEFAULT_USER_AGENT: &str = "Lunatic-Distributed-Scraper/1.0";
+
+/// Hosts whose robots.txt rules are kept before the least recently used is dropped
+pub const DEFAULT_ROBOTS_CACHE_CAPACITY: usize = 1024;
+
+/// Content hashes remembered for dedup before the least recently used is dropped
+pub const DEFAULT_DEDUP_CACHE_CAPACITY: usize = 10_000;
+
+/// Why a scrape failed, carried by `Error::Scraping`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScrapeErrorKind {
+    InvalidUrl,
+    Timeout,
+    HttpStatus(u16),
+    RobotsBlocked,
+    Parse,
+}
+
+impl ScrapeErrorKind {
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ScrapeErrorKind::Timeout => true,
+            ScrapeErrorKind::HttpStatus(status) => *status >= 500 || *status == 408 || *status == 429,
+            ScrapeErrorKind::InvalidUrl | ScrapeErrorKind::RobotsBlocked | ScrapeErrorKind::Parse => false,
+        }
+    }
+
+    pub fn retry_delay_ms(&self) -> u64 {
+        match self {
+            ScrapeErrorKind::HttpStatus(429) => 5000,
+            kind if kind.is_retryable() => 1000,
+            _ => 0,
+        }
+    }
+}
+
+impl std::fmt::Display for ScrapeErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScrapeErrorKind::InvalidUrl => write!(f, "invalid URL"),
+            ScrapeErrorKind::Timeout => write!(f, "request timed out"),
+            ScrapeErrorKind::HttpStatus(status) => write!(f, "HTTP {}", status),
+            ScrapeErrorKind::RobotsBlocked => write!(f, "blocked by robots.txt"),
+            ScrapeErrorKind::Parse => write!(f, "could not parse response"),
+        }
+    }
+}
+
+/// Classify a failed fetch of `url`, keeping errors that aren't scrape-specific as they are
+pub fn scrape_error(url: &str, error: Error) -> Error {
+    match error {
+        Error::Io(e) if e.kind() == std::io::ErrorKind::TimedOut => Error::Scraping {
+            url: url.to_string(),
+            kind: ScrapeErrorKind::Timeout,
+        },
+        Error::LLMTimeout { .. } => Error::Scraping {
+            url: url.to_string(),
+            kind: ScrapeErrorKind::Timeout,
+        },
+        other => other,
+    }
+}
+
+/// Per-task scraping options carried in the `config` field of a scraping_task message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScrapeTaskConfig {
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+    #[serde(default)]
+    pub respect_robots_txt: bool,
+    // Minimum gap between fetches to the same host
+    #[serde(default)]
+    pub rate_limit_delay_ms: u64,
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+    // Extra fetch attempts after a transient failure
+    #[serde(default)]
+    pub retry_attempts: u32,
+    // Store pages whose content was already scraped as a `duplicate_of` reference
+    #[serde(default)]
+    pub dedup: bool,
+    #[serde(default = "default_robots_cache_capacity")]
+    pub robots_cache_capacity: usize,
+    #[serde(default = "default_dedup_cache_capacity")]
+    pub dedup_cache_capacity: usize,
+}
+
+fn default_user_agent() -> String {
+    DEFAULT_USER_AGENT.to_string()
+}
+
+fn default_timeout_seconds() -> u64 {
+    crate::http_client::DEFAULT_HTTP_TIMEOUT.as_secs()
+}
+
+fn default_robots_cache_capacity() -> usize {
+    DEFAULT_ROBOTS_CACHE_CAPACITY
+}
+
+fn default_dedup_cache_capacity() -> usize {
+    DEFAULT_DEDUP_CACHE_CAPACITY
+}
+
+impl Default for ScrapeTaskConfig {
+    fn default() -> Self {
+        Self {
+            user_agent: default_user_agent(),
+            respect_robots_txt: false,
+            rate_limit_delay_ms: 0,
+            timeout_seconds: default_timeout_seconds(),
+            retry_attempts: 0,
+            dedup: false,
+            robots_cache_capacity: DEFAULT_ROBOTS_CACHE_CAPACITY,
+            dedup_cache_capacity: DEFAULT_DEDUP_CACHE_CAPACITY,
+        }
+    }
+}
+
+/// SHA-256 (hex) of page content with whitespace runs collapsed, so pages that only
+/// differ in layout whitespace hash the same
+pub fn content_hash(content: &str) -> String {
+    use sha2::{Digest, Sha256};
+
+    let normalized = content.split_whitespace().collect::<Vec<_>>().join(" ");
+    Sha256::digest(normalized.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Readable content extracted from an HTML page
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScrapedContent {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub text: String,
+    pub link_count: usize,
+    pub image_count: usize,
+    pub paragraph_count: usize,
+}
+
+impl ScrapedContent {
+    /// Metadata in the shape stored under `scraped_data.metadata`
+    pub fn metadata(&self) -> serde_json::Value {
+        serde_json::json!({
+            "description": self.description,
+            "content_length": self.text.len(),
+            "link_count": self.link_count,
+            "image_count": self.image_count,
+            "paragraph_count": self.paragraph_count
+        })
+    }
+}
+
+/// Extract title, meta description and visible paragraph text from an HTML document.
+/// Text inside script, style and noscript elements is ignored.
+#[cfg(feature = "html")]
+pub fn parse_html(body: &str) -> ScrapedContent {
+    use scraper::{Html, Selector};
+
+    let document = Html::parse_document(body);
+    let selector = |css: &str| Selector::parse(css).expect("static selector is valid");
+
+    let title = document.select(&selector("title"))
+        .next()
+        .map(visible_text)
+        .filter(|title| !title.is_empty());
+
+    let description = document.select(&selector(r#"meta[name="description"]"#))
+        .next()
+        .and_then(|meta| meta.value().attr("content"))
+        .map(|content| content.trim().to_string())
+        .filter(|content| !content.is_empty());
+
+    let paragraphs: Vec<String> = document.select(&selector("p"))
+        .map(visible_text)
+        .filter(|text| !text.is_empty())
+        .collect();
+
+    ScrapedContent {
+        title,
+        description,
+        text: paragraphs.join("\n\n"),
+        link_count: document.select(&selector("a[href]")).count(),
+        image_count: document.select(&selector("img")).count(),
+        paragraph_count: paragraphs.len(),
+    }
+}
+
+#[cfg(feature = "html")]
+fn visible_text(element: scraper::ElementRef) -> String {
+    let text: String = element.descendants()
+        .filter(|node| {
+            !node.ancestors().any(|ancestor| {
+                ancestor.value().as_element()
+                    .map(|e| matches!(e.name(), "script" | "style" | "noscript"))
+                    .unwrap_or(false)
+            })
+        })
+        .filter_map(|node| node.value().as_text().map(|text| text.to_string()))
+        .collect();
+
+    // Collapse the whitespace left behind by markup and indentation
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Split an http(s) URL into its origin (`scheme://host[:port]`) and path
+pub fn split_url(url: &str) -> Option<(String, String)> {
+    let scheme_end = url.find("://")?;
+    let rest = &url[scheme_end + 3..];
+    let host_end = rest.find(|c| c == '/' || c == '?' || c == '#').unwrap_or(rest.len());
+    if host_end == 0 {
+        return None;
+    }
+
+    let origin = format!("{}{}", &url[..scheme_end + 3], &rest[..host_end]);
+    let path = rest[host_end..].split('#').next().unwrap_or("");
+    let path = if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("/{}", path)
+    };
+    Some((origin, path))
+}
+
+/// Resolve an `href` found on `base_url` to an absolute http(s) URL without its fragment.
+/// Non-web schemes (`mailto:`, `javascript:`, ...) and same-page anchors give `None`.
+pub fn resolve_link(base_url: &str, href: &str) -> Option<String> {
+    let href = href.trim();
+    let href = href.split('#').next().unwrap_or("");
+    if href.is_empty() {
+        return None;
+    }
+
+    let (origin, base_path) = split_url(base_url)?;
+    let resolved = if href.starts_with("http://") || href.starts_with("https://") {
+        href.to_string()
+    } else if let Some(rest) = href.strip_prefix("//") {
+        let scheme = &origin[..origin.find("://")?];
+        format!("{}://{}", scheme, rest)
+    } else if href.starts_with('/') {
+        format!("{}{}", origin, href)
+    } else if href.contains(':') && !href.starts_with("./") {
+        // Some other scheme
+        return None;
+    } else {
+        let base_path = base_path.split('?').next().unwrap_or("/");
+        let dir = &base_path[..base_path.rfind('/').map_or(0, |i| i + 1)];
+        format!("{}{}{}", origin, dir, href.trim_start_matches("./"))
+    };
+    split_url(&resolved).map(|_| resolved)
+}
+
+/// Absolute URLs of every link on an HTML page, in document order without repeats
+pub fn extract_links(body: &str, base_url: &str) -> Vec<String> {
+    let mut seen = HashSet::new();
+    link_targets(body)
+        .into_iter()
+        .filter_map(|href| resolve_link(base_url, &href))
+        .filter(|link| seen.insert(link.clone()))
+        .collect()
+}
+
+#[cfg(feature = "html")]
+fn link_targets(body: &str) -> Vec<String> {
+    use scraper::{Html, Selector};
+
+    let document = Html::parse_document(body);
+    let anchors = Selector::parse("a[href]").expect("static selector is valid");
+    document.select(&anchors)
+        .filter_map(|anchor| anchor.value().attr("href"))
+        .map(str::to_string)
+        .collect()
+}
+
+// Without the parser, pick quoted `href` attributes out of the markup
+#[cfg(not(feature = "html"))]
+fn link_targets(body: &str) -> Vec<String> {
+    let mut targets = Vec::new();
+    let mut rest = body;
+    while let Some(start) = rest.find("href=") {
+        rest = &rest[start + 5..];
+        let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            continue;
+        };
+        rest = &rest[1..];
+        if let Some(end) = rest.find(quote) {
+            targets.push(rest[..end].to_string());
+            rest = &rest[end + 1..];
+        }
+    }
+    targets
+}
+
+/// Settings for a `crawl` task: follow links from `start_url` breadth-first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrawlConfig {
+    pub start_url: String,
+    // Link hops from `start_url`; 0 fetches only the start page
+    #[serde(default = "default_max_depth")]
+    pub max_depth: usize,
+    #[serde(default = "default_max_pages")]
+    pub max_pages: usize,
+    #[serde(default = "default_same_domain_only")]
+    pub same_domain_only: bool,
+}
+
+fn default_max_depth() -> usize {
+    2
+}
+
+fn default_max_pages() -> usize {
+    50
+}
+
+fn default_same_domain_only() -> bool {
+    true
+}
+
+/// Pages still to fetch in a crawl, with the visited set that keeps link cycles from
+/// being followed twice and the depth and page limits from `CrawlConfig`
+#[derive(Debug)]
+pub struct CrawlFrontier {
+    config: CrawlConfig,
+    origin: Option<String>,
+    queue: VecDeque<(String, usize)>,
+    visited: HashSet<String>,
+    fetched: usize,
+}
+
+impl CrawlFrontier {
+    pub fn new(config: CrawlConfig) -> Self {
+        let origin = split_url(&config.start_url).map(|(origin, _)| origin);
+        let mut frontier = Self {
+            origin,
+            queue: VecDeque::new(),
+            visited: HashSet::new(),
+            fetched: 0,
+            config,
+        };
+        let start = frontier.config.start_url.clone();
+        frontier.visited.insert(start.clone());
+        frontier.queue.push_back((start, 0));
+        frontier
+    }
+
+    /// Next URL to fetch and its depth, or `None` once the queue is empty or `max_pages` reached
+    pub fn next_page(&mut self) -> Option<(String, usize)> {
+        if self.fetched >= self.config.max_pages {
+            return None;
+        }
+        let next = self.queue.pop_front()?;
+        self.fetched += 1;
+        Some(next)
+    }
+
+    /// Queue the in-scope, unvisited `links` found on a page at `depth`
+    pub fn add_links(&mut self, depth: usize, links: impl IntoIterator<Item = String>) {
+        if depth >= self.config.max_depth {
+            return;
+        }
+        for link in links {
+            if self.in_scope(&link) && self.visited.insert(link.clone()) {
+                self.queue.push_back((link, depth + 1));
+            }
+        }
+    }
+
+    fn in_scope(&self, link: &str) -> bool {
+        match split_url(link) {
+            Some((origin, _)) => !self.config.same_domain_only || self.origin.as_deref() == Some(origin.as_str()),
+            None => false,
+        }
+    }
+
+    pub fn pages_fetched(&self) -> usize {
+        self.fetched
+    }
+}
+
+// One User-agent group from a robots.txt file
+#[derive(Debug, Clone, Default)]
+struct RobotsGroup {
+    agents: Vec<String>,
+    allow: Vec<String>,
+    disallow: Vec<String>,
+}
+
+/// Parsed robots.txt rules
+#[derive(Debug, Clone, Default)]
+pub struct RobotsRules {
+    groups: Vec<RobotsGroup>,
+}
+
+impl RobotsRules {
+    /// Parse the User-agent / Allow / Disallow lines of a robots.txt body
+    pub fn parse(body: &str) -> Self {
+        let mut groups: Vec<RobotsGroup> = Vec::new();
+        let mut in_agent_lines = false;
+
+        for line in body.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let Some((field, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim().to_string();
+
+            match field.trim().to_ascii_lowercase().as_str() {
+                "user-agent" => {
+                    // Consecutive User-agent lines share one group
+                    if !in_agent_lines {
+                        groups.push(RobotsGroup::default());
+                    }
+                    if let Some(group) = groups.last_mut() {
+                        group.agents.push(value.to_ascii_lowercase());
+                    }
+                    in_agent_lines = true;
+                }
+                "allow" => {
+                    in_agent_lines = false;
+                    if let Some(group) = groups.last_mut() {
+                        if !value.is_empty() {
+                            group.allow.push(value);
+                        }
+                    }
+                }
+                "disallow" => {
+                    in_agent_lines = false;
+                    if let Some(group) = groups.last_mut() {
+                        // An empty Disallow allows everything
+                        if !value.is_empty() {
+                            group.disallow.push(value);
+                        }
+                    }
+                }
+                _ => {
+                    in_agent_lines = false;
+                }
+            }
+        }
+
+        Self { groups }
+    }
+
+    /// Check a path against the group that best matches `user_agent`.
+    /// The longest matching rule wins, with Allow preferred on ties.
+    pub fn is_allowed(&self, user_agent: &str, path: &str) -> bool {
+        let Some(group) = self.group_for(user_agent) else {
+            return true;
+        };
+
+        let longest = |rules: &[String]| {
+            rules.iter()
+                .filter(|rule| path.starts_with(rule.as_str()))
+                .map(|rule| rule.len())
+                .max()
+        };
+
+        match (longest(&group.allow), longest(&group.disallow)) {
+            (_, None) => true,
+            (None, Some(_)) => false,
+            (Some(allow), Some(disallow)) => allow >= disallow,
+        }
+    }
+
+    fn group_for(&self, user_agent: &str) -> Option<&RobotsGroup> {
+        // Match on the product token, e.g. "lunatic-distributed-scraper" from "Lunatic-Distributed-Scraper/1.0"
+        let product = user_agent.split('/').next().unwrap_or(user_agent).trim().to_ascii_lowercase();
+
+        let specific = self.groups.iter()
+            .filter_map(|group| {
+                group.agents.iter()
+                    .filter(|agent| agent.as_str() != "*" && product.contains(agent.as_str()))
+                    .map(|agent| agent.len())
+                    .max()
+                    .map(|len| (len, group))
+            })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, group)| group);
+
+        specific.or_else(|| {
+            self.groups.iter().find(|group| group.agents.iter().any(|agent| agent == "*"))
+        })
+    }
+}
+
+/// Map holding at most `capacity` entries; inserting past that evicts the least recently used
+#[derive(Debug, Clone)]
+pub struct LruCache<K, V> {
+    capacity: usize,
+    entries: HashMap<K, (V, u64)>,
+    // Last-use tick -> key, oldest first
+    order: BTreeMap<u64, K>,
+    tick: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    /// Look up `key`, marking it as recently used
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.tick += 1;
+        let (value, last_used) = self.entries.get_mut(key)?;
+        let stale = std::mem::replace(last_used, self.tick);
+        if let Some(key) = self.order.remove(&stale) {
+            self.order.insert(self.tick, key);
+        }
+        Some(value)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.entries.contains_key(key)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        self.tick += 1;
+        if let Some((_, last_used)) = self.entries.insert(key.clone(), (value, self.tick)) {
+            self.order.remove(&last_used);
+        }
+        self.order.insert(self.tick, key);
+        self.evict_excess();
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (value, last_used) = self.entries.remove(key)?;
+        self.order.remove(&last_used);
+        Some(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Change the bound, evicting the least recently used entries if it shrank
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity.max(1);
+        self.evict_excess();
+    }
+
+    fn evict_excess(&mut self) {
+        while self.entries.len() > self.capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+}
+
+/// Fetches and caches robots.txt rules per host, keeping the most recently used hosts
+#[derive(Debug)]
+pub struct RobotsChecker {
+    cache: LruCache<String, RobotsRules>,
+}
+
+impl Default for RobotsChecker {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_ROBOTS_CACHE_CAPACITY)
+    }
+}
+
+impl RobotsChecker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self { cache: LruCache::new(capacity) }
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.cache.set_capacity(capacity);
+    }
+
+    /// Number of hosts with cached rules
+    pub fn cached_hosts(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// Return an error if robots.txt disallows `url` for `user_agent`.
+    /// `fetch` receives the robots.txt URL and returns its body, or `None` when the host has none.
+    pub fn check<F>(&mut self, url: &str, user_agent: &str, fetch: F) -> Result<()>
+    where
+        F: FnOnce(&str) -> Result<Option<String>>,
+    {
+        let (origin, path) = split_url(url).ok_or_else(|| Error::Scraping {
+            url: url.to_string(),
+            kind: ScrapeErrorKind::InvalidUrl,
+        })?;
+
+        if self.cache.get(&origin).is_none() {
+            let robots_url = format!("{}/robots.txt", origin);
+            let rules = match fetch(&robots_url) {
+                Ok(Some(body)) => RobotsRules::parse(&body),
+                Ok(None) => RobotsRules::default(),
+                Err(e) => {
+                    // An unreachable robots.txt is treated as allow-all
+                    log::warn!("Could not fetch {}: {}", robots_url, e);
+                    RobotsRules::default()
+                }
+            };
+            self.cache.insert(origin.clone(), rules);
+        }
+
+        let allowed = self.cache.get(&origin)
+            .map_or(true, |rules| rules.is_allowed(user_agent, &path));
+        if allowed {
+            Ok(())
+        } else {
+            Err(Error::Scraping {
+                url: url.to_string(),
+                kind: ScrapeErrorKind::RobotsBlocked,
+            })
+        }
+    }
+
+    pub fn is_cached(&self, origin: &str) -> bool {
+        self.cache.contains_key(origin)
+    }
+}
+
+/// Enforces a minimum gap between fetches to the same host; hosts don't affect each other
+#[derive(Debug, Default)]
+pub struct HostRateLimiter {
+    next_allowed: HashMap<String, Duration>,
+}
+
+impl HostRateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reserve the next fetch slot for `host` and return how long to wait before fetching.
+    /// `now` is a monotonic timestamp; reservations queue up if called faster than `min_gap`.
+    pub fn reserve(&mut self, host: &str, now: Duration, min_gap: Duration) -> Duration {
+        let slot = match self.next_allowed.get(host) {
+            Some(next) if *next > now => *next,
+            _ => now,
+        };
+        self.next_allowed.insert(host.to_string(), slot + min_gap);
+        slot - now
+    }
+}
+
+/// Base delay before the first retry; doubled on each further attempt
+pub const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// Whether a fetch outcome is worth retrying: timeouts, dropped connections,
+/// 5xx/408/429 responses and errors the crate already marks as retryable.
+pub fn is_transient(result: &Result<HttpResponse>) -> bool {
+    match result {
+        Ok(response) => response.status >= 500 || response.status == 408 || response.status == 429,
+        Err(Error::Io(e)) => matches!(
+            e.kind(),
+            std::io::ErrorKind::TimedOut
+                | std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::ConnectionAborted
+                | std::io::ErrorKind::ConnectionRefused
+                | std::io::ErrorKind::Interrupted
+                | std::io::ErrorKind::UnexpectedEof
+        ),
+        Err(e) => e.is_retryable(),
+    }
+}
+
+/// Call `fetch` until it succeeds, fails permanently, or `retry_attempts` retries are used up.
+/// Returns the last outcome and the number of attempts made.
+pub fn fetch_with_retry<F, S>(retry_attempts: u32, mut fetch: F, mut sleep: S) -> (Result<HttpResponse>, u32)
+where
+    F: FnMut() -> Result<HttpResponse>,
+    S: FnMut(Duration),
+{
+    let mut attempt = 1;
+    loop {
+        let result = fetch();
+        if attempt > retry_attempts || !is_transient(&result) {
+            return (result, attempt);
+        }
+
+        let backoff = RETRY_BASE_DELAY * 2u32.pow(attempt - 1);
+        let delay = match &result {
+            Err(e) => backoff.max(Duration::from_millis(e.retry_delay_ms())),
+            Ok(_) => backoff,
+        };
+        log::debug!("Fetch attempt {} failed transiently, retrying in {}ms", attempt, delay.as_millis());
+        sleep(delay);
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_link() {
+        let base = "https://site.test/docs/page.html?x=1";
+        assert_eq!(resolve_link(base, "other.html").as_deref(), Some("https://site.test/docs/other.html"));
+        assert_eq!(resolve_link(base, "/root#frag").as_deref(), Some("https://site.test/root"));
+        assert_eq!(resolve_link(base, "//cdn.test/a").as_deref(), Some("https://cdn.test/a"));
+        assert_eq!(resolve_link(base, "http://else.test/").as_deref(), Some("http://else.test/"));
+        assert_eq!(resolve_link(base, "#top"), None);
+        assert_eq!(resolve_link(base, "mailto:me@site.test"), None);
+    }
+
+    #[test]
+    fn test_crawl_honours_depth_limit_and_skips_cycles() {
+        let fetched = crawl(2, 50, true);
+        assert_eq!(fetched, vec![
+            ("https://site.test/".to_string(), 0),
+            ("https://site.test/a.html".to_string(), 1),
+            ("https://site.test/b.html".to_string(), 2),
+        ]);
+
+        // Depth 3 reaches the leaf; the links back to the index are not followed again
+        assert_eq!(crawl(3, 50, true).len(), 4);
+        // Off-site links are only followed when allowed
+        assert!(crawl(1, 50, false).iter().any(|(url, _)| url == "https://other.test/x"));
+    }
+
+    #[test]
+    fn test_crawl_honours_page_limit() {
+        let fetched = crawl(10, 2, true);
+        assert_eq!(fetched.len(), 2);
+        assert_eq!(fetched[1].0, "https://site.test/a.html");
+    }
+
+    const ALLOW_ALL: &str = "User-agent: *\nDisallow:\n";
+
+    // Linked fixture: the index links a (depth 1), a links b (depth 2), b links c (depth 3)
+    // and back to the index, and the index also links off-site
+    fn crawl_fixture() -> HashMap<&'static str, &'static str> {
+        HashMap::from([
+            ("https://site.test/", r##"<a href="/a.html">A</a> <a href="https://other.test/x">X</a> <a href="#top">top</a>"##),
+            ("https://site.test/a.html", r#"<a href="b.html">B</a> <a href="/">home</a>"#),
+            ("https://site.test/b.html", r#"<a href="/c.html">C</a> <a href="https://site.test/#intro">home</a>"#),
+            ("https://site.test/c.html", "<p>leaf</p>"),
+        ])
+    }
+
+    fn crawl(max_depth: usize, max_pages: usize, same_domain_only: bool) -> Vec<(String, usize)> {
+        let site = crawl_fixture();
+        let mut frontier = CrawlFrontier::new(CrawlConfig {
+            start_url: "https://site.test/".to_string(),
+            max_depth,
+            max_pages,
+            same_domain_only,
+        });
+        let mut fetched = Vec::new();
+        while let Some((url, depth)) = frontier.next_page() {
+            if let Some(body) = site.get(url.as_str()) {
+                frontier.add_links(depth, extract_links(body, &url));
+            }
+            fetched.push((url, depth));
+        }
+        fetched
+    }
+    const DISALLOW_ALL: &str = "User-agent: *\nDisallow: /\n";
+
+    #[test]
+    fn test_robots_allow_all() {
+        let mut checker = RobotsChecker::new();
+        let result = checker.check("https://example.com/articles/1", DEFAULT_USER_AGENT, |robots_url| {
+            assert_eq!(robots_url, "https://example.com/robots.txt");
+            Ok(Some(ALLOW_ALL.to_string()))
+        });
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_robots_disallow_all() {
+        let mut checker = RobotsChecker::new();
+        let result = checker.check("https://example.com/articles/1", DEFAULT_USER_AGENT, |_| {
+            Ok(Some(DISALLOW_ALL.to_string()))
+        });
+        match result {
+            Err(Error::Scraping { url, kind }) => {
+                assert_eq!(url, "https://example.com/articles/1");
+                assert_eq!(kind, ScrapeErrorKind::RobotsBlocked);
+            }
+            other => panic!("expected robots.txt block, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_robots_cached_per_host() {
+        let mut checker = RobotsChecker::new();
+        checker.check("https://example.com/a", DEFAULT_USER_AGENT, |_| Ok(Some(ALLOW_ALL.to_string()))).unwrap();
+        assert!(checker.is_cached("https://example.com"));
+
+        // A second lookup for the same host must not fetch again
+        let result = checker.check("https://example.com/b", DEFAULT_USER_AGENT, |_| {
+            panic!("robots.txt fetched twice for the same host")
+        });
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_lru_cache_evicts_least_recently_used() {
+        let mut cache = LruCache::new(3);
+        for key in ["a", "b", "c"] {
+            cache.insert(key.to_string(), key.len());
+        }
+        // Touching "a" makes "b" the oldest
+        assert!(cache.get("a").is_some());
+        cache.insert("d".to_string(), 1);
+        cache.insert("e".to_string(), 1);
+
+        assert_eq!(cache.len(), 3);
+        assert!(!cache.contains_key("b"));
+        assert!(!cache.contains_key("c"));
+        assert!(cache.contains_key("a") && cache.contains_key("d") && cache.contains_key("e"));
+
+        cache.set_capacity(1);
+        assert_eq!(cache.len(), 1);
+        assert!(cache.contains_key("e"));
+    }
+
+    #[test]
+    fn test_robots_cache_is_bounded() {
+        let mut checker = RobotsChecker::with_capacity(2);
+        for host in ["a", "b", "c"] {
+            let url = format!("https://{}.example.com/page", host);
+            checker.check(&url, DEFAULT_USER_AGENT, |_| Ok(None)).unwrap();
+        }
+        assert_eq!(checker.cached_hosts(), 2);
+        assert!(!checker.is_cached("https://a.example.com"));
+        assert!(checker.is_cached("https://c.example.com"));
+    }
+
+    #[test]
+    fn test_rate_limit_same_host_waits() {
+        let mut limiter = HostRateLimiter::new();
+        let gap = Duration::from_millis(1000);
+
+        assert_eq!(limiter.reserve("https://example.com", Duration::ZERO, gap), Duration::ZERO);
+        assert_eq!(limiter.reserve("https://example.com", Duration::from_millis(200), gap), Duration::from_millis(800));
+        // Once the gap has passed no wait is needed
+        assert_eq!(limiter.reserve("https://example.com", Duration::from_millis(5000), gap), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_rate_limit_different_hosts_independent() {
+        let mut limiter = HostRateLimiter::new();
+        let gap = Duration::from_millis(1000);
+
+        assert_eq!(limiter.reserve("https://example.com", Duration::ZERO, gap), Duration::ZERO);
+        assert_eq!(limiter.reserve("https://example.org", Duration::from_millis(10), gap), Duration::ZERO);
+    }
+
+    fn response(status: u16) -> HttpResponse {
+        HttpResponse {
+            status,
+            headers: HashMap::new(),
+            body: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_retry_fails_twice_then_succeeds() {
+        let mut calls = 0;
+        let mut delays = Vec::new();
+
+        let (result, attempts) = fetch_with_retry(3, || {
+            calls += 1;
+            match calls {
+                1 => Err(Error::Io(std::io::Error::new(std::io::ErrorKind::TimedOut, "timed out"))),
+                2 => Ok(response(503)),
+                _ => Ok(response(200)),
+            }
+        }, |delay| delays.push(delay));
+
+        assert_eq!(result.unwrap().status, 200);
+        assert_eq!(attempts, 3);
+        assert_eq!(delays, vec![RETRY_BASE_DELAY, RETRY_BASE_DELAY * 2]);
+    }
+
+    #[test]
+    fn test_retry_skips_client_errors() {
+        let mut calls = 0;
+        let (result, attempts) = fetch_with_retry(3, || {
+            calls += 1;
+            Ok(response(404))
+        }, |_| panic!("4xx responses must not be retried"));
+
+        assert_eq!(result.unwrap().status, 404);
+        assert_eq!(attempts, 1);
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_retry_gives_up_after_limit() {
+        let (result, attempts) = fetch_with_retry(2, || Ok(response(500)), |_| {});
+
+        assert_eq!(result.unwrap().status, 500);
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn test_robots_agent_specific_group() {
+        let rules = RobotsRules::parse(
+            "User-agent: lunatic-distributed-scraper\nDisallow: /private\nAllow: /private/public\n\nUser-agent: *\nDisallow: /\n",
+        );
+
+        assert!(rules.is_allowed(DEFAULT_USER_AGENT, "/articles"));
+        assert!(!rules.is_allowed(DEFAULT_USER_AGENT, "/private/notes"));
+        assert!(rules.is_allowed(DEFAULT_USER_AGENT, "/private/public/page"));
+        assert!(!rules.is_allowed("OtherBot/2.0", "/articles"));
+    }
+}
+
+#[cfg(all(test, feature = "html"))]
+mod html_tests {
+    use super::*;
+
+    const FIXTURE: &str = r#"<!DOCTYPE html>
+<html>
+  <head>
+    <title> Example Page </title>
+    <meta name="description" content="A small test page">
+    <style>p { color: red; }</style>
+    <script>var hidden = "do not extract";</script>
+  </head>
+  <body>
+    <h1>Heading</h1>
+    <p>First paragraph with a <a href="/one">link</a>.</p>
+    <p>Second paragraph.<script>document.write("nope")</script></p>
+    <p>   </p>
+    <a href="/two">Another link</a>
+    <a name="anchor-without-href">Anchor</a>
+    <img src="a.png"><img src="b.png">
+  </body>
+</html>"#;
+
+    #[test]
+    fn test_parse_html_extracts_title_and_counts() {
+        let content = parse_html(FIXTURE);
+
+        assert_eq!(content.title.as_deref(), Some("Example Page"));
+        assert_eq!(content.description.as_deref(), Some("A small test page"));
+        assert_eq!(content.link_count, 2);
+        assert_eq!(content.image_count, 2);
+        assert_eq!(content.paragraph_count, 2);
+        assert_eq!(content.text, "First paragraph with a link.\n\nSecond paragraph.");
+    }
+
+    #[test]
+    fn test_parse_html_strips_script_and_style() {
+        let content = parse_html(FIXTURE);
+
+        assert!(!content.text.contains("do not extract"));
+        assert!(!content.text.contains("nope"));
+        assert!(!content.text.contains("color"));
+    }
+
+    #[test]
+    fn test_metadata_shape() {
+        let metadata = parse_html(FIXTURE).metadata();
+
+        assert_eq!(metadata["link_count"], 2);
+        assert_eq!(metadata["image_count"], 2);
+        assert_eq!(metadata["paragraph_count"], 2);
+        assert_eq!(metadata["description"], "A small test page");
+    }
+}
@@ -0,0 +1,97 @@
+//! Spans and structured events around agent operations.
+//!
+//! With the `tracing` feature these are `tracing` spans carrying `agent_id`, `message_id`
+//! and `operation_id` fields, so a collector can filter a distributed run by agent.
+//! Without it spans are no-ops and events fall back to `log` with `key=value` pairs.
+
+use std::future::Future;
+
+/// Span covering one agent operation: handling a message, an LLM task or a scraping fetch
+pub struct OperationSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+/// Keeps an [`OperationSpan`] entered until dropped
+pub struct SpanGuard<'a> {
+    #[cfg(feature = "tracing")]
+    _entered: tracing::span::Entered<'a>,
+    #[cfg(not(feature = "tracing"))]
+    _span: std::marker::PhantomData<&'a OperationSpan>,
+}
+
+impl OperationSpan {
+    pub fn new(operation: &'static str, agent_id: &str) -> Self {
+        #[cfg(feature = "tracing")]
+        {
+            Self {
+                span: tracing::info_span!(
+                    "agent_operation",
+                    operation,
+                    agent_id,
+                    message_id = tracing::field::Empty,
+                    operation_id = tracing::field::Empty,
+                ),
+            }
+        }
+
+        #[cfg(not(feature = "tracing"))]
+        {
+            let _ = (operation, agent_id);
+            Self {}
+        }
+    }
+
+    pub fn with_message_id(self, message_id: &str) -> Self {
+        #[cfg(feature = "tracing")]
+        self.span.record("message_id", message_id);
+        #[cfg(not(feature = "tracing"))]
+        let _ = message_id;
+        self
+    }
+
+    pub fn with_operation_id(self, operation_id: &str) -> Self {
+        #[cfg(feature = "tracing")]
+        self.span.record("operation_id", operation_id);
+        #[cfg(not(feature = "tracing"))]
+        let _ = operation_id;
+        self
+    }
+
+    /// Enter the span for the rest of a synchronous scope
+    pub fn enter(&self) -> SpanGuard<'_> {
+        SpanGuard {
+            #[cfg(feature = "tracing")]
+            _entered: self.span.enter(),
+            #[cfg(not(feature = "tracing"))]
+            _span: std::marker::PhantomData,
+        }
+    }
+
+    /// Run a future inside the span; unlike [`OperationSpan::enter`] this is safe across `.await`
+    pub async fn run<F: Future>(self, future: F) -> F::Output {
+        #[cfg(feature = "tracing")]
+        {
+            use tracing::Instrument;
+            future.instrument(self.span).await
+        }
+
+        #[cfg(not(feature = "tracing"))]
+        {
+            future.await
+        }
+    }
+}
+
+/// Emit a structured event: `agent_event!(info, "message", key = value, ...)`.
+/// Values only need `Display`.
+macro_rules! agent_event {
+    ($level:ident, $message:literal $(, $key:ident = $value:expr)* $(,)?) => {{
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($key = %$value,)* $message);
+        #[cfg(not(feature = "tracing"))]
+        log::$level!(concat!($message $(, " ", stringify!($key), "={}")*) $(, $value)*);
+    }};
+}
+
+pub(crate) use agent_event;
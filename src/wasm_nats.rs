@@ -16,15 +16,21 @@ use std::collections::HashMap;
 #[cfg(feature = "wasm-nats")]
 use std::sync::{Arc, Mutex};
 #[cfg(feature = "wasm-nats")]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "wasm-nats")]
 use futures::channel::mpsc;
 #[cfg(not(feature = "wasm-nats"))]
 use futures::channel::mpsc;
+#[cfg(feature = "wasm-nats")]
+use futures::StreamExt;
 
 use serde::{Deserialize, Serialize};
+use std::future::Future;
 use std::time::Duration;
 use crate::{Result, Error};
+use crate::nats_comm::{BackoffStrategy, BoundedReceiver};
 #[cfg(feature = "wasm-nats")]
-use base64::prelude::*;
+use crate::nats_comm::{bounded_channel, BoundedSender};
 
 /// Configuration for WebSocket NATS connection
 #[derive(Debug, Clone)]
@@ -32,7 +38,11 @@ pub struct WasmNatsConfig {
     pub websocket_url: String,
     pub timeout: Duration,
     pub max_reconnects: Option<usize>,
-    pub reconnect_delay: Duration,
+    // Shared with `NatsConfig`; applied by `WasmNatsConnection::reconnect`
+    pub reconnect_backoff: BackoffStrategy,
+    // How to read payloads that aren't agent messages when the frame has no
+    // `Content-Type` header; `None` treats anything that isn't JSON as binary
+    pub default_content_type: Option<String>,
 }
 
 impl Default for WasmNatsConfig {
@@ -42,7 +52,8 @@ impl Default for WasmNatsConfig {
             websocket_url: "ws://localhost:8080/nats".to_string(),
             timeout: Duration::from_secs(10),
             max_reconnects: Some(10),
-            reconnect_delay: Duration::from_secs(1),
+            reconnect_backoff: BackoffStrategy::default(),
+            default_content_type: None,
         }
     }
 }
@@ -54,8 +65,38 @@ pub struct WasmNatsConnection {
     websocket: WebSocket,
     config: WasmNatsConfig,
     message_sender: Arc<Mutex<Option<mpsc::UnboundedSender<Vec<u8>>>>>,
-    subscriptions: Arc<Mutex<HashMap<String, mpsc::UnboundedSender<crate::agent::Message>>>>,
+    subscriptions: Arc<Mutex<HashMap<String, SubscriptionSink>>>,
     is_connected: Arc<Mutex<bool>>,
+    // (connect count, unix seconds of the latest connect)
+    connect_history: Arc<Mutex<(u64, u64)>>,
+    // Messages dropped by bounded subscriptions whose buffer was full
+    dropped_messages: Arc<AtomicU64>,
+}
+
+// Where messages for one subscription go
+#[cfg(feature = "wasm-nats")]
+#[derive(Debug)]
+enum SubscriptionSink {
+    Unbounded(mpsc::UnboundedSender<crate::agent::Message>),
+    Bounded(BoundedSender<crate::agent::Message>),
+}
+
+#[cfg(feature = "wasm-nats")]
+impl SubscriptionSink {
+    fn deliver(&mut self, message: crate::agent::Message) {
+        match self {
+            SubscriptionSink::Unbounded(sender) => {
+                if let Err(e) = sender.unbounded_send(message) {
+                    log::warn!("Failed to send message to subscriber: {:?}", e);
+                }
+            }
+            SubscriptionSink::Bounded(sender) => {
+                if !sender.offer(message) && !sender.is_closed() {
+                    log::debug!("Bounded subscription is full, dropped a message");
+                }
+            }
+        }
+    }
 }
 
 #[cfg(not(feature = "wasm-nats"))]
@@ -84,6 +125,8 @@ impl WasmNatsConnection {
             message_sender: message_sender.clone(),
             subscriptions: subscriptions.clone(),
             is_connected: is_connected.clone(),
+            connect_history: Arc::new(Mutex::new((0, 0))),
+            dropped_messages: Arc::new(AtomicU64::new(0)),
         };
         
         // Set up WebSocket event handlers
@@ -96,13 +139,17 @@ impl WasmNatsConnection {
     async fn setup_event_handlers(&self) -> Result<()> {
         let is_connected = self.is_connected.clone();
         let subscriptions = self.subscriptions.clone();
+        let default_content_type = self.config.default_content_type.clone();
         
         // On open handler
         let onopen_callback = {
             let is_connected = is_connected.clone();
+            let connect_history = self.connect_history.clone();
             Closure::wrap(Box::new(move |_event: web_sys::Event| {
                 log::info!("WebSocket NATS connection opened");
                 *is_connected.lock().unwrap() = true;
+                let mut history = connect_history.lock().unwrap();
+                *history = (history.0 + 1, chrono::Utc::now().timestamp() as u64);
             }) as Box<dyn FnMut(web_sys::Event)>)
         };
         self.websocket.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
@@ -118,20 +165,11 @@ impl WasmNatsConnection {
                     
                     // Parse NATS protocol message
                     if let Ok(message) = Self::parse_nats_message(&data) {
-                        let subscriptions_guard = subscriptions.lock().unwrap();
-                        if let Some(sender) = subscriptions_guard.get(&message.subject) {
-                            let agent_message = crate::agent::Message {
-                                id: format!("nats_{}", chrono::Utc::now().timestamp_nanos_opt().unwrap_or(0)),
-                                from: crate::agent::AgentId("nats".to_string()),
-                                to: crate::agent::AgentId(message.subject.clone()),
-                                payload: serde_json::from_slice(&message.payload)
-                                    .unwrap_or_else(|_| serde_json::json!({"raw": base64::prelude::BASE64_STANDARD.encode(&message.payload)})),
-                                timestamp: chrono::Utc::now().timestamp() as u64,
-                            };
-                            
-                            if let Err(e) = sender.unbounded_send(agent_message) {
-                                log::warn!("Failed to send message to subscriber: {:?}", e);
-                            }
+                        // The server sends one MSG per matching subscription, each carrying
+                        // that subscription's sid, so deliver to that one only
+                        match subscriptions.lock().unwrap().get_mut(&message.sid) {
+                            Some(sender) => sender.deliver(inbound_message(&message, default_content_type.as_deref())),
+                            None => log::debug!("Dropping message for unknown subscription {}", message.sid),
                         }
                     }
                 }
@@ -163,30 +201,43 @@ impl WasmNatsConnection {
     
     /// Parse NATS protocol message from binary data
     fn parse_nats_message(data: &[u8]) -> Result<NatsMessage> {
-        let message_str = String::from_utf8_lossy(data);
-        let lines: Vec<&str> = message_str.lines().collect();
-        
-        if lines.is_empty() {
+        if data.is_empty() {
             return Err(Error::Custom("Empty NATS message".to_string()));
         }
+        let line_end = data.iter().position(|b| *b == b'\n').unwrap_or(data.len());
+        let first_line = String::from_utf8_lossy(&data[..line_end]);
         
-        // Parse MSG line: MSG <subject> <sid> [reply-to] <#bytes>
-        let first_line_parts: Vec<&str> = lines[0].split_whitespace().collect();
-        if first_line_parts.len() < 4 || first_line_parts[0] != "MSG" {
+        // MSG <subject> <sid> [reply-to] <#bytes>
+        // HMSG <subject> <sid> [reply-to] <#header bytes> <#total bytes>
+        let first_line_parts: Vec<&str> = first_line.split_whitespace().collect();
+        let has_headers = first_line_parts.first() == Some(&"HMSG");
+        let min_parts = if has_headers { 5 } else { 4 };
+        if first_line_parts.len() < min_parts || !(has_headers || first_line_parts[0] == "MSG") {
             return Err(Error::Custom("Invalid NATS message format".to_string()));
         }
         
         let subject = first_line_parts[1].to_string();
-        let payload_size: usize = first_line_parts.last()
-            .and_then(|s| s.parse().ok())
-            .ok_or_else(|| Error::Custom("Invalid payload size".to_string()))?;
+        let sid = first_line_parts[2].to_string();
+        let size = |index: usize| first_line_parts[index].parse::<usize>()
+            .map_err(|_| Error::Custom("Invalid payload size".to_string()));
+        let total_size = size(first_line_parts.len() - 1)?;
+        let header_size = if has_headers { size(first_line_parts.len() - 2)? } else { 0 };
+        if header_size > total_size {
+            return Err(Error::Custom("Invalid payload size".to_string()));
+        }
         
-        // Extract payload (should be on the next line(s))
-        let payload_start = message_str.find('\n').unwrap_or(0) + 1;
-        let payload_end = std::cmp::min(payload_start + payload_size, data.len());
-        let payload = data[payload_start..payload_end].to_vec();
+        // Headers, then the payload, follow the first line
+        let body_start = std::cmp::min(line_end + 1, data.len());
+        let header_end = std::cmp::min(body_start + header_size, data.len());
+        let payload_end = std::cmp::min(body_start + total_size, data.len());
+        let content_type = String::from_utf8_lossy(&data[body_start..header_end])
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-type"))
+            .map(|(_, value)| value.trim().to_string());
+        let payload = data[header_end..payload_end].to_vec();
         
-        Ok(NatsMessage { subject, payload })
+        Ok(NatsMessage { subject, sid, payload, content_type })
     }
     
     /// Publish a message to a NATS subject
@@ -195,32 +246,80 @@ impl WasmNatsConnection {
             return Err(Error::Custom("WebSocket NATS not connected".to_string()));
         }
         
-        // Format NATS PUB command: PUB <subject> <#bytes>\r\n<payload>\r\n
-        let pub_command = format!("PUB {} {}\r\n", subject, data.len());
-        let mut message = pub_command.into_bytes();
-        message.extend_from_slice(data);
-        message.extend_from_slice(b"\r\n");
-        
         // Send binary message through WebSocket
-        self.websocket.send_with_u8_array(&message)
+        self.websocket.send_with_u8_array(&pub_frame(subject, None, data))
             .map_err(|e| Error::Custom(format!("Failed to send WebSocket message: {:?}", e)))?;
         
         log::debug!("Published WebSocket NATS message to subject: {}", subject);
         Ok(())
     }
+
+    /// Wait until the browser has handed every queued frame to the network.
+    /// `publish` returns as soon as a frame is buffered, so call this before `close`.
+    pub async fn flush(&self) -> Result<()> {
+        let websocket = &self.websocket;
+        wait_until_drained(|| websocket.buffered_amount(), browser_sleep, self.config.timeout).await
+    }
+
+    /// Publish with a reply subject and wait for the subscriber's answer, confirming the
+    /// message was routed. Only works when a subscriber on `subject` replies.
+    pub async fn publish_confirmed(&self, subject: &str, data: &[u8]) -> Result<crate::agent::Message> {
+        let inbox = format!("_INBOX.{}", uuid::Uuid::new_v4().simple());
+        let mut replies = self.subscribe(&inbox).await?;
+
+        let result = match self.websocket.send_with_u8_array(&pub_frame(subject, Some(&inbox), data)) {
+            Ok(()) => {
+                let timeout = browser_sleep(self.config.timeout);
+                futures::pin_mut!(timeout);
+                match futures::future::select(replies.next(), timeout).await {
+                    futures::future::Either::Left((Some(reply), _)) => Ok(reply),
+                    futures::future::Either::Left((None, _)) => {
+                        Err(Error::Nats(format!("reply subscription for {} closed", subject)))
+                    }
+                    futures::future::Either::Right(_) => {
+                        Err(Error::Nats(format!("no confirmation for {} within {:?}", subject, self.config.timeout)))
+                    }
+                }
+            }
+            Err(e) => Err(Error::Custom(format!("Failed to send WebSocket message: {:?}", e))),
+        };
+
+        self.unsubscribe(&inbox);
+        result
+    }
+
+    // Drop a subscription; the subject doubles as the sid (see `subscribe`)
+    fn unsubscribe(&self, subject: &str) {
+        self.subscriptions.lock().unwrap().remove(subject);
+        if let Err(e) = self.websocket.send_with_u8_array(format!("UNSUB {}\r\n", subject).as_bytes()) {
+            log::warn!("Failed to unsubscribe from {}: {:?}", subject, e);
+        }
+    }
     
     /// Subscribe to a NATS subject
     pub async fn subscribe(&self, subject: &str) -> Result<mpsc::UnboundedReceiver<crate::agent::Message>> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.add_subscription(subject, SubscriptionSink::Unbounded(sender))?;
+        Ok(receiver)
+    }
+
+    /// Subscribe to a NATS subject, buffering at most `capacity` messages. While the buffer
+    /// is full, newer messages are dropped and counted in `get_stats().dropped_messages`.
+    pub async fn subscribe_bounded(&self, subject: &str, capacity: usize) -> Result<BoundedReceiver<crate::agent::Message>> {
+        let (sender, receiver) = bounded_channel(capacity, self.dropped_messages.clone());
+        self.add_subscription(subject, SubscriptionSink::Bounded(sender))?;
+        Ok(receiver)
+    }
+
+    fn add_subscription(&self, subject: &str, sink: SubscriptionSink) -> Result<()> {
         if !self.is_connected() {
             return Err(Error::Custom("WebSocket NATS not connected".to_string()));
         }
         
-        let (sender, receiver) = mpsc::unbounded();
-        
         // Store subscription
         {
             let mut subscriptions = self.subscriptions.lock().unwrap();
-            subscriptions.insert(subject.to_string(), sender);
+            subscriptions.insert(subject.to_string(), sink);
         }
         
         // Send SUB command: SUB <subject> <sid>\r\n
@@ -231,7 +330,7 @@ impl WasmNatsConnection {
             .map_err(|e| Error::Custom(format!("Failed to send subscribe command: {:?}", e)))?;
         
         log::debug!("Subscribed to WebSocket NATS subject: {}", subject);
-        Ok(receiver)
+        Ok(())
     }
     
     /// Check if WebSocket is connected
@@ -252,13 +351,65 @@ impl WasmNatsConnection {
         log::info!("Closed WebSocket NATS connection");
         Ok(())
     }
+
+    /// Re-open the WebSocket after it dropped, waiting `reconnect_backoff` before each
+    /// attempt and giving up after `max_reconnects`. Existing subscriptions are restored.
+    pub async fn reconnect(&mut self) -> Result<()> {
+        let max_attempts = self.config.max_reconnects.unwrap_or(usize::MAX);
+        for attempt in 1..=max_attempts {
+            browser_sleep(self.config.reconnect_backoff.delay(attempt)).await;
+
+            let websocket = match WebSocket::new(&self.config.websocket_url) {
+                Ok(websocket) => websocket,
+                Err(e) => {
+                    log::warn!("WebSocket NATS reconnect attempt {} failed: {:?}", attempt, e);
+                    continue;
+                }
+            };
+            websocket.set_binary_type(BinaryType::Arraybuffer);
+            self.websocket = websocket;
+            self.setup_event_handlers().await?;
+
+            if self.wait_open().await {
+                let subjects: Vec<String> = self.subscriptions.lock().unwrap().keys().cloned().collect();
+                for subject in subjects {
+                    self.websocket.send_with_u8_array(format!("SUB {} {}\r\n", subject, subject).as_bytes())
+                        .map_err(|e| Error::Custom(format!("Failed to send subscribe command: {:?}", e)))?;
+                }
+                log::info!("Reconnected WebSocket NATS after {} attempt(s)", attempt);
+                return Ok(());
+            }
+            log::warn!("WebSocket NATS reconnect attempt {} did not open within {:?}", attempt, self.config.timeout);
+            let _ = self.websocket.close();
+        }
+        Err(Error::Nats(format!("could not reconnect to {} after {} attempts", self.config.websocket_url, max_attempts)))
+    }
+
+    // Whether the socket opened within the configured timeout
+    async fn wait_open(&self) -> bool {
+        let mut waited = Duration::ZERO;
+        while !self.is_connected() {
+            if waited >= self.config.timeout {
+                return false;
+            }
+            browser_sleep(FLUSH_POLL_INTERVAL).await;
+            waited += FLUSH_POLL_INTERVAL;
+        }
+        true
+    }
     
     /// Get connection statistics (stub for WebSocket)
     pub fn get_stats(&self) -> WasmConnectionStats {
+        let (connects, last_connect_at) = *self.connect_history.lock().unwrap();
+        let reconnects = connects.saturating_sub(1);
         WasmConnectionStats {
             is_connected: self.is_connected(),
             ready_state: self.ready_state(),
             url: self.config.websocket_url.clone(),
+            connects,
+            reconnects,
+            last_reconnect_at: (reconnects > 0).then_some(last_connect_at),
+            dropped_messages: self.dropped_messages.load(Ordering::Relaxed),
         }
     }
 }
@@ -274,12 +425,26 @@ impl WasmNatsConnection {
         log::debug!("WASM NATS stub: would publish to subject: {}", subject);
         Ok(())
     }
+
+    pub async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    pub async fn publish_confirmed(&self, subject: &str, _data: &[u8]) -> Result<crate::agent::Message> {
+        Err(Error::Nats(format!("cannot confirm publish to {}: WASM NATS feature not enabled", subject)))
+    }
     
     pub async fn subscribe(&self, subject: &str) -> Result<mpsc::UnboundedReceiver<crate::agent::Message>> {
         log::debug!("WASM NATS stub: would subscribe to subject: {}", subject);
         let (_sender, receiver) = mpsc::unbounded();
         Ok(receiver)
     }
+
+    pub async fn subscribe_bounded(&self, subject: &str, capacity: usize) -> Result<BoundedReceiver<crate::agent::Message>> {
+        log::debug!("WASM NATS stub: would subscribe to subject: {}", subject);
+        let (_sender, receiver) = crate::nats_comm::bounded_channel(capacity, Default::default());
+        Ok(receiver)
+    }
     
     pub fn is_connected(&self) -> bool {
         false
@@ -293,21 +458,121 @@ impl WasmNatsConnection {
         log::debug!("WASM NATS stub: close called");
         Ok(())
     }
+
+    pub async fn reconnect(&mut self) -> Result<()> {
+        log::debug!("WASM NATS stub: would reconnect to {}", self.config.websocket_url);
+        Ok(())
+    }
     
     pub fn get_stats(&self) -> WasmConnectionStats {
         WasmConnectionStats {
             is_connected: false,
             ready_state: 3,
             url: self.config.websocket_url.clone(),
+            connects: 0,
+            reconnects: 0,
+            last_reconnect_at: None,
+            dropped_messages: 0,
         }
     }
 }
 
+/// Whether `subject` matches a subscription `pattern`: `*` matches exactly one token,
+/// a trailing `>` matches one or more remaining tokens, anything else must match literally
+pub fn subject_matches(pattern: &str, subject: &str) -> bool {
+    let mut subject_tokens = subject.split('.');
+    for token in pattern.split('.') {
+        match (token, subject_tokens.next()) {
+            (">", Some(_)) => return true,
+            ("*", Some(_)) => {}
+            (literal, Some(actual)) if literal == actual => {}
+            _ => return false,
+        }
+    }
+    subject_tokens.next().is_none()
+}
+
+/// Format a NATS PUB command: `PUB <subject> [reply-to] <#bytes>\r\n<payload>\r\n`
+#[cfg_attr(not(feature = "wasm-nats"), allow(dead_code))]
+fn pub_frame(subject: &str, reply_to: Option<&str>, data: &[u8]) -> Vec<u8> {
+    let command = match reply_to {
+        Some(reply_to) => format!("PUB {} {} {}\r\n", subject, reply_to, data.len()),
+        None => format!("PUB {} {}\r\n", subject, data.len()),
+    };
+    let mut frame = command.into_bytes();
+    frame.extend_from_slice(data);
+    frame.extend_from_slice(b"\r\n");
+    frame
+}
+
+/// Delay between `bufferedAmount` checks while flushing
+pub const FLUSH_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Poll `buffered_amount` until it reports zero, giving up after `timeout`.
+/// `sleep` is passed in so the loop can run without a browser timer.
+#[cfg_attr(not(feature = "wasm-nats"), allow(dead_code))]
+async fn wait_until_drained<B, S, F>(mut buffered_amount: B, mut sleep: S, timeout: Duration) -> Result<()>
+where
+    B: FnMut() -> u32,
+    S: FnMut(Duration) -> F,
+    F: Future<Output = ()>,
+{
+    let mut waited = Duration::ZERO;
+    loop {
+        let pending = buffered_amount();
+        if pending == 0 {
+            return Ok(());
+        }
+        if waited >= timeout {
+            return Err(Error::Nats(format!("{} bytes still buffered after {:?}", pending, timeout)));
+        }
+        sleep(FLUSH_POLL_INTERVAL).await;
+        waited += FLUSH_POLL_INTERVAL;
+    }
+}
+
+// setTimeout as a future; resolves immediately where there is no window (e.g. a worker)
+#[cfg(feature = "wasm-nats")]
+async fn browser_sleep(duration: Duration) {
+    let promise = js_sys::Promise::new(&mut |resolve, _reject| {
+        let scheduled = web_sys::window().map(|window| {
+            window.set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, duration.as_millis() as i32)
+        });
+        if !matches!(scheduled, Some(Ok(_))) {
+            let _ = resolve.call0(&JsValue::NULL);
+        }
+    });
+    let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+}
+
 /// Parsed NATS message structure
 #[derive(Debug, Clone)]
 struct NatsMessage {
     subject: String,
+    // Subscription the server matched; `subscribe` uses the subject pattern as sid
+    sid: String,
     payload: Vec<u8>,
+    // From the frame's `Content-Type` header, if it had one
+    content_type: Option<String>,
+}
+
+// Agent messages arrive encoded; anything else is wrapped with its payload typed by content type
+#[cfg(feature = "wasm-nats")]
+fn inbound_message(message: &NatsMessage, default_content_type: Option<&str>) -> crate::agent::Message {
+    crate::agent::decode_message(&message.payload).unwrap_or_else(|_| {
+        let content_type = message.content_type.as_deref().or(default_content_type);
+        crate::agent::Message {
+            id: format!("nats_{}", chrono::Utc::now().timestamp_nanos_opt().unwrap_or(0)),
+            from: crate::agent::AgentId("nats".to_string()),
+            to: crate::agent::AgentId(message.subject.clone()),
+            payload: crate::agent::MessagePayload::from_bytes(&message.payload, content_type).into_value(),
+            timestamp: chrono::Utc::now().timestamp() as u64,
+            expires_at: None,
+            correlation_id: None,
+            reply_to: None,
+            signature: None,
+        }
+    })
 }
 
 /// WebSocket NATS connection statistics
@@ -316,11 +581,19 @@ pub struct WasmConnectionStats {
     pub is_connected: bool,
     pub ready_state: u16,
     pub url: String,
+    // Same meaning as the fields on `ConnectionStats`
+    pub connects: u64,
+    pub reconnects: u64,
+    pub last_reconnect_at: Option<u64>,
+    #[serde(default)]
+    pub dropped_messages: u64,
 }
 
 /// Helper trait for JSON publishing over WebSocket NATS
 pub trait WasmNatsPublisher {
     fn publish_json<T: Serialize>(&self, subject: &str, data: &T) -> impl std::future::Future<Output = Result<()>>;
+    /// Publish an agent message in the standard wire encoding
+    fn publish_message(&self, subject: &str, message: &crate::agent::Message) -> impl std::future::Future<Output = Result<()>>;
 }
 
 impl WasmNatsPublisher for WasmNatsConnection {
@@ -328,6 +601,11 @@ impl WasmNatsPublisher for WasmNatsConnection {
         let json_data = serde_json::to_vec(data)?;
         self.publish(subject, &json_data).await
     }
+
+    async fn publish_message(&self, subject: &str, message: &crate::agent::Message) -> Result<()> {
+        let data = crate::agent::encode_message(message)?;
+        self.publish(subject, &data).await
+    }
 }
 
 #[cfg(test)]
@@ -340,7 +618,7 @@ mod tests {
         assert_eq!(config.websocket_url, "ws://localhost:8080/nats");
         assert_eq!(config.timeout, Duration::from_secs(10));
         assert_eq!(config.max_reconnects, Some(10));
-        assert_eq!(config.reconnect_delay, Duration::from_secs(1));
+        assert_eq!(config.reconnect_backoff, BackoffStrategy::default());
     }
 
     #[test]
@@ -349,21 +627,116 @@ mod tests {
             websocket_url: "wss://nats.example.com/ws".to_string(),
             timeout: Duration::from_secs(5),
             max_reconnects: Some(5),
-            reconnect_delay: Duration::from_secs(2),
+            reconnect_backoff: BackoffStrategy::Exponential { base: Duration::from_secs(1), max: Duration::from_secs(30), jitter: true },
+            default_content_type: Some("text/plain".to_string()),
         };
         assert_eq!(config.websocket_url, "wss://nats.example.com/ws");
         assert_eq!(config.timeout, Duration::from_secs(5));
         assert_eq!(config.max_reconnects, Some(5));
-        assert_eq!(config.reconnect_delay, Duration::from_secs(2));
+        assert!(matches!(config.reconnect_backoff, BackoffStrategy::Exponential { jitter: true, .. }));
+    }
+
+    #[test]
+    fn test_wait_until_drained_polls_buffered_amount() {
+        let buffered = std::cell::Cell::new(300u32);
+        let sleeps = std::cell::Cell::new(0);
+        let result = futures::executor::block_on(wait_until_drained(
+            || buffered.get(),
+            |_| {
+                sleeps.set(sleeps.get() + 1);
+                buffered.set(buffered.get().saturating_sub(100));
+                futures::future::ready(())
+            },
+            Duration::from_secs(1),
+        ));
+        assert!(result.is_ok());
+        assert_eq!(sleeps.get(), 3);
+
+        // A socket that never drains gives up after the timeout
+        let mut polls = 0;
+        let result = futures::executor::block_on(wait_until_drained(
+            || { polls += 1; 64 },
+            |_| futures::future::ready(()),
+            FLUSH_POLL_INTERVAL * 5,
+        ));
+        assert!(result.unwrap_err().to_string().contains("64 bytes still buffered"));
+        assert_eq!(polls, 6);
+    }
+
+    #[test]
+    fn test_subject_matches_wildcards() {
+        assert!(subject_matches("agent.worker_1", "agent.worker_1"));
+        assert!(!subject_matches("agent.worker_1", "agent.worker_2"));
+        assert!(!subject_matches("agent.worker_1", "agent.worker_1.inbox"));
+
+        assert!(subject_matches("agent.*", "agent.worker_1"));
+        assert!(subject_matches("*.worker_1", "agent.worker_1"));
+        assert!(!subject_matches("agent.*", "agent"));
+        assert!(!subject_matches("agent.*", "agent.worker_1.inbox"));
+
+        assert!(subject_matches("agent.>", "agent.worker_1"));
+        assert!(subject_matches("agent.>", "agent.worker_1.inbox"));
+        assert!(!subject_matches("agent.>", "agent"));
+        assert!(subject_matches(">", "heartbeat.worker_1"));
+    }
+
+    #[test]
+    fn test_pub_frame_with_reply_subject() {
+        assert_eq!(pub_frame("scrape.results", None, b"ok"), b"PUB scrape.results 2\r\nok\r\n");
+        assert_eq!(pub_frame("scrape.results", Some("_INBOX.1"), b"ok"), b"PUB scrape.results _INBOX.1 2\r\nok\r\n");
     }
 
     #[cfg(feature = "wasm-nats")]
     #[test]
     fn test_nats_message_parsing() {
-        let test_message = b"MSG test.subject 1 5\r\nhello\r\n";
+        let test_message = b"MSG test.subject test.* 5\r\nhello\r\n";
         let parsed = WasmNatsConnection::parse_nats_message(test_message).unwrap();
         
         assert_eq!(parsed.subject, "test.subject");
+        assert_eq!(parsed.sid, "test.*");
         assert_eq!(parsed.payload, b"hello");
+        assert_eq!(parsed.content_type, None);
+    }
+
+    #[cfg(feature = "wasm-nats")]
+    #[test]
+    fn test_non_json_payload_arrives_as_binary() {
+        let frame = b"MSG sensor.raw 1 4\r\n\xde\xad\xbe\xef\r\n";
+        let parsed = WasmNatsConnection::parse_nats_message(frame).unwrap();
+        let message = inbound_message(&parsed, None);
+        assert_eq!(message.to.0, "sensor.raw");
+        assert_eq!(message.typed_payload(), crate::agent::MessagePayload::Binary(vec![0xde, 0xad, 0xbe, 0xef]));
+
+        // A Content-Type header overrides the configured default
+        let frame = b"HMSG logs.app 1 38 43\r\nNATS/1.0\r\nContent-Type: text/plain\r\n\r\nhello\r\n";
+        let parsed = WasmNatsConnection::parse_nats_message(frame).unwrap();
+        assert_eq!(parsed.content_type.as_deref(), Some("text/plain"));
+        assert_eq!(parsed.payload, b"hello");
+        let message = inbound_message(&parsed, Some("application/octet-stream"));
+        assert_eq!(message.typed_payload(), crate::agent::MessagePayload::Text("hello".to_string()));
+    }
+
+    #[cfg(feature = "wasm-nats")]
+    #[test]
+    fn test_bounded_sink_counts_drops() {
+        let dropped = Arc::new(AtomicU64::new(0));
+        let (sender, mut receiver) = bounded_channel(1, dropped.clone());
+        let mut sink = SubscriptionSink::Bounded(sender);
+        for i in 0..5 {
+            sink.deliver(crate::agent::Message {
+                id: format!("burst_{}", i),
+                from: crate::agent::AgentId("nats".to_string()),
+                to: crate::agent::AgentId("monitor".to_string()),
+                payload: serde_json::json!({}),
+                timestamp: 0,
+                expires_at: None,
+                correlation_id: None,
+                reply_to: None,
+                signature: None,
+            });
+        }
+        assert_eq!(receiver.try_next().map(|message| message.id), Some("burst_0".to_string()));
+        assert_eq!(receiver.dropped(), 4);
+        assert_eq!(dropped.load(Ordering::Relaxed), 4);
     }
 }
\ No newline at end of file
@@ -0,0 +1,365 @@
+//! Executes planned workflows by dispatching each step to an agent of the matching type
+
+use lunatic::ap::ProcessRef;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use crate::agent::{AgentId, Message as AgentMessage};
+use crate::llm_client::{validate_workflow, WorkflowStep};
+use crate::supervisor::{get_agent_state, request_from_agent, wait_for_operation_every, AgentProcess};
+use crate::{Error, Result};
+
+pub const DEFAULT_STEP_TIMEOUT: Duration = Duration::from_secs(120);
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", content = "reason", rename_all = "snake_case")]
+pub enum StepStatus {
+    Completed,
+    Failed(String),
+    // Not run because a dependency did not complete
+    Skipped(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepResult {
+    pub step_id: String,
+    pub agent_type: String,
+    pub status: StepStatus,
+    pub outputs: HashMap<String, serde_json::Value>,
+}
+
+/// Outcome of a workflow run, with steps listed in execution order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowResult {
+    pub steps: Vec<StepResult>,
+    // Every output produced so far, keyed by output name
+    pub outputs: HashMap<String, serde_json::Value>,
+}
+
+impl WorkflowResult {
+    pub fn is_success(&self) -> bool {
+        self.steps.iter().all(|step| step.status == StepStatus::Completed)
+    }
+
+    pub fn step(&self, step_id: &str) -> Option<&StepResult> {
+        self.steps.iter().find(|step| step.step_id == step_id)
+    }
+}
+
+/// Runs `WorkflowStep`s in dependency order, feeding each step's outputs to later steps' inputs
+pub struct WorkflowExecutor {
+    agents: HashMap<String, ProcessRef<AgentProcess>>,
+    initial_inputs: HashMap<String, serde_json::Value>,
+    step_timeout: Duration,
+    poll_interval: Duration,
+}
+
+impl WorkflowExecutor {
+    /// `agents` maps a step's `agent_type` to the agent that runs it
+    pub fn new(agents: HashMap<String, ProcessRef<AgentProcess>>) -> Self {
+        Self {
+            agents,
+            initial_inputs: HashMap::new(),
+            step_timeout: DEFAULT_STEP_TIMEOUT,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+        }
+    }
+
+    /// Provide a value for an input no step produces
+    pub fn with_input(mut self, name: &str, value: serde_json::Value) -> Self {
+        self.initial_inputs.insert(name.to_string(), value);
+        self
+    }
+
+    /// How long to wait for an LLM-backed step before marking it failed
+    pub fn with_step_timeout(mut self, timeout: Duration) -> Self {
+        self.step_timeout = timeout;
+        self
+    }
+
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Execute the plan. Fails up front if the plan itself is invalid; step failures are
+    /// reported per step and cause dependent steps to be skipped.
+    pub fn execute(&self, steps: &[WorkflowStep]) -> Result<WorkflowResult> {
+        let order = validate_workflow(steps)?;
+        let by_id: HashMap<&str, &WorkflowStep> = steps.iter().map(|s| (s.step_id.as_str(), s)).collect();
+
+        let mut outputs = self.initial_inputs.clone();
+        let mut completed: HashSet<String> = HashSet::new();
+        let mut results = Vec::with_capacity(order.len());
+
+        for step_id in order {
+            let step = by_id[step_id.as_str()];
+
+            if let Some(blocked_on) = step.depends_on.iter().find(|dep| !completed.contains(*dep)) {
+                results.push(StepResult {
+                    step_id: step.step_id.clone(),
+                    agent_type: step.agent_type.clone(),
+                    status: StepStatus::Skipped(format!("dependency '{}' did not complete", blocked_on)),
+                    outputs: HashMap::new(),
+                });
+                continue;
+            }
+
+            let result = match self.run_step(step, &outputs) {
+                Ok(step_outputs) => {
+                    completed.insert(step.step_id.clone());
+                    outputs.extend(step_outputs.clone());
+                    StepResult {
+                        step_id: step.step_id.clone(),
+                        agent_type: step.agent_type.clone(),
+                        status: StepStatus::Completed,
+                        outputs: step_outputs,
+                    }
+                }
+                Err(e) => {
+                    log::warn!("Workflow step {} failed: {}", step.step_id, e);
+                    StepResult {
+                        step_id: step.step_id.clone(),
+                        agent_type: step.agent_type.clone(),
+                        status: StepStatus::Failed(e.to_string()),
+                        outputs: HashMap::new(),
+                    }
+                }
+            };
+            results.push(result);
+        }
+
+        Ok(WorkflowResult { steps: results, outputs })
+    }
+
+    fn run_step(&self, step: &WorkflowStep, available: &HashMap<String, serde_json::Value>) -> Result<HashMap<String, serde_json::Value>> {
+        let agent = self.agents.get(&step.agent_type).ok_or_else(|| {
+            Error::WorkflowValidation(format!("no agent registered for type '{}'", step.agent_type))
+        })?;
+
+        let inputs: serde_json::Map<String, serde_json::Value> = step.inputs.iter()
+            .filter_map(|name| available.get(name).map(|value| (name.clone(), value.clone())))
+            .collect();
+
+        log::info!("Workflow dispatching step {} ({}) to {}", step.step_id, step.action, step.agent_type);
+
+        match llm_task_for_action(&step.action) {
+            Some(llm_task) => self.run_llm_step(agent, step, llm_task, inputs),
+            None => self.run_message_step(agent, step, inputs),
+        }
+    }
+
+    // Non-LLM steps complete synchronously; the agent's acknowledgement means it has run the step
+    fn run_message_step(
+        &self,
+        agent: &ProcessRef<AgentProcess>,
+        step: &WorkflowStep,
+        inputs: serde_json::Map<String, serde_json::Value>,
+    ) -> Result<HashMap<String, serde_json::Value>> {
+        let ack = request_from_agent(agent, step_message(step, serde_json::json!({
+            "message_type": "workflow_step",
+            "step_id": step.step_id,
+            "action": step.action,
+            "inputs": inputs,
+            "outputs": step.outputs,
+        })))?;
+        if ack["accepted"] != true {
+            return Err(Error::Custom(format!("agent rejected step {}", step.step_id)));
+        }
+
+        let state = get_agent_state(agent);
+        step.outputs.iter()
+            .map(|name| {
+                state.get(name)
+                    .map(|value| (name.clone(), value.clone()))
+                    .ok_or_else(|| Error::Custom(format!("step {} did not produce output '{}'", step.step_id, name)))
+            })
+            .collect()
+    }
+
+    // LLM steps run in a worker process; the step picks the operation id so it can wait on it
+    fn run_llm_step(
+        &self,
+        agent: &ProcessRef<AgentProcess>,
+        step: &WorkflowStep,
+        llm_task: &str,
+        inputs: serde_json::Map<String, serde_json::Value>,
+    ) -> Result<HashMap<String, serde_json::Value>> {
+        let operation_id = format!("workflow_{}_{}", step.step_id, uuid::Uuid::new_v4());
+
+        let input_values: Vec<serde_json::Value> = inputs.values().cloned().collect();
+        let ack = request_from_agent(agent, step_message(step, serde_json::json!({
+            "llm_task": llm_task,
+            "operation_id": operation_id,
+            "data": input_values,
+            "prompt": format!("{} using {}", step.action, serde_json::Value::Object(inputs.clone())),
+            "context": inputs,
+            "task_description": step.action,
+        })))?;
+        if ack["accepted"] != true {
+            return Err(Error::Custom(format!("agent {} did not start an LLM task for step {}", step.agent_type, step.step_id)));
+        }
+
+        let status = wait_for_operation_every(agent, &operation_id, self.step_timeout, self.poll_interval)
+            .map_err(|_| Error::Custom(format!("step {} timed out after {:?}", step.step_id, self.step_timeout)))?;
+
+        if !status.is_success() {
+            return Err(Error::Custom(format!("LLM task for step {} finished with status {:?}", step.step_id, status)));
+        }
+
+        let result_key = llm_result_key(llm_task);
+        let value = get_agent_state(agent).remove(result_key).ok_or_else(|| {
+            Error::Custom(format!("step {} completed without a {} entry", step.step_id, result_key))
+        })?;
+        Ok(step.outputs.iter().map(|name| (name.clone(), value.clone())).collect())
+    }
+}
+
+fn step_message(step: &WorkflowStep, payload: serde_json::Value) -> AgentMessage {
+    AgentMessage {
+        id: format!("workflow_{}_{}", step.step_id, uuid::Uuid::new_v4()),
+        from: AgentId("workflow_executor".to_string()),
+        to: AgentId(step.agent_type.clone()),
+        payload,
+        timestamp: chrono::Utc::now().timestamp() as u64,
+        expires_at: None,
+        correlation_id: None,
+        reply_to: None,
+        signature: None,
+    }
+}
+
+// Step actions that map onto the agent's built-in LLM tasks
+fn llm_task_for_action(action: &str) -> Option<&'static str> {
+    match action {
+        "summarize" | "summarization" | "generate_summary" => Some("summarize"),
+        "plan_workflow" | "workflow_planning" => Some("plan_workflow"),
+        "reason" | "reasoning" => Some("reason"),
+        _ => None,
+    }
+}
+
+// State key each LLM task writes its result to
+fn llm_result_key(llm_task: &str) -> &'static str {
+    match llm_task {
+        "summarize" => "last_summary",
+        "plan_workflow" => "workflow_plan",
+        _ => "last_reasoning",
+    }
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod tests {
+    use super::*;
+    use crate::llm_client::LLMProviderSpec;
+    use crate::supervisor::{send_message_to_agent, spawn_single_agent, AgentConfig, AgentType, MemoryBackendType};
+
+    fn agent(id: &str, agent_type: AgentType, llm_enabled: bool) -> ProcessRef<AgentProcess> {
+        spawn_single_agent(AgentConfig {
+            id: AgentId(id.to_string()),
+            memory_backend_type: MemoryBackendType::InMemory,
+            nats_enabled: false,
+            llm_enabled,
+            agent_type,
+            ..Default::default()
+        }).unwrap()
+    }
+
+    fn step(id: &str, agent_type: &str, action: &str, inputs: &[&str], outputs: &[&str], depends_on: &[&str]) -> WorkflowStep {
+        let strings = |items: &[&str]| items.iter().map(|s| s.to_string()).collect();
+        WorkflowStep {
+            step_id: id.to_string(),
+            agent_type: agent_type.to_string(),
+            action: action.to_string(),
+            inputs: strings(inputs),
+            outputs: strings(outputs),
+            depends_on: strings(depends_on),
+        }
+    }
+
+    #[test]
+    fn test_collect_then_summarize() {
+        let collector = agent("workflow_collector", AgentType::DataCollector, false);
+        let summarizer = agent("workflow_summarizer", AgentType::Summarizer, true);
+
+        // The collector already holds scraped content from an earlier run
+        send_message_to_agent(&collector, AgentMessage {
+            id: "seed".to_string(),
+            from: AgentId("test".to_string()),
+            to: AgentId("workflow_collector".to_string()),
+            payload: serde_json::json!({
+                "message_type": "state_update",
+                "updates": {"scraped_data": {"title": "Rust Blog", "content": "Rust 1.80 released"}}
+            }),
+            timestamp: 0,
+            expires_at: None,
+            correlation_id: None,
+            reply_to: None,
+            signature: None,
+        });
+
+        let plan = vec![
+            step("summarize", "summarizer", "summarize", &["scraped_data"], &["summary"], &["collect"]),
+            step("collect", "collector", "collect_data", &["urls"], &["scraped_data"], &[]),
+        ];
+        let executor = WorkflowExecutor::new(HashMap::from([
+            ("collector".to_string(), collector),
+            ("summarizer".to_string(), summarizer),
+        ]))
+        .with_input("urls", serde_json::json!(["https://blog.rust-lang.org"]))
+        .with_step_timeout(Duration::from_secs(30));
+
+        let result = executor.execute(&plan).unwrap();
+        assert!(result.is_success(), "{:?}", result.steps);
+        assert_eq!(result.steps[0].step_id, "collect");
+        assert_eq!(result.steps[0].outputs["scraped_data"]["title"], "Rust Blog");
+        assert!(result.outputs["summary"].as_str().is_some_and(|s| !s.is_empty()));
+    }
+
+    #[test]
+    fn test_llm_step_waits_while_queued() {
+        let summarizer = spawn_single_agent(AgentConfig {
+            id: AgentId("queued_summarizer".to_string()),
+            memory_backend_type: MemoryBackendType::InMemory,
+            nats_enabled: false,
+            llm_enabled: true,
+            agent_type: AgentType::Summarizer,
+            max_concurrent_llm_ops: 1,
+            llm_provider: Some(LLMProviderSpec::Mock { latency_ms: Some(500) }),
+            ..Default::default()
+        }).unwrap();
+
+        // Hold the only LLM slot so the step's task starts out "queued"
+        send_message_to_agent(&summarizer, AgentMessage {
+            id: "busy".to_string(),
+            from: AgentId("test".to_string()),
+            to: AgentId("queued_summarizer".to_string()),
+            payload: serde_json::json!({"llm_task": "summarize", "data": ["earlier"]}),
+            timestamp: 0,
+            expires_at: None,
+            correlation_id: None,
+            reply_to: None,
+            signature: None,
+        });
+
+        let plan = vec![step("summarize", "summarizer", "summarize", &["scraped_data"], &["summary"], &[])];
+        let result = WorkflowExecutor::new(HashMap::from([("summarizer".to_string(), summarizer)]))
+            .with_input("scraped_data", serde_json::json!({"title": "Rust Blog"}))
+            .with_step_timeout(Duration::from_secs(30))
+            .execute(&plan)
+            .unwrap();
+        assert!(result.is_success(), "{:?}", result.steps);
+    }
+
+    #[test]
+    fn test_missing_agent_skips_dependents() {
+        let plan = vec![
+            step("collect", "collector", "collect_data", &[], &["scraped_data"], &[]),
+            step("summarize", "summarizer", "summarize", &["scraped_data"], &["summary"], &["collect"]),
+        ];
+        let result = WorkflowExecutor::new(HashMap::new()).execute(&plan).unwrap();
+        assert!(matches!(result.steps[0].status, StepStatus::Failed(_)));
+        assert!(matches!(result.steps[1].status, StepStatus::Skipped(_)));
+    }
+}
@@ -59,6 +59,7 @@ fn test_llm_enabled_agent_creation() {
         nats_enabled: false,
         llm_enabled: true,
         agent_type: AgentType::Summarizer,
+        ..Default::default()
     };
 
     // Test that agent can be spawned with LLM configuration
@@ -78,6 +79,10 @@ fn test_llm_enabled_agent_creation() {
             "data": test_data
         }),
         timestamp: chrono::Utc::now().timestamp() as u64,
+        expires_at: None,
+        correlation_id: None,
+        reply_to: None,
+        signature: None,
     };
 
     send_message_to_agent(&agent, llm_message);
@@ -92,6 +97,10 @@ fn test_llm_enabled_agent_creation() {
         to: AgentId("test_llm_agent".to_string()),
         payload: json!({"type": "ping"}),
         timestamp: chrono::Utc::now().timestamp() as u64,
+        expires_at: None,
+        correlation_id: None,
+        reply_to: None,
+        signature: None,
     };
 
     send_message_to_agent(&agent, ping_message);
@@ -120,6 +129,7 @@ fn test_different_agent_types() {
             nats_enabled: false,
             llm_enabled: matches!(agent_type, AgentType::Summarizer | AgentType::WorkflowCoordinator),
             agent_type: agent_type.clone(),
+            ..Default::default()
         };
 
         let agent = spawn_single_agent(config).unwrap();
@@ -131,6 +141,10 @@ fn test_different_agent_types() {
             to: AgentId(format!("test_{:?}_agent", agent_type)),
             payload: json!({"type": "test", "agent_type": format!("{:?}", agent_type)}),
             timestamp: chrono::Utc::now().timestamp() as u64,
+            expires_at: None,
+            correlation_id: None,
+            reply_to: None,
+            signature: None,
         };
         
         send_message_to_agent(&agent, test_message);
@@ -153,6 +167,7 @@ fn test_llm_task_message_processing() {
         nats_enabled: false,
         llm_enabled: true,
         agent_type: AgentType::Generic,
+        ..Default::default()
     };
 
     let agent = spawn_single_agent(config).unwrap();
@@ -182,6 +197,10 @@ fn test_llm_task_message_processing() {
             to: AgentId("llm_test_agent".to_string()),
             payload,
             timestamp: chrono::Utc::now().timestamp() as u64,
+            expires_at: None,
+            correlation_id: None,
+            reply_to: None,
+            signature: None,
         };
 
         send_message_to_agent(&agent, message);
@@ -197,6 +216,10 @@ fn test_llm_task_message_processing() {
         to: AgentId("llm_test_agent".to_string()),
         payload: json!({"type": "final_ping"}),
         timestamp: chrono::Utc::now().timestamp() as u64,
+        expires_at: None,
+        correlation_id: None,
+        reply_to: None,
+        signature: None,
     };
 
     send_message_to_agent(&agent, final_ping);
@@ -215,6 +238,7 @@ fn test_llm_fault_tolerance() {
         nats_enabled: false,
         llm_enabled: true,
         agent_type: AgentType::Generic,
+        ..Default::default()
     };
 
     let agent = spawn_single_agent(config).unwrap();
@@ -234,6 +258,10 @@ fn test_llm_fault_tolerance() {
             to: AgentId("fault_test_agent".to_string()),
             payload,
             timestamp: chrono::Utc::now().timestamp() as u64,
+            expires_at: None,
+            correlation_id: None,
+            reply_to: None,
+            signature: None,
         };
 
         send_message_to_agent(&agent, message);
@@ -247,6 +275,10 @@ fn test_llm_fault_tolerance() {
         to: AgentId("fault_test_agent".to_string()),
         payload: json!({"type": "recovery_ping"}),
         timestamp: chrono::Utc::now().timestamp() as u64,
+        expires_at: None,
+        correlation_id: None,
+        reply_to: None,
+        signature: None,
     };
 
     send_message_to_agent(&agent, recovery_message);
@@ -266,6 +298,7 @@ fn test_workflow_step_serialization() {
             action: "collect_data".to_string(),
             inputs: vec!["urls".to_string()],
             outputs: vec!["scraped_data".to_string()],
+            depends_on: Vec::new(),
         },
         WorkflowStep {
             step_id: "step2".to_string(),
@@ -273,6 +306,7 @@ fn test_workflow_step_serialization() {
             action: "summarize".to_string(),
             inputs: vec!["scraped_data".to_string()],
             outputs: vec!["summary".to_string()],
+            depends_on: vec!["step1".to_string()],
         },
     ];
 
@@ -300,6 +334,7 @@ fn test_agent_performance() {
         nats_enabled: false,
         llm_enabled: i % 2 == 0, // Half with LLM
         agent_type: AgentType::Generic,
+        ..Default::default()
     }).collect();
     
     let agents: Vec<_> = configs.into_iter()
@@ -320,6 +355,10 @@ fn test_agent_performance() {
             to: AgentId(format!("perf_agent_{}", i)),
             payload: json!({"type": "performance_test", "data": "test"}),
             timestamp: chrono::Utc::now().timestamp() as u64,
+            expires_at: None,
+            correlation_id: None,
+            reply_to: None,
+            signature: None,
         };
         
         send_message_to_agent(agent, message);
@@ -353,6 +392,7 @@ fn test_agent_memory_backends() {
         nats_enabled: false,
         llm_enabled: false,
         agent_type: AgentType::Generic,
+        ..Default::default()
     };
     
     let agent1 = spawn_single_agent(in_memory_config).unwrap();
@@ -369,6 +409,7 @@ fn test_agent_memory_backends() {
         nats_enabled: false,
         llm_enabled: false,
         agent_type: AgentType::Generic,
+        ..Default::default()
     };
     
     let agent2 = spawn_single_agent(file_config).unwrap();
@@ -447,6 +488,7 @@ fn test_concurrent_agent_operations() {
             nats_enabled: false,
             llm_enabled: i % 2 == 0,
             agent_type: AgentType::Generic,
+            ..Default::default()
         };
         spawn_single_agent(config).unwrap()
     }).collect();
@@ -466,6 +508,10 @@ fn test_concurrent_agent_operations() {
                         "message_num": j
                     }),
                     timestamp: chrono::Utc::now().timestamp() as u64,
+                    expires_at: None,
+                    correlation_id: None,
+                    reply_to: None,
+                    signature: None,
                 };
                 
                 send_message_to_agent(&agent, message);
@@ -492,4 +538,27 @@ fn test_concurrent_agent_operations() {
         let state = get_agent_state(&agent);
         assert!(state.contains_key("last_message_from_concurrent_test"));
     }
-}
\ No newline at end of file
+}
+/// Two contenders for the same distributed lock: exactly one wins until it releases
+#[cfg(feature = "jetstream")]
+#[tokio::test]
+#[ignore = "requires a running NATS server with JetStream"]
+async fn test_distributed_lock_contention() {
+    let first = NatsConnection::new(NatsConfig::from_env().unwrap()).await.unwrap();
+    let second = NatsConnection::new(NatsConfig::from_env().unwrap()).await.unwrap();
+    let name = format!("test-lock-{}", std::process::id());
+    let ttl = Duration::from_secs(5);
+
+    let (a, b) = tokio::join!(first.try_acquire_lock(&name, ttl), second.try_acquire_lock(&name, ttl));
+    let (a, b) = (a.unwrap(), b.unwrap());
+    assert!(a.is_some() ^ b.is_some(), "exactly one contender should hold the lock");
+
+    let winner = a.or(b).unwrap();
+    assert!(winner.is_held());
+    winner.release().await.unwrap();
+
+    // Released, so the next attempt succeeds
+    let again = second.try_acquire_lock(&name, ttl).await.unwrap();
+    assert!(again.is_some());
+    again.unwrap().release().await.unwrap();
+}